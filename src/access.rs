@@ -0,0 +1,135 @@
+//! Which clients can make requests, by their address.
+//!
+//! There are separate policies for the content and for the admin endpoints
+//! (`/.control/`, `/.reload` and `/.stats`), each with `allow` and `deny`
+//! lists of address ranges. Addresses in `deny` are turned away, and when
+//! `allow` isn't empty so is everyone not in it. The lists can be given on
+//! the command line, for every site, and in the `[access]` table of a site's
+//! `site.toml`, and a request has to be let through by both.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A range of addresses, e.g. `192.168.1.0/24`, `fd00::/8` or a single
+/// address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = addr
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|_| format!("invalid address range \"{s}\""))?
+            .to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(x) => {
+                x.trim().parse().ok().filter(|x| *x <= max).ok_or_else(
+                    || format!("invalid prefix length in \"{s}\""),
+                )?
+            }
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// The addresses that can and can't make some requests.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl Policy {
+    /// Whether `client` is let through. A client whose address isn't known,
+    /// like one on a Unix socket without a trusted proxy, only is if there's
+    /// no `allow` list.
+    pub fn permits(&self, client: Option<IpAddr>) -> bool {
+        let Some(client) = client else {
+            return self.allow.is_empty();
+        };
+        !self.deny.iter().any(|x| x.contains(client))
+            && (self.allow.is_empty()
+                || self.allow.iter().any(|x| x.contains(client)))
+    }
+}
+
+/// The `[access]` table of `site.toml`, or the `--allow`/`--deny` options.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Access {
+    /// For everything but the admin endpoints.
+    pub content: Policy,
+    pub admin: Policy,
+}
+
+impl Access {
+    /// Whether `client` can make a request for `path`.
+    pub fn permits(&self, client: Option<IpAddr>, path: &str) -> bool {
+        if is_admin(path) {
+            self.admin.permits(client)
+        } else {
+            self.content.permits(client)
+        }
+    }
+}
+
+/// Whether `path` is one of the admin endpoints.
+pub fn is_admin(path: &str) -> bool {
+    path.starts_with("/.control/") || path == "/.reload" || path == "/.stats"
+}
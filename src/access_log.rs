@@ -0,0 +1,113 @@
+//! The access log, kept apart from the application log so that standard tools
+//! can parse it. Lines are in the Combined Log Format with the response time,
+//! in microseconds, appended.
+
+use std::cell::Cell;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tiny_http::Request;
+
+static LOG: OnceLock<AccessLog> = OnceLock::new();
+
+thread_local! {
+    /// When the request being handled by this thread arrived and who it's
+    /// from. Serve threads handle one request at a time, so this is set when
+    /// a request is received and taken when it's responded to.
+    static CURRENT: Cell<Option<(Instant, Option<IpAddr>)>> =
+        const { Cell::new(None) };
+}
+
+struct AccessLog {
+    /// The file being logged to, standard output if `None`.
+    path: Option<PathBuf>,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+fn open(path: Option<&Path>) -> std::io::Result<Box<dyn Write + Send>> {
+    Ok(match path {
+        Some(path) => Box::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?,
+        ),
+        None => Box::new(std::io::stdout()),
+    })
+}
+
+/// Start logging requests to the file at `path`, or standard output if
+/// `None`.
+pub fn init(path: Option<&Path>) -> std::io::Result<()> {
+    let log = AccessLog {
+        path: path.map(Path::to_path_buf),
+        out: Mutex::new(open(path)?),
+    };
+    let _ = LOG.set(log);
+    Ok(())
+}
+
+/// Reopen the log file, e.g. after it was rotated.
+pub fn reopen() {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let Some(path) = &log.path else {
+        return;
+    };
+    match open(Some(path)) {
+        Ok(out) => {
+            *log.out.lock().unwrap() = out;
+            log::info!("Reopened the access log \"{}\"", path.display());
+        }
+        Err(e) => log::error!(
+            "Failed to reopen the access log \"{}\": {e}",
+            path.display()
+        ),
+    }
+}
+
+/// Note that this thread started handling a request from `client` at
+/// `started`.
+pub fn begin(started: Instant, client: Option<IpAddr>) {
+    CURRENT.set(Some((started, client)));
+}
+
+/// Log the response to `rq`.
+pub fn record(rq: &Request, status: u16, bytes: Option<usize>) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let current = CURRENT.take();
+    let client = current
+        .and_then(|(_, client)| client)
+        .or_else(|| rq.remote_addr().map(|x| x.ip()))
+        .map_or_else(|| "-".to_string(), |x| x.to_string());
+    let latency = current.map_or_else(
+        || "-".to_string(),
+        |(started, _)| started.elapsed().as_micros().to_string(),
+    );
+    let header = |name| {
+        crate::http::header(rq, name).map_or_else(
+            || "-".to_string(),
+            |x| x.replace('\\', "\\\\").replace('"', "\\\""),
+        )
+    };
+    let line = format!(
+        "{client} - - [{}] \"{} {} HTTP/{}\" {status} {} \"{}\" \"{}\" {latency}\n",
+        chrono::Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+        rq.method(),
+        rq.url().replace('"', "%22"),
+        rq.http_version(),
+        bytes.map_or_else(|| "-".to_string(), |x| x.to_string()),
+        header("Referer"),
+        header("User-Agent"),
+    );
+
+    let mut out = log.out.lock().unwrap();
+    if let Err(e) = out.write_all(line.as_bytes()).and_then(|()| out.flush()) {
+        log::error!("Failed to write to the access log: {e}");
+    }
+}
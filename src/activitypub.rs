@@ -0,0 +1,813 @@
+//! A minimal [ActivityPub](https://www.w3.org/TR/activitypub/) actor, so that
+//! the site can be followed from Mastodon and the like.
+//!
+//! It's off unless `enabled = true` is set in the `[activitypub]` table of
+//! `site.toml`, and needs `base_url`. The actor is found by WebFinger as
+//! `@username@host`, and its outbox has a `Create` of an `Article` for each
+//! of the newest documents. Follows sent to its inbox are checked and
+//! accepted in the background, since that means fetching the follower's key,
+//! and documents a reload publishes are delivered to the followers' inboxes,
+//! signed with a key that's made the first time.
+
+use crate::webmention::agent;
+use crate::{IndexEntry, SiteConfig, State, background, http};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use log::{error, info, warn};
+use rsa::pkcs1v15::{Signature, SigningKey, VerifyingKey};
+use rsa::pkcs8::{
+    DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey,
+    LineEnding,
+};
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tiny_http::{Method, Request, Response, StatusCode};
+use url::Url;
+
+pub const WEBFINGER: &str = "/.well-known/webfinger";
+/// Where the actor and its collections are served.
+pub const PREFIX: &str = "/.activitypub/";
+pub const INBOX: &str = "/.activitypub/inbox";
+const ACTOR: &str = "/.activitypub/actor";
+const OUTBOX: &str = "/.activitypub/outbox";
+const FOLLOWERS: &str = "/.activitypub/followers";
+
+const CONTENT_TYPE: &str = "application/activity+json";
+const CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+/// How many documents the outbox has.
+const OUTBOX_SIZE: usize = 20;
+const MAX_BODY: u64 = 256 * 1024;
+/// How old a signed request can be, like Mastodon allows.
+const MAX_AGE: Duration = Duration::from_secs(12 * 60 * 60);
+/// The headers a signature must cover, so that it can't be replayed for
+/// another request, body or time.
+const SIGNED_HEADERS: [&str; 4] =
+    ["(request-target)", "host", "date", "digest"];
+
+/// The `[activitypub]` table of `site.toml`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ActivityPubConfig {
+    pub enabled: bool,
+    /// The actor's name, e.g. `blog` for `@blog@example.com`.
+    pub username: String,
+    /// Where the actor's private key is kept, relative to the content
+    /// directory. It's made if it doesn't exist.
+    pub key_file: PathBuf,
+    /// Where followers are kept, relative to the content directory.
+    pub followers_file: PathBuf,
+}
+
+impl Default for ActivityPubConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            username: "site".to_string(),
+            key_file: PathBuf::from(".activitypub-key.pem"),
+            followers_file: PathBuf::from(".activitypub-followers.json"),
+        }
+    }
+}
+
+/// The site's actor and its followers.
+#[derive(Debug, Default)]
+pub struct ActivityPub {
+    /// `None` if ActivityPub is off.
+    actor: Option<Actor>,
+    /// By their actor's ID.
+    followers: Mutex<BTreeMap<String, Follower>>,
+}
+
+#[derive(Debug, Clone)]
+struct Actor {
+    /// The site's `base_url`, without a trailing slash.
+    base: String,
+    host: String,
+    username: String,
+    /// The site's title, or its host.
+    name: String,
+    key: RsaPrivateKey,
+    public_key_pem: String,
+    followers_path: PathBuf,
+}
+
+impl Actor {
+    fn id(&self) -> String {
+        format!("{}{ACTOR}", self.base)
+    }
+
+    fn key_id(&self) -> String {
+        format!("{}#main-key", self.id())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Follower {
+    /// Where activities are delivered, the shared inbox of the follower's
+    /// server if it has one.
+    inbox: String,
+}
+
+/// The parts of another server's actor that are needed.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoteActor {
+    id: String,
+    inbox: String,
+    #[serde(default)]
+    endpoints: Endpoints,
+    public_key: PublicKey,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Endpoints {
+    shared_inbox: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicKey {
+    id: String,
+    owner: String,
+    public_key_pem: String,
+}
+
+impl ActivityPub {
+    pub fn load(
+        content_path: &Path,
+        config: &SiteConfig,
+    ) -> eyre::Result<Self> {
+        let ap = &config.activitypub;
+        if !ap.enabled {
+            return Ok(Self::default());
+        }
+        let Some(base) = config.base_url.as_deref() else {
+            warn!("ActivityPub is off without a base_url");
+            return Ok(Self::default());
+        };
+        let base = base.trim_end_matches('/').to_string();
+        let host = Url::parse(&base)?
+            .host_str()
+            .ok_or_else(|| eyre::eyre!("base_url has no host"))?
+            .to_string();
+        let key = load_key(&content_path.join(&ap.key_file))?;
+        let public_key_pem =
+            key.to_public_key().to_public_key_pem(LineEnding::LF)?;
+
+        let followers_path = content_path.join(&ap.followers_file);
+        let followers = if followers_path.is_file() {
+            let followers = std::fs::read_to_string(&followers_path)?;
+            serde_json::from_str(&followers).map_err(|e| {
+                eyre::eyre!("Invalid \"{}\": {e}", followers_path.display())
+            })?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            actor: Some(Actor {
+                name: config.title.clone().unwrap_or_else(|| host.clone()),
+                base,
+                host,
+                username: ap.username.clone(),
+                key,
+                public_key_pem,
+                followers_path,
+            }),
+            followers: Mutex::new(followers),
+        })
+    }
+
+    /// Add or remove (if `follower` is `None`) the follower `id`, and save
+    /// them.
+    fn update(&self, id: &str, follower: Option<Follower>) {
+        let Some(actor) = &self.actor else {
+            return;
+        };
+        let mut followers = self.followers.lock().unwrap();
+        match follower {
+            Some(follower) => followers.insert(id.to_string(), follower),
+            None => followers.remove(id),
+        };
+        let json = serde_json::to_string_pretty(&*followers)
+            .expect("followers are serializable");
+        let path = &actor.followers_path;
+        let temp = path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&temp, json)
+            .and_then(|()| std::fs::rename(&temp, path))
+        {
+            error!("Failed to save followers to \"{}\": {e}", path.display());
+        }
+    }
+}
+
+/// The actor's private key at `path`, which is made if it doesn't exist.
+fn load_key(path: &Path) -> eyre::Result<RsaPrivateKey> {
+    if path.is_file() {
+        let pem = std::fs::read_to_string(path)?;
+        return RsaPrivateKey::from_pkcs8_pem(&pem)
+            .map_err(|e| eyre::eyre!("Invalid \"{}\": {e}", path.display()));
+    }
+    info!("Making an ActivityPub key at \"{}\"", path.display());
+    let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048)?;
+    let pem = key.to_pkcs8_pem(LineEnding::LF)?;
+    // Only readable by whoever runs the site.
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(pem.as_bytes())?;
+    Ok(key)
+}
+
+/// Handle a request to [`WEBFINGER`] or under [`PREFIX`].
+pub fn serve(rq: Request, state: &Arc<RwLock<State>>, path: &str, url: &Url) {
+    let state_l = state.read().unwrap();
+    let Some(actor) = &state_l.activitypub.actor else {
+        crate::respond(rq, crate::error_response(&state_l, 404));
+        return;
+    };
+    let json = match path {
+        WEBFINGER => {
+            let resource = url
+                .query_pairs()
+                .find(|(k, _)| k == "resource")
+                .map(|(_, v)| v.into_owned());
+            let subject = format!("acct:{}@{}", actor.username, actor.host);
+            if resource.as_deref() != Some(subject.as_str())
+                && resource.as_deref() != Some(actor.id().as_str())
+            {
+                crate::respond(rq, crate::error_response(&state_l, 404));
+                return;
+            }
+            let json = json!({
+                "subject": subject,
+                "aliases": [actor.id()],
+                "links": [{
+                    "rel": "self",
+                    "type": CONTENT_TYPE,
+                    "href": actor.id(),
+                }, {
+                    "rel": "http://webfinger.net/rel/profile-page",
+                    "type": "text/html",
+                    "href": format!("{}/", actor.base),
+                }],
+            });
+            let response = Response::from_string(json.to_string()).with_header(
+                http::make_header("Content-Type", "application/jrd+json"),
+            );
+            crate::respond(rq, response);
+            return;
+        }
+        ACTOR => json!({
+            "@context": [CONTEXT, "https://w3id.org/security/v1"],
+            "id": actor.id(),
+            "type": "Person",
+            "preferredUsername": actor.username,
+            "name": actor.name,
+            "url": format!("{}/", actor.base),
+            "inbox": format!("{}{INBOX}", actor.base),
+            "outbox": format!("{}{OUTBOX}", actor.base),
+            "followers": format!("{}{FOLLOWERS}", actor.base),
+            "manuallyApprovesFollowers": false,
+            "publicKey": {
+                "id": actor.key_id(),
+                "owner": actor.id(),
+                "publicKeyPem": actor.public_key_pem,
+            },
+        }),
+        OUTBOX => {
+            let listed = state_l
+                .index
+                .iter()
+                .filter(|x| state_l.is_listed_at(x, OUTBOX))
+                .collect::<Vec<_>>();
+            let items = listed
+                .iter()
+                .take(OUTBOX_SIZE)
+                .map(|x| create(actor, x))
+                .collect::<Vec<_>>();
+            json!({
+                "@context": CONTEXT,
+                "id": format!("{}{OUTBOX}", actor.base),
+                "type": "OrderedCollection",
+                "totalItems": listed.len(),
+                "orderedItems": items,
+            })
+        }
+        // Who follows isn't shown, only how many.
+        FOLLOWERS => json!({
+            "@context": CONTEXT,
+            "id": format!("{}{FOLLOWERS}", actor.base),
+            "type": "OrderedCollection",
+            "totalItems": state_l.activitypub.followers.lock().unwrap().len(),
+        }),
+        INBOX => {
+            let actor = actor.clone();
+            drop(state_l);
+            inbox(rq, state, &actor);
+            return;
+        }
+        _ => {
+            crate::respond(rq, crate::error_response(&state_l, 404));
+            return;
+        }
+    };
+    let response = Response::from_string(json.to_string())
+        .with_header(http::make_header("Content-Type", CONTENT_TYPE));
+    crate::respond(rq, response);
+}
+
+/// Accept or undo a follow. Other activities are ignored.
+fn inbox(mut rq: Request, state: &Arc<RwLock<State>>, actor: &Actor) {
+    if *rq.method() != Method::Post {
+        crate::respond(
+            rq,
+            Response::new_empty(StatusCode(405))
+                .with_header(http::make_header("Allow", "POST")),
+        );
+        return;
+    }
+    let mut body = vec![];
+    if let Err(e) = crate::timeout::reader(rq.as_reader())
+        .take(MAX_BODY)
+        .read_to_end(&mut body)
+    {
+        error!("Failed to read an activity: {e}");
+        crate::respond(rq, Response::new_empty(StatusCode(400)));
+        return;
+    }
+    let Ok(activity) = serde_json::from_slice::<Value>(&body) else {
+        crate::respond(rq, Response::new_empty(StatusCode(400)));
+        return;
+    };
+
+    let kind = activity["type"].as_str().unwrap_or_default();
+    let object = &activity["object"];
+    let ours = match kind {
+        "Follow" => object.as_str() == Some(actor.id().as_str()),
+        "Undo" => object["type"] == "Follow",
+        _ => false,
+    };
+    if !ours {
+        crate::respond(rq, Response::new_empty(StatusCode(202)));
+        return;
+    }
+    let Some(signed) = signature(&rq, &body) else {
+        info!("Ignored an unsigned {kind} activity");
+        crate::respond(rq, Response::new_empty(StatusCode(401)));
+        return;
+    };
+
+    // Checking the signature means fetching the sender's key, which can take
+    // a while, so it's left to the background.
+    let (state, actor) = (state.clone(), actor.clone());
+    if !background::submit(move || follow(&state, &actor, &activity, &signed)) {
+        warn!("Too many activities are waiting, rejecting one");
+        crate::respond(
+            rq,
+            Response::new_empty(StatusCode(503))
+                .with_header(http::make_header("Retry-After", "60")),
+        );
+        return;
+    }
+    crate::respond(rq, Response::new_empty(StatusCode(202)));
+}
+
+/// Check the signature of a `Follow` or `Undo` activity, then record it and
+/// deliver an `Accept` of the follow.
+fn follow(
+    state: &RwLock<State>,
+    actor: &Actor,
+    activity: &Value,
+    signed: &Signed,
+) {
+    let kind = activity["type"].as_str().unwrap_or_default();
+    let agent = agent();
+    let remote = match signer(&agent, actor, signed) {
+        Some(x) if activity["actor"].as_str() == Some(x.id.as_str()) => x,
+        _ => {
+            info!("Ignored a {kind} activity with a wrong signature");
+            return;
+        }
+    };
+
+    // The current state, which a reload may have replaced in the meantime.
+    let state_l = state.read().unwrap();
+    if kind == "Undo" {
+        info!("Unfollowed by \"{}\"", remote.id);
+        state_l.activitypub.update(&remote.id, None);
+        return;
+    }
+    info!("Followed by \"{}\"", remote.id);
+    let follower = Follower {
+        inbox: remote
+            .endpoints
+            .shared_inbox
+            .unwrap_or(remote.inbox.clone()),
+    };
+    state_l.activitypub.update(&remote.id, Some(follower));
+    drop(state_l);
+
+    let follow_id = activity["id"].as_str().unwrap_or_default();
+    let id = format!(
+        "{}#accepts/{}",
+        actor.id(),
+        hex::encode(Sha256::digest(follow_id))
+    );
+    let accept = json!({
+        "@context": CONTEXT,
+        "id": id,
+        "type": "Accept",
+        "actor": actor.id(),
+        "object": activity,
+    });
+    deliver(&agent, actor, &remote.inbox, &accept);
+}
+
+/// A request's signature, which is checked once the signer's key is fetched.
+struct Signed {
+    key_id: Url,
+    /// What was signed, the covered headers one per line.
+    signing_string: String,
+    signature: Signature,
+}
+
+/// The signature of `rq`, if it covers the request's target, host, date and
+/// body, and the date is recent.
+fn signature(rq: &Request, body: &[u8]) -> Option<Signed> {
+    // `keyId="...",headers="(request-target) host date digest",...`
+    let params = http::header(rq, "Signature")?
+        .split(',')
+        .filter_map(|x| {
+            let (k, v) = x.split_once('=')?;
+            Some((k.trim(), v.trim().trim_matches('"')))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let names = params.get("headers").copied().unwrap_or("date");
+    if !SIGNED_HEADERS
+        .iter()
+        .all(|x| names.split_whitespace().any(|y| y.eq_ignore_ascii_case(x)))
+    {
+        return None;
+    }
+    let digest = format!("SHA-256={}", BASE64.encode(Sha256::digest(body)));
+    if http::header(rq, "Digest") != Some(digest.as_str()) {
+        return None;
+    }
+    let date = httpdate::parse_http_date(http::header(rq, "Date")?).ok()?;
+    let age = SystemTime::now()
+        .duration_since(date)
+        .or_else(|_| date.duration_since(SystemTime::now()))
+        .ok()?;
+    if age > MAX_AGE {
+        return None;
+    }
+
+    let signing_string = names
+        .split_whitespace()
+        .map(|name| {
+            let value = match name {
+                "(request-target)" => format!(
+                    "{} {}",
+                    rq.method().to_string().to_lowercase(),
+                    rq.url()
+                ),
+                _ => http::header(rq, name)?.to_string(),
+            };
+            Some(format!("{name}: {value}"))
+        })
+        .collect::<Option<Vec<_>>>()?
+        .join("\n");
+    let signature = BASE64.decode(params.get("signature")?).ok()?;
+    Some(Signed {
+        key_id: Url::parse(params.get("keyId")?).ok()?,
+        signing_string,
+        signature: Signature::try_from(signature.as_slice()).ok()?,
+    })
+}
+
+/// The actor that made `signed`, if the signature is right.
+fn signer(
+    agent: &ureq::Agent,
+    actor: &Actor,
+    signed: &Signed,
+) -> Option<RemoteActor> {
+    let remote = fetch_actor(agent, actor, &signed.key_id)?;
+    if !owns_key(&remote, &signed.key_id) {
+        info!(
+            "\"{}\" doesn't belong to the actor \"{}\"",
+            signed.key_id, remote.id
+        );
+        return None;
+    }
+    let key =
+        RsaPublicKey::from_public_key_pem(&remote.public_key.public_key_pem)
+            .ok()?;
+    VerifyingKey::<Sha256>::new(key)
+        .verify(signed.signing_string.as_bytes(), &signed.signature)
+        .ok()?;
+    Some(remote)
+}
+
+/// Whether `key_id` is the key of `remote`, as its document says and from
+/// the same server, so that a document can't claim someone else's identity.
+fn owns_key(remote: &RemoteActor, key_id: &Url) -> bool {
+    let same_origin =
+        |x: &str| Url::parse(x).is_ok_and(|x| x.origin() == key_id.origin());
+    remote.public_key.id == key_id.as_str()
+        && remote.public_key.owner == remote.id
+        && same_origin(&remote.id)
+}
+
+/// The actor whose key is `key_id`, fetched with a signed request for
+/// servers that require one.
+fn fetch_actor(
+    agent: &ureq::Agent,
+    actor: &Actor,
+    key_id: &Url,
+) -> Option<RemoteActor> {
+    let mut url = key_id.clone();
+    url.set_fragment(None);
+    let mut request = agent.get(url.as_str()).set("Accept", CONTENT_TYPE);
+    for (name, value) in sign(actor, "get", &url, None) {
+        request = request.set(name, &value);
+    }
+    let response = request
+        .call()
+        .inspect_err(|e| info!("Failed to fetch the actor \"{url}\": {e}"))
+        .ok()?;
+    serde_json::from_reader(response.into_reader().take(MAX_BODY)).ok()
+}
+
+/// The headers that sign a request to `url` as the actor, along with its
+/// `body`, if it has one.
+fn sign(
+    actor: &Actor,
+    method: &str,
+    url: &Url,
+    body: Option<&[u8]>,
+) -> Vec<(&'static str, String)> {
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let target = &url[url::Position::BeforePath..url::Position::AfterQuery];
+    let mut headers = vec![
+        ("(request-target)", format!("{method} {target}")),
+        ("host", host),
+        ("date", httpdate::fmt_http_date(SystemTime::now())),
+    ];
+    if let Some(body) = body {
+        let digest = BASE64.encode(Sha256::digest(body));
+        headers.push(("digest", format!("SHA-256={digest}")));
+    }
+
+    let signing_string = headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let signature = SigningKey::<Sha256>::new(actor.key.clone())
+        .sign(signing_string.as_bytes());
+    let names = headers.iter().map(|x| x.0).collect::<Vec<_>>().join(" ");
+    let signature = format!(
+        "keyId=\"{}\",algorithm=\"rsa-sha256\",headers=\"{names}\",\
+         signature=\"{}\"",
+        actor.key_id(),
+        BASE64.encode(signature.to_bytes())
+    );
+    // The agent sets the others itself.
+    headers.retain(|x| matches!(x.0, "date" | "digest"));
+    headers.push(("signature", signature));
+    headers
+}
+
+/// Post `activity` to `inbox`, returning whether it was accepted.
+fn deliver(
+    agent: &ureq::Agent,
+    actor: &Actor,
+    inbox: &str,
+    activity: &Value,
+) -> bool {
+    let Ok(url) = Url::parse(inbox) else {
+        return false;
+    };
+    let body = activity.to_string();
+    let mut request =
+        agent.post(url.as_str()).set("Content-Type", CONTENT_TYPE);
+    for (name, value) in sign(actor, "post", &url, Some(body.as_bytes())) {
+        request = request.set(name, &value);
+    }
+    match request.send_string(&body) {
+        Ok(_) => true,
+        Err(e) => {
+            info!("Failed to deliver an activity to \"{inbox}\": {e}");
+            false
+        }
+    }
+}
+
+/// The `Create` activity of the document `entry`.
+fn create(actor: &Actor, entry: &IndexEntry) -> Value {
+    let url = format!("{}{}", actor.base, http::encode_path(&entry.url));
+    let meta = &entry.meta;
+    let mut content = String::new();
+    if let Some(desc) = &meta.desc {
+        content.push_str(&format!("<p>{}</p>", escape(desc)));
+    }
+    content.push_str(&format!(
+        "<p><a href=\"{}\">{}</a></p>",
+        escape(&url),
+        escape(&meta.title)
+    ));
+    let followers = format!("{}{FOLLOWERS}", actor.base);
+    let mut article = json!({
+        "id": url,
+        "type": "Article",
+        "attributedTo": actor.id(),
+        "name": meta.title,
+        "url": url,
+        "content": content,
+        "to": [PUBLIC],
+        "cc": [followers],
+    });
+    let mut create = json!({
+        "@context": CONTEXT,
+        "id": format!("{url}#create"),
+        "type": "Create",
+        "actor": actor.id(),
+        "to": [PUBLIC],
+        "cc": [followers],
+    });
+    if let Some(date) = meta.date {
+        let published = format!("{date}T00:00:00Z");
+        article["published"] = published.clone().into();
+        create["published"] = published.into();
+    }
+    create["object"] = article;
+    create
+}
+
+/// The documents a reload published, to deliver to the followers.
+pub struct Outgoing {
+    actor: Actor,
+    inboxes: BTreeSet<String>,
+    activities: Vec<Value>,
+}
+
+impl Outgoing {
+    /// The documents in `current` that weren't in `previous`, `None` if there
+    /// are none or nobody to deliver them to.
+    pub fn new(previous: &State, current: &State) -> Option<Self> {
+        // Everything would be new when it's just been turned on.
+        previous.activitypub.actor.as_ref()?;
+        let actor = current.activitypub.actor.clone()?;
+        let activities = current
+            .index
+            .iter()
+            .filter(|x| !previous.index.iter().any(|y| y.url == x.url))
+            .filter(|x| current.is_listed_at(x, OUTBOX))
+            .map(|x| create(&actor, x))
+            .collect::<Vec<_>>();
+        let followers = current.activitypub.followers.lock().unwrap();
+        let inboxes = followers
+            .values()
+            .map(|x| x.inbox.clone())
+            .collect::<BTreeSet<_>>();
+        (!activities.is_empty() && !inboxes.is_empty()).then_some(Self {
+            actor,
+            inboxes,
+            activities,
+        })
+    }
+
+    /// Deliver the documents, which can take a while.
+    pub fn send(self) {
+        let agent = agent();
+        for activity in &self.activities {
+            let delivered = self
+                .inboxes
+                .iter()
+                .filter(|x| deliver(&agent, &self.actor, x, activity))
+                .count();
+            info!(
+                "Delivered \"{}\" to {delivered} of {} inboxes",
+                activity["object"]["url"].as_str().unwrap_or_default(),
+                self.inboxes.len()
+            );
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_http::TestRequest;
+
+    const BODY: &[u8] = br#"{"type":"Follow"}"#;
+
+    /// A request to the inbox signed over `names`.
+    fn signed(names: &str) -> Request {
+        let digest = format!("SHA-256={}", BASE64.encode(Sha256::digest(BODY)));
+        let signature = format!(
+            "keyId=\"https://example.com/actor#main-key\",\
+             headers=\"{names}\",signature=\"{}\"",
+            BASE64.encode([0; 256])
+        );
+        [
+            ("Host", "site.example"),
+            ("Date", &httpdate::fmt_http_date(SystemTime::now())),
+            ("Digest", &digest),
+            ("Signature", &signature),
+        ]
+        .into_iter()
+        .fold(
+            TestRequest::new()
+                .with_method(Method::Post)
+                .with_path(INBOX),
+            |rq, (name, value)| rq.with_header(http::make_header(name, value)),
+        )
+        .into()
+    }
+
+    #[test]
+    fn covered_headers() {
+        let rq = signed("(request-target) host date digest");
+        let signed = signature(&rq, BODY).unwrap();
+        assert_eq!(
+            signed.key_id.as_str(),
+            "https://example.com/actor#main-key"
+        );
+        assert!(signed.signing_string.starts_with(&format!(
+            "(request-target): post {INBOX}\nhost: site.example\ndate: "
+        )));
+    }
+
+    #[test]
+    fn uncovered_headers() {
+        for names in [
+            "digest",
+            "date digest",
+            "host date digest",
+            "(request-target) host digest",
+            "(request-target) host date",
+        ] {
+            assert!(signature(&signed(names), BODY).is_none(), "{names}");
+        }
+    }
+
+    fn remote(id: &str, key_id: &str, owner: &str) -> RemoteActor {
+        serde_json::from_value(json!({
+            "id": id,
+            "inbox": format!("{id}/inbox"),
+            "publicKey": {
+                "id": key_id,
+                "owner": owner,
+                "publicKeyPem": "",
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn key_owners() {
+        let alice = "https://social.example/users/alice";
+        let key_id = Url::parse(&format!("{alice}#main-key")).unwrap();
+        assert!(owns_key(&remote(alice, key_id.as_str(), alice), &key_id));
+
+        // Another server's document claiming to be alice.
+        let evil = Url::parse("https://evil.example/k").unwrap();
+        assert!(!owns_key(&remote(alice, evil.as_str(), alice), &evil));
+        // A key that isn't the one that signed.
+        let other = format!("{alice}#other-key");
+        assert!(!owns_key(&remote(alice, &other, alice), &key_id));
+        // A key owned by someone else.
+        let bob = "https://social.example/users/bob";
+        assert!(!owns_key(&remote(alice, key_id.as_str(), bob), &key_id));
+    }
+
+    #[test]
+    fn changed_body() {
+        let rq = signed("(request-target) host date digest");
+        assert!(signature(&rq, br#"{"type":"Undo"}"#).is_none());
+    }
+}
@@ -0,0 +1,240 @@
+//! AsciiDoc documents.
+//!
+//! They're converted to HTML by `asciidoctor`, if it's installed, and their
+//! metadata comes from the document header: the `= Title` line, the author
+//! and revision lines after it, and the `:description:`, `:keywords:`,
+//! `:lang:`, `:revdate:`, `:slug:`, `:aliases:` and `:draft:` attributes.
+//! Files without a title aren't documents. Without `asciidoctor` their source
+//! is shown as it is.
+
+use crate::{Body, GitMeta, HeaderTemplate, LinkTarget, Links, Meta, Rendered};
+use chrono::NaiveDate;
+use log::{error, warn};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{LazyLock, Mutex};
+
+/// Converted documents by their source, so that they're only converted once
+/// rather than every time they're rendered. Ones that failed to convert are
+/// `None`.
+static CONVERTED: LazyLock<Mutex<HashMap<String, Option<String>>>> =
+    LazyLock::new(Default::default);
+
+static ASCIIDOCTOR: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
+    let program = crate::find_program("asciidoctor");
+    if program.is_none() {
+        warn!("asciidoctor wasn't found, so AsciiDoc is shown as it is");
+    }
+    program
+});
+
+pub fn is_asciidoc(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|x| x.to_str()),
+        Some("adoc" | "asciidoc")
+    )
+}
+
+/// Render an AsciiDoc document, like [`crate::markdown_to_document`] does a
+/// markdown one.
+pub fn to_document(
+    header: &HeaderTemplate,
+    contents: &str,
+    url: Option<&str>,
+    links: Option<&Links>,
+    git: Option<&GitMeta>,
+) -> Rendered {
+    let (html, targets) = match convert(contents) {
+        Some(html) => rewrite_links(&html, links),
+        None => (
+            format!("<pre class=\"asciidoc\">{}</pre>", escape(contents)),
+            vec![],
+        ),
+    };
+    crate::document_page(
+        header,
+        Body {
+            html,
+            meta: meta(contents),
+            toc: vec![],
+            words: search_text(contents).split_whitespace().count(),
+            links: targets,
+            mermaid: false,
+            code: false,
+            embeds: false,
+            tables: false,
+        },
+        url,
+        links,
+        git,
+    )
+}
+
+/// The text of the document, without its markup, for the search index.
+pub fn search_text(contents: &str) -> String {
+    match convert(contents) {
+        Some(html) => crate::html_pages::text(&html),
+        None => contents.to_string(),
+    }
+}
+
+/// The document's body as HTML, or `None` if it can't be converted.
+fn convert(contents: &str) -> Option<String> {
+    let program = ASCIIDOCTOR.as_ref()?;
+    CONVERTED
+        .lock()
+        .unwrap()
+        .entry(contents.to_string())
+        .or_insert_with(|| run(program, contents))
+        .clone()
+}
+
+fn run(program: &Path, contents: &str) -> Option<String> {
+    // Cross references keep the `.adoc` extension, so that they're resolved
+    // like links to other documents.
+    let child = Command::new(program)
+        .args(["--embedded", "-a", "outfilesuffix=.adoc", "-o", "-", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to run asciidoctor: {e}");
+            return None;
+        }
+    };
+    // Written from another thread so that a large document can't fill the
+    // output pipe while its input is still being written.
+    let mut stdin = child.stdin.take()?;
+    let input = contents.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output();
+    if let Ok(Err(e)) = writer.join() {
+        error!("Failed to write to asciidoctor: {e}");
+    }
+    match output {
+        Ok(out) if out.status.success() => {
+            Some(String::from_utf8_lossy(&out.stdout).into_owned())
+        }
+        Ok(out) => {
+            error!(
+                "asciidoctor failed to convert a document: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            None
+        }
+        Err(e) => {
+            error!("Failed to run asciidoctor: {e}");
+            None
+        }
+    }
+}
+
+/// Point the links in `html` to where the documents they link to are served,
+/// returning it with where they pointed.
+fn rewrite_links(
+    html: &str,
+    links: Option<&Links>,
+) -> (String, Vec<LinkTarget>) {
+    const HREF: &str = "href=\"";
+    let mut rewritten = String::with_capacity(html.len());
+    let mut targets = vec![];
+    let mut rest = html;
+    while let Some(start) = rest.find(HREF) {
+        let (before, after) = rest.split_at(start + HREF.len());
+        rewritten.push_str(before);
+        let end = after.find('"').unwrap_or(after.len());
+        let href = after[..end].replace("&amp;", "&");
+        targets.push(LinkTarget::Path(href.clone()));
+        match links.and_then(|x| x.resolve(&href)) {
+            Some(resolved) => rewritten.push_str(&escape(&resolved)),
+            None => rewritten.push_str(&after[..end]),
+        }
+        rest = &after[end..];
+    }
+    rewritten.push_str(rest);
+    (rewritten, targets)
+}
+
+/// The metadata in the document's header, which ends at the first blank
+/// line.
+fn meta(contents: &str) -> Option<Meta> {
+    let mut meta = Meta::default();
+    let mut title = None;
+    // The author and revision lines, in that order.
+    let mut line_after_title = 0;
+    for line in contents
+        .lines()
+        .skip_while(|x| x.trim().is_empty() || x.starts_with("//"))
+        .take_while(|x| !x.trim().is_empty())
+        .filter(|x| !x.starts_with("//"))
+    {
+        if title.is_none() {
+            title = Some(line.strip_prefix("= ")?.trim().to_string());
+            continue;
+        }
+        if let Some(attribute) = line.strip_prefix(':')
+            && let Some((name, value)) = attribute.split_once(':')
+        {
+            let value = value.trim();
+            match name.trim_start_matches('!') {
+                "description" => meta.desc = Some(value.to_string()),
+                "keywords" => {
+                    meta.tags = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|x| !x.is_empty())
+                        .map(String::from)
+                        .collect();
+                }
+                "lang" => meta.lang = Some(value.to_string()),
+                "author" => meta.author = Some(value.to_string()),
+                "revdate" => meta.date = date(value),
+                "slug" => meta.slug = Some(value.to_string()),
+                "aliases" => {
+                    meta.aliases = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|x| !x.is_empty())
+                        .map(String::from)
+                        .collect();
+                }
+                // Setting it without a value turns it on.
+                "draft" => {
+                    meta.draft = !name.starts_with('!')
+                        && matches!(value, "" | "true" | "yes")
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match line_after_title {
+            // `Jane Doe <jane@example.com>`
+            0 => {
+                let name = line.split('<').next().unwrap_or(line).trim();
+                meta.author = Some(name.to_string());
+            }
+            // `v1.0, 2024-01-15: Remark`
+            1 => meta.date = line.split([',', ':']).find_map(date),
+            _ => {}
+        }
+        line_after_title += 1;
+    }
+    meta.title = title?;
+    Some(meta)
+}
+
+fn date(value: &str) -> Option<NaiveDate> {
+    value.trim().get(..10)?.parse().ok()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
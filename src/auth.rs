@@ -0,0 +1,130 @@
+//! Passwords for parts of the site, checked with HTTP Basic authentication.
+//!
+//! Each `[[protected]]` table in `site.toml` has a path prefix (`/` for the
+//! whole site) and the users allowed under it, with their passwords hashed
+//! with argon2 or bcrypt. The most specific prefix that covers a request is
+//! the one it's checked against. Documents under a prefix are left out of
+//! listings that aren't under it too, like the root index and search.
+
+use crate::http;
+use base64::Engine;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use tiny_http::{Request, Response, ResponseBox, StatusCode};
+
+/// Hashes of the credentials that were right, so that the slow hashes are
+/// only checked once per user rather than on every request.
+static VERIFIED: LazyLock<Mutex<HashSet<[u8; 32]>>> =
+    LazyLock::new(Default::default);
+const MAX_VERIFIED: usize = 1024;
+
+/// A `[[protected]]` table of `site.toml`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Protected {
+    /// The path prefix, e.g. `/notes`, or `/` for the whole site.
+    pub path: String,
+    /// Password hashes by user name, e.g. `$argon2id$...` or `$2y$...`.
+    pub users: BTreeMap<String, String>,
+}
+
+impl Protected {
+    fn covers(&self, path: &str) -> bool {
+        let prefix = self.path.trim_end_matches('/');
+        path.strip_prefix(prefix)
+            .is_some_and(|x| x.is_empty() || x.starts_with('/'))
+    }
+}
+
+/// The protection that covers `path`, if any.
+fn protection<'a>(
+    config: &'a [Protected],
+    path: &str,
+) -> Option<&'a Protected> {
+    config
+        .iter()
+        .filter(|x| x.covers(path))
+        .max_by_key(|x| x.path.trim_end_matches('/').len())
+}
+
+/// Whether the document at `url` can be listed on the page at `page`, which
+/// it can unless it's protected differently.
+pub fn listable(config: &[Protected], url: &str, page: &str) -> bool {
+    match protection(config, url) {
+        Some(x) => protection(config, page).is_some_and(|y| std::ptr::eq(x, y)),
+        None => true,
+    }
+}
+
+/// Check the credentials of a request for `path`, returning the response
+/// that asks for them if they're wrong or missing.
+pub fn check(
+    rq: &Request,
+    config: &[Protected],
+    path: &str,
+) -> Result<(), ResponseBox> {
+    let Some(protected) = protection(config, path) else {
+        return Ok(());
+    };
+    let credentials = http::header(rq, "Authorization")
+        .and_then(|x| x.strip_prefix("Basic "))
+        .and_then(|x| {
+            base64::engine::general_purpose::STANDARD
+                .decode(x.trim())
+                .ok()
+        })
+        .and_then(|x| String::from_utf8(x).ok());
+    if let Some(credentials) = credentials
+        && let Some((user, password)) = credentials.split_once(':')
+        && let Some(hash) = protected.users.get(user)
+        && verify(user, password, hash)
+    {
+        return Ok(());
+    }
+    let realm = protected.path.replace('"', "");
+    Err(Response::new_empty(StatusCode(401))
+        .with_header(http::make_header(
+            "WWW-Authenticate",
+            &format!("Basic realm=\"{realm}\", charset=\"UTF-8\""),
+        ))
+        .boxed())
+}
+
+fn verify(user: &str, password: &str, hash: &str) -> bool {
+    let key: [u8; 32] = Sha256::new()
+        .chain_update(hash)
+        .chain_update([0])
+        .chain_update(user)
+        .chain_update([0])
+        .chain_update(password)
+        .finalize()
+        .into();
+    if VERIFIED.lock().unwrap().contains(&key) {
+        return true;
+    }
+
+    let right = if hash.starts_with("$argon2") {
+        use argon2::password_hash::{PasswordHash, PasswordVerifier};
+        PasswordHash::new(hash).is_ok_and(|hash| {
+            argon2::Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    } else if hash.starts_with("$2") {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    } else {
+        warn!("The password of \"{user}\" isn't an argon2 or bcrypt hash");
+        false
+    };
+    if right {
+        let mut verified = VERIFIED.lock().unwrap();
+        if verified.len() >= MAX_VERIFIED {
+            verified.clear();
+        }
+        verified.insert(key);
+    }
+    right
+}
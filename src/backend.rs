@@ -0,0 +1,595 @@
+//! How connections are accepted.
+//!
+//! The threads backend is tiny_http on its own, where a client keeps a serve
+//! thread busy for as long as it takes to send its request and read the
+//! response. The async backend (with the `async` feature) puts hyper in
+//! front of it: connections are accepted and kept alive by tokio, with
+//! HTTP/1.1 or HTTP/2, and each request is read in full before it's passed
+//! on to tiny_http over a private Unix socket. The serve threads only see
+//! requests that are ready, and hand their responses to hyper to send, so
+//! routing and rendering are the same for both.
+//!
+//! The async backend can also serve HTTPS, offering HTTP/2 with ALPN.
+
+use std::path::PathBuf;
+use std::time::Duration;
+use tiny_http::Listener;
+
+/// The PEM files of a certificate (chain) and its private key, for serving
+/// HTTPS.
+#[derive(Debug, Clone)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// How long the async backend waits for a client, like the threads backend's
+/// socket timeouts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeouts {
+    /// For a request's headers, and for an HTTP/2 client to answer a ping.
+    pub read: Option<Duration>,
+    /// For a client to take more of a response.
+    pub write: Option<Duration>,
+}
+
+/// Which server accepts connections.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    /// tiny_http, which a client has a serve thread to itself with.
+    #[default]
+    Threads,
+    /// tokio and hyper in front of tiny_http, for keep-alive, HTTP/2 and
+    /// many connections at once. Needs the `async` feature.
+    Async,
+}
+
+#[cfg(feature = "async")]
+pub use front::Front;
+
+#[cfg(not(feature = "async"))]
+#[derive(Debug)]
+pub struct Front;
+
+#[cfg(not(feature = "async"))]
+impl Front {
+    pub fn spawn(
+        _listeners: Vec<Listener>,
+        _trust_proxy: bool,
+        _tls: Option<&Tls>,
+        _timeouts: Timeouts,
+    ) -> eyre::Result<(Self, Listener)> {
+        Err(eyre::eyre!(
+            "The async backend needs site to be built with the \"async\" \
+             feature"
+        ))
+    }
+
+    pub fn stop(self) {}
+}
+
+#[cfg(feature = "async")]
+mod front {
+    use super::{Listener, Timeouts, Tls};
+    use crate::listen::{self, BindAddr};
+    use eyre::eyre;
+    use http_body_util::combinators::BoxBody;
+    use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+    use hyper::body::{Body, Bytes, Frame, Incoming};
+    use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+    use hyper::{Request, Response, StatusCode, Version};
+    use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+    use hyper_util::server::conn::auto;
+    use log::{debug, error, warn};
+    use std::convert::Infallible;
+    use std::future::Future;
+    use std::io;
+    use std::net::IpAddr;
+    use std::path::{Path, PathBuf};
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, ready};
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::net::{TcpListener, UnixListener, UnixStream};
+    use tokio::sync::{Notify, mpsc};
+    use tokio_rustls::TlsAcceptor;
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::rustls::pki_types::pem::PemObject;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    /// The largest request body that's passed on, like the webhook's.
+    const MAX_BODY: usize = 4 * 1024 * 1024;
+    /// How many frames of a response are read ahead of the client, so that
+    /// the serve thread is done with most responses before they're sent.
+    const READ_AHEAD: usize = 256;
+    /// How long a client has to finish the TLS handshake.
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+    /// Headers that are about a connection rather than the request or
+    /// response, so aren't passed on.
+    const HOP_BY_HOP: [&str; 7] = [
+        "connection",
+        "keep-alive",
+        "proxy-connection",
+        "te",
+        "trailer",
+        "transfer-encoding",
+        "upgrade",
+    ];
+
+    type ResponseBody = BoxBody<Bytes, hyper::Error>;
+
+    /// hyper, accepting connections and passing their requests on to
+    /// tiny_http.
+    #[derive(Debug)]
+    pub struct Front {
+        shutdown: Arc<Notify>,
+        thread: JoinHandle<()>,
+        /// The directory the private socket is in.
+        dir: PathBuf,
+    }
+
+    struct Options {
+        /// The socket tiny_http listens on.
+        backend: PathBuf,
+        trust_proxy: bool,
+        tls: Option<TlsAcceptor>,
+        timeouts: Timeouts,
+    }
+
+    impl Front {
+        /// Accept connections from `listeners`, returning the listener for
+        /// tiny_http to take requests from. `trust_proxy` keeps the
+        /// `X-Forwarded-*` headers the client sent, they're replaced
+        /// otherwise. Connections are HTTPS if there's `tls`.
+        pub fn spawn(
+            listeners: Vec<Listener>,
+            trust_proxy: bool,
+            tls: Option<&Tls>,
+            timeouts: Timeouts,
+        ) -> eyre::Result<(Self, Listener)> {
+            let tls = tls.map(acceptor).transpose()?;
+            let dir = private_dir()?;
+            let backend = dir.join("backend.sock");
+            let internal =
+                listen::bind(&BindAddr::Unix(backend.clone()), Some(0o600))?;
+
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()?;
+            let shutdown = Arc::new(Notify::new());
+            let options = Arc::new(Options {
+                backend,
+                trust_proxy,
+                tls,
+                timeouts,
+            });
+            let thread = {
+                let shutdown = shutdown.clone();
+                std::thread::spawn(move || {
+                    let accepted =
+                        runtime.block_on(accept(listeners, options, shutdown));
+                    if let Err(e) = accepted {
+                        error!("Failed to accept connections: {e}");
+                    }
+                    runtime.shutdown_background();
+                })
+            };
+            Ok((
+                Self {
+                    shutdown,
+                    thread,
+                    dir,
+                },
+                internal,
+            ))
+        }
+
+        /// Stop accepting connections, dropping the ones that are open.
+        pub fn stop(self) {
+            self.shutdown.notify_one();
+            if self.thread.join().is_err() {
+                error!("The async backend panicked");
+            }
+            if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+                warn!("Failed to remove \"{}\": {e}", self.dir.display());
+            }
+        }
+    }
+
+    /// Create a directory only this user can use for the private socket. Its
+    /// name is random rather than the process's ID, which is often the same
+    /// from one run to the next in a container, so that one left behind by a
+    /// run that was killed is never in the way.
+    fn private_dir() -> eyre::Result<PathBuf> {
+        use std::os::unix::fs::DirBuilderExt;
+        loop {
+            let dir = std::env::temp_dir().join(format!(
+                "site-{}-{:08x}",
+                std::process::id(),
+                rand::random::<u32>()
+            ));
+            match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+                Ok(()) => return Ok(dir),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(eyre!(
+                        "Failed to create \"{}\": {e}",
+                        dir.display()
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Accept connections from each of `listeners` until `shutdown`.
+    async fn accept(
+        listeners: Vec<Listener>,
+        options: Arc<Options>,
+        shutdown: Arc<Notify>,
+    ) -> eyre::Result<()> {
+        for listener in listeners {
+            let options = options.clone();
+            match listener {
+                Listener::Tcp(listener) => {
+                    listener.set_nonblocking(true)?;
+                    let listener = TcpListener::from_std(listener)?;
+                    tokio::spawn(async move {
+                        loop {
+                            match listener.accept().await {
+                                Ok((stream, client)) => {
+                                    let stream = WriteTimeout::new(
+                                        stream,
+                                        options.timeouts.write,
+                                    );
+                                    tokio::spawn(handshake(
+                                        stream,
+                                        Some(client.ip()),
+                                        options.clone(),
+                                    ));
+                                }
+                                Err(e) => warn!("Failed to accept: {e}"),
+                            }
+                        }
+                    });
+                }
+                Listener::Unix(listener) => {
+                    listener.set_nonblocking(true)?;
+                    let listener = UnixListener::from_std(listener)?;
+                    tokio::spawn(async move {
+                        loop {
+                            match listener.accept().await {
+                                Ok((stream, _)) => {
+                                    let stream = WriteTimeout::new(
+                                        stream,
+                                        options.timeouts.write,
+                                    );
+                                    tokio::spawn(handshake(
+                                        stream,
+                                        None,
+                                        options.clone(),
+                                    ));
+                                }
+                                Err(e) => warn!("Failed to accept: {e}"),
+                            }
+                        }
+                    });
+                }
+            }
+        }
+        shutdown.notified().await;
+        Ok(())
+    }
+
+    /// The TLS settings for `tls`, offering HTTP/2.
+    fn acceptor(tls: &Tls) -> eyre::Result<TlsAcceptor> {
+        let certs = CertificateDer::pem_file_iter(&tls.cert)
+            .and_then(|x| x.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| eyre!("Invalid \"{}\": {e}", tls.cert.display()))?;
+        let key = PrivateKeyDer::from_pem_file(&tls.key)
+            .map_err(|e| eyre!("Invalid \"{}\": {e}", tls.key.display()))?;
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+
+    /// Serve a connection from `client`, once its TLS handshake is done if
+    /// it's HTTPS.
+    async fn handshake<S>(
+        stream: S,
+        client: Option<IpAddr>,
+        options: Arc<Options>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let Some(tls) = &options.tls else {
+            connection(stream, client, false, options).await;
+            return;
+        };
+        let accepted =
+            tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await;
+        match accepted {
+            Ok(Ok(stream)) => connection(stream, client, true, options).await,
+            Ok(Err(e)) => debug!("TLS handshake failed: {e}"),
+            Err(_) => debug!("TLS handshake timed out"),
+        }
+    }
+
+    /// Serve the requests of a connection from `client`, over TLS if
+    /// `secure`.
+    async fn connection<S>(
+        stream: S,
+        client: Option<IpAddr>,
+        secure: bool,
+        options: Arc<Options>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let timeouts = options.timeouts;
+        let service = hyper::service::service_fn(move |rq| {
+            let options = options.clone();
+            async move {
+                let response = forward(rq, client, secure, &options).await;
+                Ok::<_, Infallible>(response)
+            }
+        });
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        // Idle and slow clients are dropped: HTTP/1.1 ones that take too long
+        // to send a request's headers, including between requests, and
+        // HTTP/2 ones that don't answer pings in time.
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(timeouts.read);
+        if let Some(read) = timeouts.read {
+            builder
+                .http2()
+                .timer(TokioTimer::new())
+                .keep_alive_interval(read)
+                .keep_alive_timeout(read);
+        }
+        if let Err(e) = builder
+            .serve_connection(TokioIo::new(stream), service)
+            .await
+        {
+            debug!("Connection closed: {e}");
+        }
+    }
+
+    /// Pass `rq` on to tiny_http, once it's been read in full.
+    async fn forward(
+        rq: Request<Incoming>,
+        client: Option<IpAddr>,
+        secure: bool,
+        options: &Options,
+    ) -> Response<ResponseBody> {
+        let (mut parts, body) = rq.into_parts();
+        let body = match Limited::new(body, MAX_BODY).collect().await {
+            Ok(x) => x.to_bytes(),
+            Err(e) if e.is::<LengthLimitError>() => {
+                debug!("Rejected a request body over {MAX_BODY} bytes");
+                return status(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            Err(e) => {
+                debug!("Failed to read a request's body: {e}");
+                return status(StatusCode::BAD_REQUEST);
+            }
+        };
+
+        // HTTP/2 has the host in the URI rather than a header, and
+        // tiny_http only speaks HTTP/1.1.
+        let host = parts.uri.authority().map(|x| x.to_string()).or_else(|| {
+            let host = parts.headers.get(header::HOST)?;
+            host.to_str().ok().map(str::to_string)
+        });
+        remove_hop_by_hop(&mut parts.headers);
+        let forwarded = [
+            ("x-forwarded-for", client.map(|x| x.to_string())),
+            ("x-forwarded-host", host.clone()),
+            (
+                "x-forwarded-proto",
+                Some(if secure { "https" } else { "http" }.to_string()),
+            ),
+        ];
+        for (name, value) in forwarded {
+            let name = HeaderName::from_static(name);
+            if options.trust_proxy && parts.headers.contains_key(&name) {
+                continue;
+            }
+            parts.headers.remove(&name);
+            if let Some(value) =
+                value.and_then(|x| HeaderValue::from_str(&x).ok())
+            {
+                parts.headers.insert(name, value);
+            }
+        }
+        if let Some(host) = host.and_then(|x| HeaderValue::from_str(&x).ok()) {
+            parts.headers.insert(header::HOST, host);
+        }
+        parts.version = Version::HTTP_11;
+        let path = parts.uri.path_and_query().map(|x| x.as_str().parse());
+        parts.uri = path.and_then(Result::ok).unwrap_or_default();
+        let rq = Request::from_parts(parts, Full::new(body));
+
+        match send(rq, &options.backend).await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to pass a request on: {e}");
+                status(StatusCode::BAD_GATEWAY)
+            }
+        }
+    }
+
+    /// Send `rq` to tiny_http, listening on `backend`, and start reading the
+    /// response.
+    async fn send(
+        rq: Request<Full<Bytes>>,
+        backend: &Path,
+    ) -> eyre::Result<Response<ResponseBody>> {
+        let stream = UnixStream::connect(backend).await?;
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("Connection to tiny_http closed: {e}");
+            }
+        });
+        let response = sender.send_request(rq).await?;
+
+        let (mut parts, mut body) = response.into_parts();
+        remove_hop_by_hop(&mut parts.headers);
+        let (tx, rx) = mpsc::channel(READ_AHEAD);
+        tokio::spawn(async move {
+            while let Some(frame) = body.frame().await {
+                if tx.send(frame).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::from_parts(parts, ReadAhead(rx).boxed()))
+    }
+
+    fn remove_hop_by_hop(headers: &mut HeaderMap) {
+        // `Connection` can name more of them.
+        let named = headers
+            .get_all(header::CONNECTION)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(','))
+            .filter_map(|x| HeaderName::from_bytes(x.trim().as_bytes()).ok())
+            .collect::<Vec<_>>();
+        for name in named {
+            headers.remove(name);
+        }
+        for name in HOP_BY_HOP {
+            headers.remove(name);
+        }
+    }
+
+    fn status(status: StatusCode) -> Response<ResponseBody> {
+        let mut response = Response::new(
+            Full::new(Bytes::new())
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        *response.status_mut() = status;
+        response
+    }
+
+    /// A connection whose writes fail once the client hasn't taken any of
+    /// the response for `timeout`.
+    struct WriteTimeout<S> {
+        inner: S,
+        timeout: Option<Duration>,
+        /// When the write that's waiting for the client gives up.
+        sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+    }
+
+    impl<S> WriteTimeout<S> {
+        fn new(inner: S, timeout: Option<Duration>) -> Self {
+            Self {
+                inner,
+                timeout,
+                sleep: None,
+            }
+        }
+
+        /// Wait for the client, or fail if it's been too long.
+        fn poll_waiting(&mut self, cx: &mut Context<'_>) -> Poll<io::Error> {
+            let Some(timeout) = self.timeout else {
+                return Poll::Pending;
+            };
+            let sleep = self
+                .sleep
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+            ready!(sleep.as_mut().poll(cx));
+            Poll::Ready(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the client stopped reading",
+            ))
+        }
+
+        /// Call `write`, and wait for the client if it can't take more.
+        fn poll_write_with<T, F>(
+            &mut self,
+            cx: &mut Context<'_>,
+            write: F,
+        ) -> Poll<io::Result<T>>
+        where
+            S: Unpin,
+            F: FnOnce(Pin<&mut S>, &mut Context<'_>) -> Poll<io::Result<T>>,
+        {
+            match write(Pin::new(&mut self.inner), cx) {
+                Poll::Ready(x) => {
+                    self.sleep = None;
+                    Poll::Ready(x)
+                }
+                Poll::Pending => self.poll_waiting(cx).map(Err),
+            }
+        }
+    }
+
+    impl<S: AsyncRead + Unpin> AsyncRead for WriteTimeout<S> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for WriteTimeout<S> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.poll_write_with(cx, |x, cx| x.poll_write(cx, buf))
+        }
+
+        fn poll_write_vectored(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            bufs: &[io::IoSlice<'_>],
+        ) -> Poll<io::Result<usize>> {
+            self.poll_write_with(cx, |x, cx| x.poll_write_vectored(cx, bufs))
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            self.inner.is_write_vectored()
+        }
+
+        fn poll_flush(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            self.poll_write_with(cx, |x, cx| x.poll_flush(cx))
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            self.poll_write_with(cx, |x, cx| x.poll_shutdown(cx))
+        }
+    }
+
+    /// A response body that's been read ahead by a task.
+    struct ReadAhead(mpsc::Receiver<Result<Frame<Bytes>, hyper::Error>>);
+
+    impl Body for ReadAhead {
+        type Data = Bytes;
+        type Error = hyper::Error;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+            self.0.poll_recv(cx)
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Work that a request starts but shouldn't wait for, like fetching another
+//! site, done one job at a time on a thread of its own so that it doesn't
+//! hold up the serve threads.
+
+use log::error;
+use std::panic::AssertUnwindSafe;
+use std::sync::LazyLock;
+use std::sync::mpsc::SyncSender;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// How many jobs can wait, more are turned away.
+const CAPACITY: usize = 64;
+
+static QUEUE: LazyLock<SyncSender<Job>> = LazyLock::new(|| {
+    let (queue, jobs) = std::sync::mpsc::sync_channel::<Job>(CAPACITY);
+    std::thread::spawn(move || {
+        for job in jobs {
+            // One failing job shouldn't stop the rest.
+            if std::panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                error!("A background job panicked");
+            }
+        }
+    });
+    queue
+});
+
+/// Queue `job`, returning `false` if too many are already waiting.
+pub fn submit(job: impl FnOnce() + Send + 'static) -> bool {
+    QUEUE.try_send(Box::new(job)).is_ok()
+}
@@ -0,0 +1,308 @@
+//! Serving the site under a path of a larger one, e.g. `/blog`, behind a
+//! reverse proxy.
+//!
+//! Requests are routed with the base path taken off, if the proxy hasn't
+//! already, and it's put back on the root-relative URLs of HTML and CSS
+//! responses and redirects. Absolute URLs made from the request's origin
+//! have it too, `base_url` should include it. Those responses aren't served
+//! in ranges, which wouldn't line up with the rewritten body, and their ETags
+//! cover the base path.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io::{Cursor, Read};
+use tiny_http::Response;
+use url::Url;
+
+thread_local! {
+    /// The base path of the server the request being handled by this thread
+    /// is for, set when it's received like the security headers.
+    static CURRENT: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Attributes with URLs in them.
+const ATTRIBUTES: [&str; 5] = ["action", "href", "poster", "src", "srcset"];
+
+/// `path` as a base path, e.g. `/blog` for `blog/`, or `""` for none.
+pub fn normalize(path: Option<&str>) -> String {
+    let path = path.unwrap_or_default().trim_matches('/');
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("/{path}")
+    }
+}
+
+/// Use `base` for the responses to the request this thread is handling.
+pub fn begin(base: &str) {
+    CURRENT.with_borrow_mut(|x| base.clone_into(x));
+}
+
+/// The base path of the request this thread is handling, e.g. `/blog`, or
+/// `""` if there isn't one.
+pub fn get() -> String {
+    CURRENT.with_borrow(Clone::clone)
+}
+
+/// Whether the bodies of `content_type` responses get the base path.
+pub fn rewrites(content_type: &str) -> bool {
+    !get().is_empty()
+        && (content_type.starts_with("text/html")
+            || content_type.starts_with("text/css"))
+}
+
+/// `etag` for a body that gets the base path, which changes with it.
+pub fn etag(etag: &str) -> String {
+    let base = crate::http::etag(get().as_bytes());
+    format!(
+        "{}-{}",
+        etag.trim_end_matches('"'),
+        base.trim_start_matches('"')
+    )
+}
+
+/// `url`, the path and query of a request, without the base path `base`.
+pub fn strip<'a>(url: &'a str, base: &str) -> Cow<'a, str> {
+    match url.strip_prefix(base) {
+        Some(rest) if base.is_empty() || rest.starts_with('/') => rest.into(),
+        Some(rest) if rest.is_empty() || rest.starts_with('?') => {
+            format!("/{rest}").into()
+        }
+        _ => url.into(),
+    }
+}
+
+/// The origin of `url` with the base path `base`, e.g.
+/// `https://example.com/blog`.
+pub fn origin(url: &Url, base: &str) -> String {
+    format!("{}{base}", url.origin().ascii_serialization())
+}
+
+/// A response's body, with the base path put into it or as it was.
+pub enum Body<R> {
+    Original(R),
+    Prefixed(Cursor<Vec<u8>>),
+}
+
+impl<R: Read> Read for Body<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Original(x) => x.read(buf),
+            Self::Prefixed(x) => x.read(buf),
+        }
+    }
+}
+
+/// Put the base path into the root-relative URLs of `response`.
+pub fn apply<R: Read>(response: Response<R>) -> Response<Body<R>> {
+    let base = get();
+    let status = response.status_code();
+    let length = response.data_length();
+    let threshold = response.chunked_threshold();
+    let mut headers = response.headers().to_vec();
+    if base.is_empty() {
+        let body = Body::Original(response.into_reader());
+        return Response::new(status, headers, body, length, None)
+            .with_chunked_threshold(threshold);
+    }
+
+    for header in &mut headers {
+        if header.field.equiv("Location")
+            && is_root_relative(header.value.as_str())
+        {
+            let location = format!("{base}{}", header.value);
+            *header = crate::http::make_header("Location", &location);
+        }
+    }
+    let content_type = headers
+        .iter()
+        .find(|x| x.field.equiv("Content-Type"))
+        .map(|x| x.value.to_string())
+        .unwrap_or_default();
+    let rewrite: Option<fn(&str, &str) -> String> = match &content_type {
+        x if !rewrites(x) => None,
+        x if x.starts_with("text/html") => Some(prefix_html),
+        _ => Some(prefix_css),
+    };
+    let mut reader = response.into_reader();
+    let Some(rewrite) = rewrite else {
+        return Response::new(
+            status,
+            headers,
+            Body::Original(reader),
+            length,
+            None,
+        )
+        .with_chunked_threshold(threshold);
+    };
+
+    let mut data = vec![];
+    if let Err(e) = reader.read_to_end(&mut data) {
+        log::error!("Failed to read a {content_type} response: {e}");
+    }
+    let data = match String::from_utf8(data) {
+        Ok(x) => rewrite(&x, &base).into_bytes(),
+        Err(e) => e.into_bytes(),
+    };
+    let length = data.len();
+    Response::new(
+        status,
+        headers,
+        Body::Prefixed(Cursor::new(data)),
+        Some(length),
+        None,
+    )
+    .with_chunked_threshold(threshold)
+}
+
+/// Whether `url` starts with a single `/`.
+fn is_root_relative(url: &str) -> bool {
+    url.starts_with('/') && !url.starts_with("//")
+}
+
+/// Put `base` before the root-relative URLs in the attributes of the tags in
+/// `html`, leaving its text and comments alone.
+fn prefix_html(html: &str, base: &str) -> String {
+    let mut prefixed = String::with_capacity(html.len() + 1024);
+    let mut rest = html;
+    while let Some(i) = rest.find('<') {
+        prefixed.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let (tag, after) = rest.split_at(tag_len(rest));
+            prefixed.push_str(&prefix_tag(tag, base));
+            rest = after;
+        } else {
+            let len = if rest.starts_with("<!--") {
+                rest.find("-->").map_or(rest.len(), |x| x + 3)
+            } else {
+                1
+            };
+            prefixed.push_str(&rest[..len]);
+            rest = &rest[len..];
+        }
+    }
+    prefixed.push_str(rest);
+    prefixed
+}
+
+/// The length of the tag at the start of `html`, up to its `>`, which can't
+/// be in a quoted value.
+fn tag_len(html: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in html.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if matches!(c, '"' | '\'') => quote = Some(c),
+            None if c == '>' => return i + 1,
+            None => {}
+        }
+    }
+    html.len()
+}
+
+/// Put `base` before the root-relative URLs in the attributes of `tag`.
+fn prefix_tag(tag: &str, base: &str) -> String {
+    let mut prefixed = String::with_capacity(tag.len() + base.len());
+    let mut rest = tag;
+    while let Some(i) = rest.find('=') {
+        let (before, after) = rest.split_at(i + 1);
+        prefixed.push_str(before);
+        rest = after;
+
+        let name_start = before[..i]
+            .trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '-')
+            .len();
+        let name = before[name_start..i].to_ascii_lowercase();
+        let follows_space =
+            before[..name_start].ends_with(|c: char| c.is_ascii_whitespace());
+        let Some(quote) =
+            rest.chars().next().filter(|x| matches!(x, '"' | '\''))
+        else {
+            continue;
+        };
+        let value = &rest[1..];
+        let end = value.find(quote).unwrap_or(value.len());
+        let (value, after) = value.split_at(end);
+        prefixed.push(quote);
+        if !follows_space || !ATTRIBUTES.contains(&name.as_str()) {
+            // Other attributes' values are skipped whole.
+            prefixed.push_str(value);
+        } else if name == "srcset" {
+            let candidates = value.split(',').map(|x| {
+                let x = x.trim_start();
+                if is_root_relative(x) {
+                    format!("{base}{x}")
+                } else {
+                    x.to_string()
+                }
+            });
+            prefixed.push_str(&candidates.collect::<Vec<_>>().join(", "));
+        } else {
+            if is_root_relative(value) {
+                prefixed.push_str(base);
+            }
+            prefixed.push_str(value);
+        }
+        rest = after;
+    }
+    prefixed.push_str(rest);
+    prefixed
+}
+
+/// Put `base` before the root-relative URLs in the `url()`s of `css`.
+fn prefix_css(css: &str, base: &str) -> String {
+    let mut prefixed = String::with_capacity(css.len() + 1024);
+    let mut rest = css;
+    while let Some(i) = rest.find("url(") {
+        let (before, after) = rest.split_at(i + 4);
+        prefixed.push_str(before);
+        let quote = after.find(|c: char| c != '"' && c != '\'').unwrap_or(0);
+        prefixed.push_str(&after[..quote]);
+        rest = &after[quote..];
+        if is_root_relative(rest) {
+            prefixed.push_str(base);
+        }
+    }
+    prefixed.push_str(rest);
+    prefixed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stripped() {
+        assert_eq!(strip("/blog/post?x=1", "/blog"), "/post?x=1");
+        assert_eq!(strip("/blog", "/blog"), "/");
+        assert_eq!(strip("/blog?x=1", "/blog"), "/?x=1");
+        assert_eq!(strip("/blogs/post", "/blog"), "/blogs/post");
+        assert_eq!(strip("/post", ""), "/post");
+        assert_eq!(normalize(Some("blog/")), "/blog");
+        assert_eq!(normalize(Some("/")), "");
+    }
+
+    #[test]
+    fn prefixed_tags() {
+        let html = "<a href=\"/post\" title='x href=\"/no\"'>\
+                    <img srcset=\"/a.png 1x, /b.png 2x\" src=//cdn/x>";
+        assert_eq!(
+            prefix_html(html, "/blog"),
+            "<a href=\"/blog/post\" title='x href=\"/no\"'>\
+             <img srcset=\"/blog/a.png 1x, /blog/b.png 2x\" src=//cdn/x>"
+        );
+    }
+
+    #[test]
+    fn text_is_left_alone() {
+        for html in [
+            "<p>Write <code>&lt;a href=\"/x\"&gt;</code> or href=\"/x\".</p>",
+            "<!-- <a href=\"/x\"> -->",
+            "a < b and c <= d, href=\"/x\"",
+        ] {
+            assert_eq!(prefix_html(html, "/blog"), html);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Exporting the site as a tree of static files.
+
+use crate::{
+    ErrorTemplate, IndexEntry, IndexTemplate, Rendered, State, TagsTemplate,
+};
+use eyre::eyre;
+use log::info;
+use std::path::Path;
+
+/// Render every section index and indexed document in `state` into `output`,
+/// along with the embedded assets and styles and the user's assets.
+///
+/// Documents are written to `<url>/index.html` (e.g. `blog/post/index.html`)
+/// so that the `/blog/post` links in the indexes keep working on static hosts.
+pub fn build(
+    state: &State,
+    content_path: &Path,
+    output: &Path,
+) -> eyre::Result<()> {
+    std::fs::create_dir_all(output)?;
+
+    for section in state.sections.iter().map(|x| &x.name) {
+        let name = (!section.is_empty()).then_some(section.as_str());
+        for page in 1..=IndexTemplate::pages(state, name) {
+            let url = crate::index_url(section, page);
+            let html = IndexTemplate::index(state, name, page)
+                .ok_or_else(|| eyre!("Failed to render \"{url}\""))?;
+            write(&output.join(&url[1..]), html.as_bytes())?;
+        }
+
+        // Feeds need absolute links, which we can only make with a base URL.
+        if let Some(base_url) = &state.config.base_url
+            && let Some(feed) = crate::feed::atom(state, base_url, section)
+        {
+            write(&output.join(section).join("feed.xml"), feed.as_bytes())?;
+        }
+        if let Some(base_url) = &state.config.base_url
+            && let Some(feed) = crate::feed::json(state, base_url, section)
+        {
+            write(&output.join(section).join("feed.json"), feed.as_bytes())?;
+        }
+    }
+
+    if !state.tags.is_empty() {
+        let html = TagsTemplate::tags(state)
+            .ok_or_else(|| eyre!("Failed to render the tags"))?;
+        write(&output.join("tags/index.html"), html.as_bytes())?;
+    }
+    for (tag, _) in &state.tags {
+        if let Some(html) = IndexTemplate::tag(state, tag) {
+            write(
+                &output.join("tags").join(tag).join("index.html"),
+                html.as_bytes(),
+            )?;
+        }
+    }
+
+    let graph = crate::graph_json(state);
+    write(&output.join(".graph.json"), graph.as_bytes())?;
+
+    // Most static hosts serve this for missing pages.
+    let html = ErrorTemplate::error(state, 404);
+    write(&output.join("404.html"), html.as_bytes())?;
+
+    // As with feeds, the sitemap needs absolute links.
+    if let Some(base_url) = &state.config.base_url {
+        let sitemap = crate::sitemap::sitemap(state, base_url)
+            .ok_or_else(|| eyre!("Failed to render the sitemap"))?;
+        write(&output.join("sitemap.xml"), sitemap.as_bytes())?;
+        let robots = crate::sitemap::robots(state, base_url);
+        write(&output.join("robots.txt"), robots.as_bytes())?;
+    }
+
+    for entry in &state.index {
+        let html = render(state, content_path, entry)?;
+        write(
+            &output.join(&entry.url[1..]).join("index.html"),
+            html.as_bytes(),
+        )?;
+        if state.config.og.enabled
+            && entry.meta.cover.is_none()
+            && let Some(png) = crate::og::png(
+                &state.config,
+                &state.templates,
+                content_path,
+                entry,
+            )
+        {
+            write(&output.join(&crate::og::url(&entry.url)[1..]), &png)?;
+        }
+    }
+
+    // Pages link to the fingerprinted names, the plain ones are kept for
+    // anything else that links to them.
+    for (path, file) in crate::fingerprint::all() {
+        write(&output.join(&path[1..]), &file.contents)?;
+        write(&output.join(&file.url[1..]), &file.contents)?;
+    }
+    for path in [crate::HIGHLIGHT_CSS, &state.highlight_url] {
+        write(&output.join(&path[1..]), state.highlight_css.as_bytes())?;
+    }
+    if let Some(assets) = crate::assets_dir(content_path) {
+        copy_dir(&assets, &output.join("assets"))?;
+    }
+    // Rendering the documents made the variants of the images they show.
+    for (path, contents) in crate::images::variants(content_path) {
+        write(&output.join(path), &contents)?;
+    }
+
+    info!(
+        "Built {} documents and {} indexes into \"{}\"",
+        state.index.len(),
+        state.sections.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn render(
+    state: &State,
+    content_path: &Path,
+    entry: &IndexEntry,
+) -> eyre::Result<String> {
+    let contents = crate::read_document(&content_path.join(&entry.path))?;
+    let path = Path::new(&entry.path);
+    if crate::html_pages::is_html(path) && !entry.meta.wrap {
+        return Ok(contents);
+    }
+    let url = state
+        .config
+        .base_url
+        .as_deref()
+        .map(|x| format!("{}{}", x.trim_end_matches('/'), entry.url));
+    let Rendered { html, .. } = crate::renderer(path)(
+        &crate::HeaderTemplate::new(state),
+        &contents,
+        url.as_deref(),
+        Some(&crate::Links {
+            from: &entry.path,
+            index: &state.index,
+        }),
+        entry.git.as_ref(),
+    );
+    html.ok_or_else(|| eyre!("Failed to render \"{}\"", entry.path))
+}
+
+/// Copy the files in `from` into `to`, skipping dotfiles as the server does.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_name().as_encoded_bytes().starts_with(b".") {
+            continue;
+        }
+        let path = entry.path();
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&path, &target)?;
+        } else {
+            write(&target, &std::fs::read(&path)?)?;
+        }
+    }
+    Ok(())
+}
+
+fn write(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    log::trace!("Writing \"{}\"", path.display());
+    std::fs::write(path, contents)
+}
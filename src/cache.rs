@@ -0,0 +1,90 @@
+//! A size-bounded cache of rendered documents.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Rendered pages keyed by URL, evicting the least recently used pages once
+/// they take up more than `capacity` bytes. A cached page is only used while
+/// the file's modification time matches the one it was rendered from.
+#[derive(Debug)]
+pub struct RenderCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    size: usize,
+    /// Incremented on every access, entries remember the value of their last
+    /// use.
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    modified: SystemTime,
+    html: Arc<str>,
+    last_used: u64,
+}
+
+impl RenderCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Get the page at `url` as it was rendered at `modified`.
+    pub fn get(&self, url: &str, modified: SystemTime) -> Option<Arc<str>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let entry = inner.entries.get_mut(url)?;
+        if entry.modified != modified {
+            return None;
+        }
+        entry.last_used = clock;
+        Some(entry.html.clone())
+    }
+
+    /// Forget every page, for when something they all show has changed.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.size = 0;
+    }
+
+    pub fn insert(&self, url: &str, modified: SystemTime, html: Arc<str>) {
+        if html.len() > self.capacity {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let entry = Entry {
+            modified,
+            html,
+            last_used: inner.clock,
+        };
+        inner.size += entry.html.len();
+        if let Some(old) = inner.entries.insert(url.to_string(), entry) {
+            inner.size -= old.html.len();
+        }
+
+        while inner.size > self.capacity {
+            let Some(lru) = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, x)| x.last_used)
+                .map(|(k, _)| k.clone())
+            else {
+                break;
+            };
+            let evicted = inner.entries.remove(&lru).unwrap();
+            inner.size -= evicted.html.len();
+            log::trace!("Evicted \"{lru}\" from the render cache");
+        }
+    }
+}
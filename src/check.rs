@@ -0,0 +1,173 @@
+//! Validating the content before it's published.
+
+use crate::{ASSETS, Links, Meta, STYLES, State, is_markdown, is_markup};
+use std::path::Path;
+
+/// Check every markdown file in `content_path` for metadata that's missing or
+/// invalid and for links and images that don't lead anywhere, printing each
+/// problem. Wiki links are only checked if `wiki_links` is set, since vaults
+/// tend to have links to pages that haven't been written yet. Fails if there
+/// were any problems.
+pub fn check(
+    state: &State,
+    content_path: &Path,
+    wiki_links: bool,
+) -> eyre::Result<()> {
+    let mut files = vec![];
+    crate::walk(content_path, &mut |is_dir, path| {
+        let hidden = path
+            .file_name()
+            .is_some_and(|x| x.as_encoded_bytes().starts_with(b"."));
+        if hidden || is_dir && path == content_path.join("assets") {
+            return Ok(false);
+        }
+        // The 404 page and section landings aren't documents, so they don't
+        // need metadata.
+        let not_found = path == content_path.join("404.md")
+            || crate::landing_section(content_path, path).is_some();
+        if !is_dir && is_markdown(path) && !not_found {
+            let path = path
+                .strip_prefix(content_path)
+                .expect("is a subdir of content path");
+            files.push(path.to_str().unwrap().to_string());
+        }
+        Ok(true)
+    })?;
+    let ignored = crate::gitignore::filter_ignored(content_path, &files);
+    files.retain(|x| !ignored.iter().any(|i| i == Path::new(x)));
+    files.sort();
+
+    let mut problems = 0;
+    for file in &files {
+        let contents = std::fs::read_to_string(content_path.join(file))?;
+        for problem in
+            check_document(state, content_path, file, &contents, wiki_links)
+        {
+            println!("{file}: {problem}");
+            problems += 1;
+        }
+    }
+
+    if problems > 0 {
+        return Err(eyre::eyre!(
+            "Found {problems} problems in {} documents",
+            files.len()
+        ));
+    }
+    println!("No problems found in {} documents", files.len());
+    Ok(())
+}
+
+fn check_document(
+    state: &State,
+    content_path: &Path,
+    path: &str,
+    contents: &str,
+    wiki_links: bool,
+) -> Vec<String> {
+    use pulldown_cmark::{
+        CodeBlockKind, Event, MetadataBlockKind, Parser, Tag, TagEnd,
+        TextMergeStream,
+    };
+
+    let options = state.config.markdown.options();
+
+    let links = Links {
+        from: path,
+        index: &state.index,
+    };
+    let mut problems = vec![];
+    let mut found_meta = false;
+    // Whether the text being parsed is metadata, and if so whether it's YAML.
+    let mut in_meta = None;
+    let mut in_code = false;
+    for event in TextMergeStream::new(Parser::new_ext(contents, options)) {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang)))
+                if lang.trim() == "meta" =>
+            {
+                in_meta = Some(false);
+            }
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::Start(Tag::MetadataBlock(kind)) => {
+                in_meta = Some(kind == MetadataBlockKind::YamlStyle);
+            }
+            Event::End(TagEnd::CodeBlock | TagEnd::MetadataBlock(_)) => {
+                in_meta = None;
+                in_code = false;
+            }
+            Event::Text(text) => {
+                let Some(yaml) = in_meta else {
+                    if wiki_links && !in_code {
+                        for link in crate::wiki_links(&text) {
+                            if links.wiki_url(link.target).is_none() {
+                                problems.push(format!(
+                                    "wiki link to \"{}\" doesn't resolve",
+                                    link.target
+                                ));
+                            }
+                        }
+                    }
+                    continue;
+                };
+                found_meta = true;
+                if let Err(e) = Meta::parse(&text, yaml) {
+                    problems.push(format!("invalid metadata: {}", e.trim()));
+                }
+            }
+            Event::Start(Tag::Link { dest_url, .. })
+                if !resolves(state, content_path, &links, &dest_url) =>
+            {
+                problems
+                    .push(format!("link to \"{dest_url}\" doesn't resolve"));
+            }
+            Event::Start(Tag::Image { dest_url, .. })
+                if !resolves(state, content_path, &links, &dest_url) =>
+            {
+                problems.push(format!("image \"{dest_url}\" doesn't resolve"));
+            }
+            _ => {}
+        }
+    }
+    if !found_meta {
+        problems.push("no metadata, so it isn't indexed".to_string());
+    }
+    problems
+}
+
+/// Whether a link to `dest` leads to something the site serves.
+fn resolves(
+    state: &State,
+    content_path: &Path,
+    links: &Links,
+    dest: &str,
+) -> bool {
+    let Some((path, _)) = links.local_path(dest) else {
+        // Links to other sites can't be checked without fetching them.
+        return true;
+    };
+    if is_markup(&path) {
+        return links.document(&path).is_some();
+    }
+    let Some(path) = path.to_str() else {
+        return false;
+    };
+
+    if let Some(asset) = path.strip_prefix("assets/") {
+        return [".assets", "assets"]
+            .iter()
+            .any(|x| content_path.join(x).join(asset).is_file());
+    }
+    if let Some(asset) = path.strip_prefix(".static-assets/") {
+        return ASSETS.get_file(asset).is_some();
+    }
+    if let Some(style) = path.strip_prefix(".styles/") {
+        return style == "highlight.css" || STYLES.get_file(style).is_some();
+    }
+    // Pages that are generated rather than read from a file.
+    let generated =
+        matches!(path, "" | "search" | "sitemap.xml" | "robots.txt")
+            || path.ends_with("feed.xml")
+            || path.ends_with("index.html");
+    generated || state.index.iter().any(|x| x.url[1..] == *path)
+}
@@ -0,0 +1,40 @@
+//! Emoji shortcodes, e.g. `:tada:`, as GitHub and chat apps write them.
+//!
+//! Only text is expanded, so code spans and blocks keep their colons.
+
+use std::borrow::Cow;
+
+/// `text` with the shortcodes of known emoji replaced by the emoji. Anything
+/// else between colons is left alone.
+pub fn expand(text: &str) -> Cow<'_, str> {
+    if !text.contains(':') {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let name_len = after
+            .find(|c: char| {
+                !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+            })
+            .unwrap_or(after.len());
+        let name = &after[..name_len];
+        let closed = after[name_len..].starts_with(':');
+        match emojis::get_by_shortcode(name).filter(|_| closed) {
+            Some(emoji) => {
+                out.push_str(emoji.as_str());
+                rest = &after[name_len + 1..];
+            }
+            None => {
+                // The closing colon might start the next shortcode, e.g. in
+                // `12:30:tada:`.
+                out.push(':');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
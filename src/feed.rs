@@ -0,0 +1,357 @@
+//! Atom and JSON feeds of the indexed documents.
+//!
+//! With a WebSub hub configured, the feeds advertise it and it's pinged
+//! when a reload changes them, so that subscribers don't have to poll.
+
+use crate::{IndexEntry, Rendered, State, http};
+use log::{error, info, warn};
+use rinja::Template;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::Duration;
+use tiny_http::Header;
+
+#[derive(Template)]
+#[template(ext = "xml", path = "feed.xml")]
+struct FeedTemplate<'a> {
+    title: &'a str,
+    author: &'a str,
+    author_url: Option<&'a str>,
+    base: &'a str,
+    self_path: &'a str,
+    index_path: &'a str,
+    /// The WebSub hub.
+    hub: Option<&'a str>,
+    updated: chrono::NaiveDate,
+    docs: &'a [&'a IndexEntry],
+}
+
+mod filters {
+    /// Percent-encode a URL path.
+    pub fn encode_path(path: &str) -> rinja::Result<String> {
+        Ok(crate::http::encode_path(path))
+    }
+}
+
+/// A [JSON Feed](https://jsonfeed.org/version/1.1).
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    home_page_url: String,
+    feed_url: String,
+    authors: [Author<'a>; 1],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hubs: Vec<Hub<'a>>,
+    items: Vec<Item<'a>>,
+}
+
+#[derive(Serialize)]
+struct Hub<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    url: &'a str,
+}
+
+#[derive(Serialize)]
+struct Author<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Item<'a> {
+    id: String,
+    url: String,
+    title: &'a str,
+    content_html: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_published: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_modified: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<Author<'a>>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tags: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'a str>,
+}
+
+/// What the feeds of a section have in common.
+struct Feed<'a> {
+    title: String,
+    author: &'a str,
+    author_url: Option<&'a str>,
+    base: &'a str,
+    /// Where the section's feeds and index are, e.g. `/blog/`.
+    dir: String,
+    docs: Vec<&'a IndexEntry>,
+}
+
+impl<'a> Feed<'a> {
+    /// The feed of `section` (the whole site if it's empty), or `None` if
+    /// there's no such section. `origin` is the scheme and host of the
+    /// request (e.g. `https://example.com`) which links are made absolute
+    /// with, unless the site has a configured base URL.
+    fn new(state: &'a State, origin: &'a str, section: &str) -> Option<Self> {
+        let section = state.section(section)?;
+
+        let path = if section.name.is_empty() {
+            "/feed.xml".to_string()
+        } else {
+            format!("/{}/feed.xml", section.name)
+        };
+        let docs = state
+            .index
+            .iter()
+            .filter(|x| {
+                if section.name.is_empty() {
+                    !state.is_hidden(&x.section)
+                } else {
+                    x.section == section.name
+                }
+            })
+            .filter(|x| state.is_listed_at(x, &path))
+            .collect::<Vec<_>>();
+        let dir = if section.name.is_empty() {
+            "/".to_string()
+        } else {
+            format!("/{}/", crate::http::encode_path(&section.name))
+        };
+        let base = state.config.base(origin);
+        let site_title = state.config.title.as_deref().unwrap_or_else(|| {
+            base.split_once("://").map_or(base, |(_, host)| host)
+        });
+        let title = if section.name.is_empty() {
+            site_title.to_string()
+        } else {
+            format!("{site_title}: {}", section.title())
+        };
+
+        Some(Self {
+            title,
+            author: state.config.author.as_deref().unwrap_or(site_title),
+            author_url: state
+                .config
+                .author
+                .as_ref()
+                .and(state.config.author_url.as_deref()),
+            base,
+            dir,
+            docs,
+        })
+    }
+}
+
+/// Render the Atom feed for `section`, like [`Feed::new`] finds it.
+pub fn atom(state: &State, origin: &str, section: &str) -> Option<String> {
+    let feed = Feed::new(state, origin, section)?;
+    let template = FeedTemplate {
+        title: &feed.title,
+        author: feed.author,
+        author_url: feed.author_url,
+        base: feed.base,
+        self_path: &format!("{}feed.xml", feed.dir),
+        index_path: &format!("{}index.html", feed.dir),
+        hub: state.config.websub_hub.as_deref(),
+        updated: feed
+            .docs
+            .iter()
+            .filter_map(|x| x.meta.last_updated())
+            .max()
+            .unwrap_or_default(),
+        docs: &feed.docs,
+    };
+    crate::render(&template, "feed.xml")
+}
+
+/// Render the JSON Feed for `section`, with each document's full HTML.
+pub fn json(state: &State, origin: &str, section: &str) -> Option<String> {
+    let feed = Feed::new(state, origin, section)?;
+    let base = feed.base;
+    let absolute = |url: &str| {
+        if url.starts_with('/') {
+            format!("{base}{}", crate::http::encode_path(url))
+        } else {
+            url.to_string()
+        }
+    };
+    let items = feed
+        .docs
+        .iter()
+        .map(|doc| Item {
+            id: absolute(&doc.url),
+            url: absolute(&doc.url),
+            title: &doc.meta.title,
+            content_html: content(state, doc),
+            summary: doc.meta.desc.as_deref(),
+            image: doc.meta.cover.as_deref().map(absolute),
+            date_published: doc.meta.date.map(|x| format!("{x}T00:00:00Z")),
+            date_modified: doc
+                .meta
+                .last_updated()
+                .map(|x| format!("{x}T00:00:00Z")),
+            authors: doc
+                .meta
+                .author
+                .as_deref()
+                .map(|name| Author {
+                    name,
+                    url: doc.meta.author_url.as_deref(),
+                })
+                .into_iter()
+                .collect(),
+            tags: &doc.meta.tags,
+            language: doc.meta.lang.as_deref(),
+        })
+        .collect();
+    let json = JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: &feed.title,
+        home_page_url: format!("{base}{}index.html", feed.dir),
+        feed_url: format!("{base}{}feed.json", feed.dir),
+        authors: [Author {
+            name: feed.author,
+            url: feed.author_url,
+        }],
+        language: state.config.language.as_deref(),
+        hubs: state
+            .config
+            .websub_hub
+            .as_deref()
+            .map(|url| Hub {
+                kind: "WebSub",
+                url,
+            })
+            .into_iter()
+            .collect(),
+        items,
+    };
+    Some(serde_json::to_string(&json).unwrap())
+}
+
+/// The HTML of `doc` without the page around it, which is cached like pages
+/// are.
+fn content(state: &State, doc: &IndexEntry) -> String {
+    let path = state.content_path.join(&doc.path);
+    let key = format!("feed:{}", doc.url);
+    let modified = std::fs::metadata(&path).and_then(|x| x.modified());
+    if let Ok(modified) = modified
+        && let Some(html) = state.cache.get(&key, modified)
+    {
+        return html.to_string();
+    }
+    let contents = match crate::read_document(&path) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Error getting \"{}\": {e}", path.display());
+            return String::new();
+        }
+    };
+    let Rendered { body, .. } = crate::renderer(Path::new(&doc.path))(
+        &crate::HeaderTemplate::new(state),
+        &contents,
+        None,
+        Some(&crate::Links {
+            from: &doc.path,
+            index: &state.index,
+        }),
+        doc.git.as_ref(),
+    );
+    if let Ok(modified) = modified {
+        state.cache.insert(&key, modified, body.as_str().into());
+    }
+    body
+}
+
+/// The `Link` header that advertises the WebSub hub in the response to the
+/// feed at `path`, if there's a hub.
+pub fn hub_header(state: &State, origin: &str, path: &str) -> Option<Header> {
+    let hub = state.config.websub_hub.as_deref()?;
+    let topic =
+        format!("{}{}", state.config.base(origin), http::encode_path(path));
+    Some(http::make_header(
+        "Link",
+        &format!("<{hub}>; rel=\"hub\", <{topic}>; rel=\"self\""),
+    ))
+}
+
+/// The feeds a reload changed, to ping the WebSub hub with.
+pub struct Ping {
+    hub: String,
+    feeds: Vec<String>,
+}
+
+impl Ping {
+    /// The feeds of the sections with documents that were added, changed or
+    /// removed since `previous`, `None` if there are none.
+    pub fn new(previous: &State, current: &State) -> Option<Self> {
+        let hub = current.config.websub_hub.clone()?;
+        let Some(base) = current.config.base_url.as_deref() else {
+            warn!("The WebSub hub can't be pinged without a base_url");
+            return None;
+        };
+        let modified = |state: &State, entry: &IndexEntry| {
+            let path = state.content_path.join(&entry.path);
+            state.parsed.get(&path).map(|x| x.modified)
+        };
+        let changed = current.index.iter().filter(|x| {
+            previous
+                .index
+                .iter()
+                .find(|y| y.url == x.url)
+                .is_none_or(|y| modified(previous, y) != modified(current, x))
+        });
+        let removed = previous
+            .index
+            .iter()
+            .filter(|x| !current.index.iter().any(|y| y.url == x.url));
+        let sections = changed
+            .chain(removed)
+            .map(|x| x.section.as_str())
+            .collect::<BTreeSet<_>>();
+        if sections.is_empty() {
+            return None;
+        }
+
+        let base = base.trim_end_matches('/');
+        // The site's feeds have every section's documents.
+        let feeds = std::iter::once("")
+            .chain(sections)
+            .map(|section| match section {
+                "" => format!("{base}/"),
+                _ => format!("{base}/{}/", http::encode_path(section)),
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .flat_map(|dir| {
+                [format!("{dir}feed.xml"), format!("{dir}feed.json")]
+            })
+            .collect();
+        Some(Self { hub, feeds })
+    }
+
+    /// Ping the hub, one feed at a time since not every hub takes several.
+    pub fn send(self) {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(10))
+            .build();
+        for feed in &self.feeds {
+            let result = agent
+                .post(&self.hub)
+                .send_form(&[("hub.mode", "publish"), ("hub.url", feed)]);
+            match result {
+                Ok(_) => info!("Pinged the WebSub hub about \"{feed}\""),
+                Err(e) => error!("Failed to ping the WebSub hub: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Fingerprinted URLs for the embedded files.
+//!
+//! Pages link to the stylesheets and fonts by names with a hash of their
+//! contents in them (e.g. `/.styles/fonts.<hash>.css`), so those URLs can be
+//! cached forever and a new binary's styles are fetched straight away. URLs
+//! inside the stylesheets are rewritten the same way.
+
+use crate::{ASSETS, STYLES};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// The embedded files as they're served, by their plain URL (e.g.
+/// `/.styles/fonts.css`).
+static EMBEDDED: LazyLock<HashMap<String, Embedded>> = LazyLock::new(embedded);
+
+/// How long clients may keep a fingerprinted file: it can't change without
+/// its URL changing.
+pub const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// An embedded file as it's served.
+#[derive(Debug)]
+pub struct Embedded {
+    pub contents: Cow<'static, [u8]>,
+    pub etag: String,
+    /// The fingerprinted URL.
+    pub url: String,
+}
+
+/// The file served at `path`, either its plain or its fingerprinted URL, and
+/// whether it was the fingerprinted one.
+pub fn lookup(path: &str) -> Option<(&'static Embedded, bool)> {
+    if let Some(file) = EMBEDDED.get(path) {
+        return Some((file, false));
+    }
+    let (plain, hash) = strip(path)?;
+    let file = EMBEDDED.get(&plain)?;
+    // An old hash names contents we don't have any more, so don't let those
+    // be cached forever.
+    Some((file, file.etag.trim_matches('"') == hash))
+}
+
+/// The fingerprinted URL of the embedded file at the plain URL `path`.
+pub fn url(path: &str) -> &'static str {
+    &EMBEDDED[path].url
+}
+
+/// Every embedded file, by its plain URL.
+pub fn all() -> impl Iterator<Item = (&'static str, &'static Embedded)> {
+    EMBEDDED.iter().map(|(path, file)| (path.as_str(), file))
+}
+
+/// The main stylesheet, which is inlined into every page.
+pub fn styles() -> &'static str {
+    let file = &EMBEDDED["/.styles/styles.css"];
+    std::str::from_utf8(&file.contents).expect("styles.css is UTF-8")
+}
+
+/// Put `hash` into the file name in `path`, before the extension.
+pub fn with_hash(path: &str, hash: &str) -> String {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    match name.split_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => {
+            format!("{dir}/{stem}.{hash}.{ext}")
+        }
+        _ => format!("{path}.{hash}"),
+    }
+}
+
+/// Split a fingerprinted `path` into its plain URL and hash.
+pub fn strip(path: &str) -> Option<(String, &str)> {
+    let (dir, name) = path.rsplit_once('/')?;
+    let mut parts = name.splitn(3, '.');
+    let stem = parts.next()?;
+    let hash = parts.next()?;
+    if hash.len() != 16 || !hash.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(match parts.next() {
+        Some(ext) => (format!("{dir}/{stem}.{ext}"), hash),
+        None => (format!("{dir}/{stem}"), hash),
+    })
+}
+
+fn embedded() -> HashMap<String, Embedded> {
+    let mut files = HashMap::new();
+    for (dir, prefix) in [(&ASSETS, "/.static-assets"), (&STYLES, "/.styles")] {
+        let mut dirs = vec![dir];
+        while let Some(dir) = dirs.pop() {
+            for entry in dir.entries() {
+                match entry {
+                    include_dir::DirEntry::Dir(d) => dirs.push(d),
+                    include_dir::DirEntry::File(f) => {
+                        let path =
+                            format!("{prefix}/{}", f.path().to_string_lossy());
+                        let file = Embedded::new(&path, f.contents().into());
+                        files.insert(path, file);
+                    }
+                }
+            }
+        }
+    }
+
+    // Stylesheets refer to each other (`styles.css` imports `fonts.css`), so
+    // rewriting one changes the hashes the others should use. Rewrite them
+    // until nothing changes; the chains are short.
+    let stylesheets = files
+        .keys()
+        .filter(|x| x.ends_with(".css"))
+        .cloned()
+        .collect::<Vec<_>>();
+    let originals = stylesheets
+        .iter()
+        .map(|x| String::from_utf8_lossy(&files[x].contents).into_owned())
+        .collect::<Vec<_>>();
+    loop {
+        let mut changed = false;
+        for (path, original) in stylesheets.iter().zip(&originals) {
+            let mut css = original.clone();
+            for (from, file) in &files {
+                if from != path {
+                    css = css.replace(from.as_str(), &file.url);
+                }
+            }
+            let file = Embedded::new(path, css.into_bytes().into());
+            if file.etag != files[path].etag {
+                files.insert(path.clone(), file);
+                changed = true;
+            }
+        }
+        if !changed {
+            break files;
+        }
+    }
+}
+
+impl Embedded {
+    fn new(path: &str, contents: Cow<'static, [u8]>) -> Self {
+        let etag = crate::http::etag(&contents);
+        let url = with_hash(path, etag.trim_matches('"'));
+        Self {
+            contents,
+            etag,
+            url,
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Matching paths against `.gitignore` files in-process, so that ignored
+//! files are left out whether or not git is installed or the content is in a
+//! repository.
+//!
+//! Rules are read like git reads them: from `.gitignore` in every directory
+//! from the repository's root down to the path, where deeper files win, then
+//! `.git/info/exclude` and the user's global excludes file. Outside of a
+//! repository only the `.gitignore` files from the content directory down
+//! are used. A `.siteignore` next to a `.gitignore` (or without one) has more
+//! rules in the same format, for files that are committed but shouldn't be
+//! published, and wins over it.
+
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::warn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The paths in `paths` (relative to `in_dir`) that are ignored.
+pub fn filter_ignored(
+    in_dir: &Path,
+    paths: &[impl AsRef<Path>],
+) -> Vec<PathBuf> {
+    let in_dir =
+        std::path::absolute(in_dir).unwrap_or_else(|_| in_dir.to_path_buf());
+    let mut rules = Rules::new(&in_dir);
+    let ignored = paths
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|x| rules.is_ignored(&in_dir.join(x)))
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    log::trace!("Ignored paths in \"{}\": {ignored:?}", in_dir.display());
+    ignored
+}
+
+struct Rules {
+    /// The root of the repository, or the content directory if it isn't in
+    /// one.
+    root: PathBuf,
+    /// `.git/info/exclude` and the global excludes, relative to `root`.
+    excludes: Vec<Gitignore>,
+    /// The rules of each directory's `.gitignore` and `.siteignore`, read as
+    /// they're needed.
+    dirs: HashMap<PathBuf, Gitignore>,
+}
+
+impl Rules {
+    fn new(in_dir: &Path) -> Self {
+        let repo = in_dir.ancestors().find(|x| x.join(".git").exists());
+        let root = repo.unwrap_or(in_dir).to_path_buf();
+        let mut excludes = vec![];
+        if repo.is_some() {
+            excludes.push(read(&root, &[root.join(".git/info/exclude")]));
+            let (global, e) = GitignoreBuilder::new(&root).build_global();
+            if let Some(e) = e {
+                warn!("Failed to read the global git excludes: {e}");
+            }
+            excludes.push(global);
+        }
+        Self {
+            root,
+            excludes,
+            dirs: HashMap::new(),
+        }
+    }
+
+    fn is_ignored(&mut self, path: &Path) -> bool {
+        let is_dir = path.is_dir();
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(&self.root) {
+                break;
+            }
+            let rules =
+                self.dirs.entry(dir.to_path_buf()).or_insert_with(|| {
+                    read(
+                        dir,
+                        &[dir.join(".gitignore"), dir.join(".siteignore")],
+                    )
+                });
+            // Relative paths, since these panic on paths outside of the
+            // rules' directory.
+            let relative = path.strip_prefix(dir).unwrap();
+            match rules.matched_path_or_any_parents(relative, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        for rules in &self.excludes {
+            match rules.matched_path_or_any_parents(relative, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+/// Read the rules in the ignore files at `paths`, which apply to paths under
+/// `dir`. Later files win, and missing ones have no rules.
+fn read(dir: &Path, paths: &[PathBuf]) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(dir);
+    for path in paths {
+        if path.is_file()
+            && let Some(e) = builder.add(path)
+        {
+            warn!("Invalid \"{}\": {e}", path.display());
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        warn!("Invalid ignore files in \"{}\": {e}", dir.display());
+        Gitignore::empty()
+    })
+}
@@ -0,0 +1,261 @@
+//! Syntax highlighting of code blocks.
+//!
+//! Code is marked up with CSS classes rather than inline styles so that the
+//! colors come from a stylesheet (`/.styles/highlight.css`), which can follow
+//! the reader's light or dark preference and be overridden by custom CSS.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::LazyLock;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+static SYNTAX_SET: LazyLock<SyntaxSet> =
+    LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEME_SET: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// Prefixed so that the classes don't collide with the site's own.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// The `[highlight]` table of `site.toml`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HighlightConfig {
+    /// The theme code is highlighted with, `base16-ocean.dark` by default.
+    pub theme: Option<String>,
+    /// The theme used instead when the reader prefers a dark color scheme.
+    pub dark_theme: Option<String>,
+}
+
+/// How a code block is shown, from the options after the language in its
+/// info string, e.g. ```` ```rust,linenos,hl_lines=3-5,title="main.rs" ````.
+#[derive(Debug, Default)]
+pub struct Fence {
+    /// Shown above the code, usually the name of the file it's from.
+    pub title: Option<String>,
+    /// Number the lines.
+    pub line_numbers: bool,
+    /// The lines to highlight (counting from 1), as inclusive ranges.
+    pub highlight_lines: Vec<(usize, usize)>,
+}
+
+impl Fence {
+    /// Split an info string into the language and the options.
+    pub fn parse(info: &str) -> (&str, Fence) {
+        let mut parts = split_options(info).into_iter();
+        let lang = parts.next().unwrap_or_default();
+        let mut fence = Fence::default();
+        for option in parts {
+            match option.split_once('=') {
+                None if option == "linenos" => fence.line_numbers = true,
+                Some(("title", title)) => {
+                    fence.title = Some(title.trim_matches('"').to_string());
+                }
+                Some(("hl_lines", lines)) => {
+                    // Ranges are separated by spaces, e.g. `hl_lines=1 3-5`.
+                    for range in lines.trim_matches('"').split_whitespace() {
+                        let (start, end) =
+                            range.split_once('-').unwrap_or((range, range));
+                        match (start.parse(), end.parse()) {
+                            (Ok(start), Ok(end)) => {
+                                fence.highlight_lines.push((start, end));
+                            }
+                            _ => log::warn!(
+                                "Invalid line range \"{range}\" in \"{info}\""
+                            ),
+                        }
+                    }
+                }
+                // Other tools' options, e.g. rustdoc's `ignore`.
+                _ => log::debug!(
+                    "Unknown code block option \"{option}\" in \"{info}\""
+                ),
+            }
+        }
+        (lang, fence)
+    }
+
+    /// Wrap a rendered code block in a figure with a caption bar, which has
+    /// the title and a button that the copy script shows.
+    fn caption(&self, pre: &str) -> String {
+        let title = self.title.as_deref().map_or(String::new(), |x| {
+            format!("<span class=\"title\">{}</span>", escape(x))
+        });
+        format!(
+            "<figure class=\"code\"><figcaption>{title}<button class=\"copy\" \
+             type=\"button\" hidden>Copy</button></figcaption>{pre}</figure>"
+        )
+    }
+
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlight_lines
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&line))
+    }
+}
+
+/// What the text file at `path` is highlighted as (its extension, or its name
+/// if it has none), if it's one that's shown as a page. Markup that browsers
+/// show themselves isn't.
+pub fn source_language(path: &Path) -> Option<&str> {
+    let token = path
+        .extension()
+        .or_else(|| path.file_name())
+        .and_then(|x| x.to_str())?;
+    let markup = ["html", "htm", "xhtml", "svg", "xml"];
+    if markup.iter().any(|x| token.eq_ignore_ascii_case(x)) {
+        return None;
+    }
+    SYNTAX_SET.find_syntax_by_token(token).map(|_| token)
+}
+
+/// Highlight `code` as the language named `lang` (e.g. `rust` or `rs`) into
+/// a `<pre>` block.
+pub fn html(code: &str, lang: &str, fence: &Fence) -> String {
+    let syntax = SYNTAX_SET
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(
+        syntax,
+        &SYNTAX_SET,
+        CLASS_STYLE,
+    );
+    let mut html = None;
+    for line in LinesWithEndings::from(code) {
+        if let Err(e) =
+            generator.parse_html_for_line_which_includes_newline(line)
+        {
+            log::error!("Failed to highlight code as \"{lang}\": {e}");
+            html = Some(escape(code));
+            break;
+        }
+    }
+    let html = html.unwrap_or_else(|| generator.finalize());
+    if !fence.line_numbers && fence.highlight_lines.is_empty() {
+        return fence.caption(&format!(
+            "<pre class=\"hl-code\"><code>{html}</code></pre>"
+        ));
+    }
+
+    let mut out = String::new();
+    for (i, line) in split_lines(&html).iter().enumerate() {
+        let n = i + 1;
+        out.push_str(if fence.is_highlighted(n) {
+            "<span class=\"line highlighted\">"
+        } else {
+            "<span class=\"line\">"
+        });
+        if fence.line_numbers {
+            out.push_str(&format!(
+                "<span class=\"line-number\" aria-hidden=\"true\">{n}</span>"
+            ));
+        }
+        out.push_str(line);
+        out.push_str("</span>\n");
+    }
+    let class = if fence.line_numbers {
+        "hl-code line-numbers"
+    } else {
+        "hl-code"
+    };
+    fence.caption(&format!("<pre class=\"{class}\"><code>{out}</code></pre>"))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Split an info string at its commas, except for those in quotes, e.g. in
+/// `title="a, b"`.
+fn split_options(info: &str) -> Vec<&str> {
+    let mut options = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in info.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                options.push(info[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    options.push(info[start..].trim());
+    options
+}
+
+/// Split highlighted HTML into its lines, closing the spans that are open at
+/// the end of each line and opening them again on the next, so that every
+/// line can be wrapped in an element of its own.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut open: Vec<&str> = vec![];
+    let mut line = String::new();
+    // Whether `line` has any text, rather than just reopened spans.
+    let mut has_text = false;
+    let mut rest = html;
+    while !rest.is_empty() {
+        let len = if rest.starts_with("</span>") {
+            open.pop();
+            "</span>".len()
+        } else if rest.starts_with("<span") {
+            let len = rest.find('>').map_or(rest.len(), |x| x + 1);
+            open.push(&rest[..len]);
+            len
+        } else if let Some(after) = rest.strip_prefix('\n') {
+            for _ in &open {
+                line.push_str("</span>");
+            }
+            lines.push(std::mem::take(&mut line));
+            line.extend(open.iter().copied());
+            has_text = false;
+            rest = after;
+            continue;
+        } else {
+            has_text = true;
+            rest.find(['<', '\n']).unwrap_or(rest.len())
+        };
+        line.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+    if has_text {
+        lines.push(line);
+    }
+    lines
+}
+
+/// The stylesheet for the configured themes.
+pub fn css(config: &HighlightConfig) -> eyre::Result<String> {
+    let mut css =
+        theme_css(config.theme.as_deref().unwrap_or("base16-ocean.dark"))?;
+    if let Some(dark_theme) = &config.dark_theme {
+        css.push_str("@media (prefers-color-scheme: dark) {\n");
+        css.push_str(&theme_css(dark_theme)?);
+        css.push_str("}\n");
+    }
+    Ok(css)
+}
+
+fn theme_css(name: &str) -> eyre::Result<String> {
+    let Some(theme) = THEME_SET.themes.get(name) else {
+        let known = THEME_SET
+            .themes
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        return Err(eyre::eyre!(
+            "Unknown highlight theme \"{name}\", expected one of: {}",
+            known.join(", ")
+        ));
+    };
+    Ok(syntect::html::css_for_theme_with_class_style(
+        theme,
+        CLASS_STYLE,
+    )?)
+}
@@ -0,0 +1,231 @@
+//! HTML files as documents, for pages that were never markdown.
+//!
+//! Their metadata comes from a `<!-- meta ... -->` comment holding TOML, like
+//! a markdown document's `+++` block, or else from their `<title>`, `<html
+//! lang>` and `<meta>` tags. Files with neither aren't documents. They're
+//! served as they are, unless the metadata sets `wrap = true`, in which case
+//! their `<body>` is put in the site's page like a rendered markdown
+//! document.
+
+use crate::{Body, GitMeta, HeaderTemplate, LinkTarget, Links, Meta, Rendered};
+use chrono::NaiveDate;
+use log::error;
+use std::path::Path;
+
+pub fn is_html(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|x| x.to_str()),
+        Some("html" | "htm")
+    )
+}
+
+/// Render an HTML document, like [`crate::markdown_to_document`] does a
+/// markdown one.
+pub fn to_document(
+    header: &HeaderTemplate,
+    contents: &str,
+    url: Option<&str>,
+    links: Option<&Links>,
+    git: Option<&GitMeta>,
+) -> Rendered {
+    let body = body(contents);
+    let targets = tags(body, "a")
+        .filter_map(|x| attribute(x, "href"))
+        .map(LinkTarget::Path)
+        .collect();
+    crate::document_page(
+        header,
+        Body {
+            html: body.to_string(),
+            meta: meta(contents),
+            toc: vec![],
+            words: text(contents).split_whitespace().count(),
+            links: targets,
+            mermaid: false,
+            code: false,
+            embeds: false,
+            tables: false,
+        },
+        url,
+        links,
+        git,
+    )
+}
+
+/// The text of the document, without its markup, for the search index.
+pub fn text(contents: &str) -> String {
+    let mut text = String::new();
+    let mut rest = body(contents);
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        text.push(' ');
+        rest = &rest[start..];
+        // Scripts, styles and comments have no text, and their contents
+        // can have `>` in them.
+        let lower = rest.get(..8).unwrap_or(rest).to_ascii_lowercase();
+        let close = if lower.starts_with("<!--") {
+            "-->"
+        } else if lower.starts_with("<script") {
+            "</script>"
+        } else if lower.starts_with("<style") {
+            "</style>"
+        } else {
+            ">"
+        };
+        rest = match find_ignoring_case(rest, close) {
+            Some(end) => &rest[end + close.len()..],
+            None => "",
+        };
+    }
+    text.push_str(rest);
+    decode(&text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The document's metadata, or `None` if it has none.
+fn meta(contents: &str) -> Option<Meta> {
+    if let Some(start) = contents.find("<!-- meta") {
+        let rest = &contents[start + "<!-- meta".len()..];
+        let end = rest.find("-->")?;
+        return Meta::parse(&rest[..end], false)
+            .inspect_err(|e| error!("Failed to parse metadata: {e}"))
+            .ok();
+    }
+
+    let start = find_ignoring_case(contents, "<title")?;
+    let rest = &contents[start..];
+    let text = &rest[rest.find('>')? + 1..];
+    let title = &text[..find_ignoring_case(text, "</title")?];
+    let mut meta = Meta {
+        title: decode(title.trim()),
+        ..Meta::default()
+    };
+    meta.lang = tags(contents, "html").find_map(|x| attribute(x, "lang"));
+    for tag in tags(contents, "meta") {
+        let Some(name) =
+            attribute(tag, "name").or_else(|| attribute(tag, "property"))
+        else {
+            continue;
+        };
+        let Some(content) = attribute(tag, "content") else {
+            continue;
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "description" => meta.desc = Some(content),
+            "author" => meta.author = Some(content),
+            "keywords" => {
+                meta.tags = content
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|x| !x.is_empty())
+                    .map(String::from)
+                    .collect();
+            }
+            "date" | "article:published_time" => {
+                meta.date =
+                    content.get(..10).and_then(|x| x.parse::<NaiveDate>().ok());
+            }
+            "article:modified_time" => {
+                meta.updated =
+                    content.get(..10).and_then(|x| x.parse::<NaiveDate>().ok());
+            }
+            "og:image" => meta.cover = Some(content),
+            _ => {}
+        }
+    }
+    Some(meta)
+}
+
+/// What's inside the `<body>` tag, or everything if there isn't one.
+fn body(contents: &str) -> &str {
+    let Some(start) = find_ignoring_case(contents, "<body") else {
+        return contents;
+    };
+    let Some(open) = contents[start..].find('>') else {
+        return contents;
+    };
+    let body = &contents[start + open + 1..];
+    match rfind_ignoring_case(body, "</body") {
+        Some(end) => &body[..end],
+        None => body,
+    }
+}
+
+/// The attributes of each `<name ...>` tag in `html`.
+pub fn tags<'h>(html: &'h str, name: &str) -> impl Iterator<Item = &'h str> {
+    let open = format!("<{name}");
+    let mut rest = html;
+    std::iter::from_fn(move || {
+        loop {
+            let start = find_ignoring_case(rest, &open)? + open.len();
+            let after = &rest[start..];
+            let end = after.find('>')?;
+            rest = &after[end..];
+            // Not `<abbr>` when looking for `<a>`.
+            if after.starts_with(|c: char| c.is_whitespace() || c == '/') {
+                return Some(after[..end].trim_end_matches('/'));
+            }
+            if end == 0 {
+                return Some("");
+            }
+        }
+    })
+}
+
+/// The value of the attribute `name` in a tag's `attributes`.
+pub fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut rest = attributes;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = &rest[..end];
+        rest = rest[end..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                let (value, after) = match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let value = &value[1..];
+                        let end = value.find(quote).unwrap_or(value.len());
+                        (&value[..end], value.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => value
+                        .split_once(char::is_whitespace)
+                        .unwrap_or((value, "")),
+                };
+                rest = after;
+                value
+            }
+            None => "",
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(decode(value));
+        }
+    }
+}
+
+/// Decode the common character references.
+fn decode(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+fn find_ignoring_case(haystack: &str, needle: &str) -> Option<usize> {
+    // Lowercasing ASCII doesn't move anything, so the indices match.
+    haystack.to_ascii_lowercase().find(needle)
+}
+
+fn rfind_ignoring_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack.to_ascii_lowercase().rfind(needle)
+}
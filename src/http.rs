@@ -0,0 +1,539 @@
+//! Small helpers for conditional and partial responses on top of `tiny_http`.
+
+use percent_encoding::{
+    AsciiSet, CONTROLS, NON_ALPHANUMERIC, utf8_percent_encode,
+};
+use std::time::SystemTime;
+use tiny_http::{Header, Request, Response, ResponseBox, StatusCode};
+
+/// Find the value of the first header named `name` (case-insensitively).
+pub fn header<'a>(rq: &'a Request, name: &str) -> Option<&'a str> {
+    rq.headers()
+        .iter()
+        .find(|x| x.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|x| x.value.as_str())
+}
+
+/// Build a header from a name and value, both of which we control.
+pub fn make_header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+/// A strong entity tag for `bytes` (FNV-1a, which is stable across builds and
+/// runs, unlike `std`'s `DefaultHasher`).
+pub fn etag(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("\"{hash:016x}\"")
+}
+
+/// Whether the request's `If-None-Match` header matches `etag`, meaning the
+/// client's copy is still fresh.
+pub fn if_none_match(rq: &Request, etag: &str) -> bool {
+    header(rq, "If-None-Match").is_some_and(|v| {
+        v.split(',')
+            .map(str::trim)
+            .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+    })
+}
+
+/// Whether the client's copy is fresh according to `If-Modified-Since`. This is
+/// ignored when the request has an `If-None-Match`, as that takes precedence.
+pub fn if_modified_since(rq: &Request, modified: SystemTime) -> bool {
+    if header(rq, "If-None-Match").is_some() {
+        return false;
+    }
+    header(rq, "If-Modified-Since")
+        .and_then(|x| httpdate::parse_http_date(x).ok())
+        // HTTP dates have a resolution of a second
+        .is_some_and(|since| {
+            modified
+                .duration_since(since)
+                .is_ok_and(|x| x.as_secs() == 0)
+                || modified < since
+        })
+}
+
+/// Respond with `data` and its ETag (and `Last-Modified`, if given), or
+/// `304 Not Modified` if the client already has it.
+pub fn etag_response(
+    rq: &Request,
+    data: Vec<u8>,
+    modified: Option<SystemTime>,
+    headers: impl IntoIterator<Item = Header>,
+) -> ResponseBox {
+    let headers = headers.into_iter().collect::<Vec<_>>();
+    let mut etag = etag(&data);
+    if rewritten(&headers) {
+        etag = crate::base_path::etag(&etag);
+    }
+    let fresh = if_none_match(rq, &etag)
+        || modified.is_some_and(|x| if_modified_since(rq, x));
+    let mut response = if fresh {
+        Response::new_empty(StatusCode(304)).boxed()
+    } else {
+        Response::from_data(data).boxed()
+    };
+    response.add_header(make_header("ETag", &etag));
+    if let Some(modified) = modified {
+        response.add_header(make_header(
+            "Last-Modified",
+            &httpdate::fmt_http_date(modified),
+        ));
+    }
+    for h in headers {
+        response.add_header(h);
+    }
+    response
+}
+
+/// A strong entity tag for a file, from its size and modification time, so
+/// that it doesn't have to be read to be validated.
+pub fn file_etag(metadata: &std::fs::Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|x| x.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos())
+}
+
+/// Respond with the contents of `file`, which are streamed rather than read
+/// into memory, honoring `If-None-Match`, `If-Modified-Since` and `Range`.
+pub fn file_response(
+    rq: &Request,
+    mut file: std::fs::File,
+    headers: impl IntoIterator<Item = Header>,
+) -> std::io::Result<ResponseBox> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let headers = headers.into_iter().collect::<Vec<_>>();
+    let rewritten = rewritten(&headers);
+    let metadata = file.metadata()?;
+    let mut etag = file_etag(&metadata);
+    if rewritten {
+        etag = crate::base_path::etag(&etag);
+    }
+    let modified = metadata.modified().ok();
+    let len = usize::try_from(metadata.len()).unwrap_or(usize::MAX);
+    let fresh = if_none_match(rq, &etag)
+        || modified.is_some_and(|x| if_modified_since(rq, x));
+    // A range of a file that changed since the client's copy would corrupt it.
+    let range = match header(rq, "If-Range") {
+        _ if rewritten => ByteRange::Full,
+        Some(x) if x != etag => ByteRange::Full,
+        _ => byte_range(rq, len),
+    };
+
+    let mut response = if fresh {
+        Response::new_empty(StatusCode(304)).boxed()
+    } else {
+        match range {
+            // Send the length rather than chunks so clients can show
+            // progress.
+            ByteRange::Full => Response::from_file(file)
+                .with_chunked_threshold(usize::MAX)
+                .boxed(),
+            ByteRange::Partial(start, end) => {
+                file.seek(SeekFrom::Start(start as u64))?;
+                let part_len = end - start + 1;
+                Response::new(
+                    StatusCode(206),
+                    vec![make_header(
+                        "Content-Range",
+                        &format!("bytes {start}-{end}/{len}"),
+                    )],
+                    file.take(part_len as u64),
+                    Some(part_len),
+                    None,
+                )
+                .with_chunked_threshold(usize::MAX)
+                .boxed()
+            }
+            ByteRange::Unsatisfiable => Response::empty(StatusCode(416))
+                .with_header(make_header(
+                    "Content-Range",
+                    &format!("bytes */{len}"),
+                ))
+                .boxed(),
+        }
+    };
+    response.add_header(make_header("ETag", &etag));
+    if !rewritten {
+        response.add_header(make_header("Accept-Ranges", "bytes"));
+    }
+    if let Some(modified) = modified {
+        response.add_header(make_header(
+            "Last-Modified",
+            &httpdate::fmt_http_date(modified),
+        ));
+    }
+    for h in headers {
+        response.add_header(h);
+    }
+    Ok(response)
+}
+
+/// The byte range a request asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No (usable) `Range` header; send everything.
+    Full,
+    /// An inclusive range of bytes.
+    Partial(usize, usize),
+    /// The range can't be satisfied for a body of this length.
+    Unsatisfiable,
+}
+
+/// Parse the request's `Range` header for a body of `len` bytes. Only single
+/// ranges are supported, anything else is answered with the full body.
+pub fn byte_range(rq: &Request, len: usize) -> ByteRange {
+    let Some(spec) = header(rq, "Range").and_then(|x| x.strip_prefix("bytes="))
+    else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let last = len.saturating_sub(1);
+    let (start, end) = match (start.parse::<usize>(), end.parse::<usize>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(last)),
+        (Ok(start), Err(_)) if end.is_empty() => (start, last),
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            (len.saturating_sub(suffix), last)
+        }
+        _ => return ByteRange::Full,
+    };
+    if len == 0 || start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(start, end)
+}
+
+/// Whether the body of a response with `headers` gets the base path (see
+/// [`crate::base_path`]), so that it can't be served in ranges.
+fn rewritten(headers: &[Header]) -> bool {
+    headers
+        .iter()
+        .find(|x| x.field.equiv("Content-Type"))
+        .is_some_and(|x| crate::base_path::rewrites(x.value.as_str()))
+}
+
+/// Respond with `data`, honoring `If-None-Match` and `Range`. The response
+/// carries an `ETag` and `Accept-Ranges`, plus the given extra headers.
+pub fn bytes_response(
+    rq: &Request,
+    data: &[u8],
+    etag: &str,
+    headers: impl IntoIterator<Item = Header>,
+) -> ResponseBox {
+    let headers = headers.into_iter().collect::<Vec<_>>();
+    let rewritten = rewritten(&headers);
+    let etag = if rewritten {
+        crate::base_path::etag(etag)
+    } else {
+        etag.to_string()
+    };
+    let headers = [make_header("ETag", &etag)]
+        .into_iter()
+        .chain((!rewritten).then(|| make_header("Accept-Ranges", "bytes")))
+        .chain(headers);
+
+    if if_none_match(rq, &etag) {
+        let mut response = Response::new_empty(StatusCode(304));
+        for h in headers {
+            response.add_header(h);
+        }
+        return response.boxed();
+    }
+
+    let range = if rewritten {
+        ByteRange::Full
+    } else {
+        byte_range(rq, data.len())
+    };
+    let mut response = match range {
+        ByteRange::Full => Response::from_data(data.to_vec()),
+        ByteRange::Partial(start, end) => {
+            Response::from_data(data[start..=end].to_vec())
+                .with_status_code(206)
+                .with_header(make_header(
+                    "Content-Range",
+                    &format!("bytes {start}-{end}/{}", data.len()),
+                ))
+        }
+        ByteRange::Unsatisfiable => Response::from_data(vec![])
+            .with_status_code(416)
+            .with_header(make_header(
+                "Content-Range",
+                &format!("bytes */{}", data.len()),
+            )),
+    };
+    for h in headers {
+        response.add_header(h);
+    }
+    response.boxed()
+}
+
+/// The percent-decoded path of the request, without the query.
+pub fn request_path(rq: &Request) -> String {
+    let base = crate::base_path::get();
+    let url = crate::base_path::strip(rq.url(), &base);
+    let path = url.split('?').next().unwrap_or_default();
+    percent_encoding::percent_decode_str(path)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// Whether the client asked for a document's markdown source rather than the
+/// page, with `?raw=1` or by accepting `text/markdown`.
+pub fn wants_markdown(rq: &Request, url: &url::Url) -> bool {
+    url.query_pairs().any(|(k, v)| k == "raw" && v == "1")
+        || header(rq, "Accept").is_some_and(|accept| {
+            accept.split(',').any(|x| {
+                let mut params = x.split(';').map(str::trim);
+                params
+                    .next()
+                    .is_some_and(|x| x.eq_ignore_ascii_case("text/markdown"))
+                    && !params.any(|x| x == "q=0" || x == "q=0.0")
+            })
+        })
+}
+
+/// Whether the client asked for a page, as browsers do when they go to a URL,
+/// rather than the file itself, which `?raw=1` and `?download` ask for.
+pub fn wants_page(rq: &Request, url: &url::Url) -> bool {
+    !url.query_pairs().any(|(k, v)| k == "raw" && v == "1")
+        && !wants_download(rq)
+        && header(rq, "Accept").is_some_and(|accept| {
+            accept.split(',').any(|x| {
+                x.split(';')
+                    .next()
+                    .is_some_and(|x| x.trim().eq_ignore_ascii_case("text/html"))
+            })
+        })
+}
+
+/// Whether the client asked for the file to be downloaded rather than shown,
+/// with `?download`.
+pub fn wants_download(rq: &Request) -> bool {
+    rq.url().split_once('?').is_some_and(|(_, query)| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .any(|(k, v)| k == "download" && v != "0")
+    })
+}
+
+/// The `Content-Disposition` header that has browsers save the file as
+/// `name` instead of showing it.
+pub fn attachment(name: &str) -> Header {
+    // The plain `filename` is for clients that don't know `filename*`.
+    let fallback = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    make_header(
+        "Content-Disposition",
+        &format!(
+            "attachment; filename=\"{fallback}\"; filename*=UTF-8''{}",
+            utf8_percent_encode(name, NON_ALPHANUMERIC)
+        ),
+    )
+}
+
+/// Collapse repeated slashes and drop `.` segments and the trailing slash
+/// from a request path. `..` segments are already resolved by `Url::parse`.
+pub fn normalize_path(path: &str) -> String {
+    let mut normalized = String::with_capacity(path.len());
+    for segment in path.split('/').filter(|x| !x.is_empty() && *x != ".") {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// The characters that have to be escaped in a URL path.
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Percent-encode a path so it can be used in a URL or a `Location` header.
+pub fn encode_path(path: &str) -> String {
+    utf8_percent_encode(path, PATH).to_string()
+}
+
+/// Guess the `Content-Type` of a file from its extension. Text types are
+/// assumed to be UTF-8, unknown types are sent as arbitrary binary data.
+pub fn content_type(path: &std::path::Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" | "markdown" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "ics" => "text/calendar; charset=utf-8",
+        "xml" => "application/xml; charset=utf-8",
+        "atom" => "application/atom+xml; charset=utf-8",
+        "rss" => "application/rss+xml; charset=utf-8",
+        "webmanifest" => "application/manifest+json",
+        "wasm" => "application/wasm",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "pdf" => "application/pdf",
+        "bmp" => "image/bmp",
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "m4a" => "audio/mp4",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "ogv" => "video/ogg",
+        "mov" => "video/quicktime",
+        "vtt" => "text/vtt; charset=utf-8",
+        "epub" => "application/epub+zip",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "xz" => "application/x-xz",
+        "zst" => "application/zstd",
+        "7z" => "application/x-7z-compressed",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_http::TestRequest;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        headers
+            .iter()
+            .fold(TestRequest::new(), |rq, (name, value)| {
+                rq.with_header(make_header(name, value))
+            })
+            .into()
+    }
+
+    fn range(spec: &str, len: usize) -> ByteRange {
+        byte_range(&request(&[("Range", spec)]), len)
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(byte_range(&request(&[]), 10), ByteRange::Full);
+        assert_eq!(range("bytes=0-4", 10), ByteRange::Partial(0, 4));
+        assert_eq!(range("bytes=4-", 10), ByteRange::Partial(4, 9));
+        assert_eq!(range("bytes=5-4", 10), ByteRange::Full);
+        assert_eq!(range("items=0-4", 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn suffix_range() {
+        assert_eq!(range("bytes=-3", 10), ByteRange::Partial(7, 9));
+        // A suffix longer than the body is all of it.
+        assert_eq!(range("bytes=-20", 10), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=-0", 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn range_past_the_end() {
+        assert_eq!(range("bytes=5-100", 10), ByteRange::Partial(5, 9));
+        assert_eq!(range("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=20-30", 10), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn several_ranges() {
+        assert_eq!(range("bytes=0-1,4-5", 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn none_match() {
+        let tag = etag(b"body");
+        let matches = |value: &str| {
+            if_none_match(&request(&[("If-None-Match", value)]), &tag)
+        };
+        assert!(matches(&tag));
+        assert!(matches("*"));
+        assert!(matches(&format!("W/{tag}")));
+        assert!(matches(&format!("\"other\", {tag}")));
+        assert!(!matches("\"other\""));
+        assert!(!if_none_match(&request(&[]), &tag));
+    }
+
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize_path("/blog//post.md"), "/blog/post.md");
+        assert_eq!(normalize_path("//index.html"), "/index.html");
+        assert_eq!(normalize_path("/blog/post.md/"), "/blog/post.md");
+        assert_eq!(normalize_path("/blog/./post.md"), "/blog/post.md");
+        assert_eq!(normalize_path("//"), "/");
+        assert_eq!(normalize_path("/"), "/");
+    }
+
+    #[test]
+    fn embedded_asset() {
+        let data = b"body { color: red }";
+        let tag = etag(data);
+        // The tag is stable, so it can be compared across builds and runs.
+        assert_eq!(tag, etag(data));
+
+        let response = bytes_response(&request(&[]), data, &tag, []);
+        assert_eq!(response.status_code(), StatusCode(200));
+        assert_eq!(response.data_length(), Some(data.len()));
+        let etag_header = response
+            .headers()
+            .iter()
+            .find(|x| x.field.equiv("ETag"))
+            .map(|x| x.value.to_string());
+        assert_eq!(etag_header.as_deref(), Some(tag.as_str()));
+
+        let fresh = request(&[("If-None-Match", &tag)]);
+        let response = bytes_response(&fresh, data, &tag, []);
+        assert_eq!(response.status_code(), StatusCode(304));
+
+        let partial = request(&[("Range", "bytes=0-3")]);
+        let response = bytes_response(&partial, data, &tag, []);
+        assert_eq!(response.status_code(), StatusCode(206));
+        assert_eq!(response.data_length(), Some(4));
+    }
+}
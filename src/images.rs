@@ -0,0 +1,333 @@
+//! Smaller versions of the images documents show.
+//!
+//! JPEG and PNG images from the assets directory are resized to the
+//! configured widths and encoded as WebP (and AVIF, if it's enabled) the
+//! first time a document showing them is rendered, which is usually when the
+//! content is loaded. Documents show them in a `<picture>` with a `srcset`
+//! for each format and the image's size, so the page doesn't shift as they
+//! load. The variants are kept in memory and served from
+//! `/.images/<asset path>/<width>.<extension>`.
+
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+
+/// Where the variants are served from.
+pub const PREFIX: &str = "/.images/";
+
+/// Processed images by their absolute path.
+static PROCESSED: LazyLock<Mutex<HashMap<PathBuf, Arc<Processed>>>> =
+    LazyLock::new(Default::default);
+
+/// The `[images]` table of `site.toml`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    pub enabled: bool,
+    /// The widths images are resized to. They're never enlarged.
+    pub widths: Vec<u32>,
+    /// What the variants are encoded as, besides the image's own format.
+    pub formats: Vec<Format>,
+    /// The quality of lossy variants, from 1 to 100.
+    pub quality: u8,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            widths: vec![480, 960, 1600],
+            formats: vec![Format::Webp],
+            quality: 80,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Webp,
+    /// Much smaller than WebP, but slow to encode.
+    Avif,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Webp => "webp",
+            Format::Avif => "avif",
+        }
+    }
+}
+
+/// An image's variants.
+#[derive(Debug)]
+struct Processed {
+    modified: SystemTime,
+    /// What the variants were made with.
+    config: ImagesConfig,
+    /// The size of the largest variant.
+    width: u32,
+    height: u32,
+    /// The widths there are variants for, smallest first.
+    widths: Vec<u32>,
+    /// The extension of the variants in the image's own format.
+    extension: &'static str,
+    /// By file name, e.g. `960.webp`.
+    variants: HashMap<String, Vec<u8>>,
+}
+
+/// An image from the assets directory that's shown as a `<picture>`.
+#[derive(Debug)]
+pub struct Picture {
+    /// The variants' URL without the file name, e.g. `/.images/cat.jpg`.
+    base: String,
+    processed: Arc<Processed>,
+}
+
+impl Picture {
+    /// The picture for the image at `url` (e.g. `/assets/cat.jpg`), if it's
+    /// one that's processed.
+    pub fn new(
+        content_path: &Path,
+        url: &str,
+        config: &ImagesConfig,
+    ) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let asset = url.strip_prefix("/assets/")?;
+        let decoded = percent_encoding::percent_decode_str(asset)
+            .decode_utf8()
+            .ok()?;
+        let path = source(content_path, &decoded)?;
+        Some(Self {
+            base: format!("{PREFIX}{asset}"),
+            processed: process(&path, config)?,
+        })
+    }
+
+    pub fn html(&self, alt: &str, title: &str) -> String {
+        let processed = &self.processed;
+        let srcset = |extension: &str| {
+            processed
+                .widths
+                .iter()
+                .map(|w| format!("{}/{w}.{extension} {w}w", self.base))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let sizes = format!("(max-width: {0}px) 100vw, {0}px", processed.width);
+        let mut html = String::from("<picture>");
+        // Browsers use the first source they support.
+        for format in [Format::Avif, Format::Webp] {
+            if processed.config.formats.contains(&format) {
+                html.push_str(&format!(
+                    "<source type=\"image/{}\" srcset=\"{}\" \
+                     sizes=\"{sizes}\">",
+                    format.extension(),
+                    escape(&srcset(format.extension())),
+                ));
+            }
+        }
+        let title = if title.is_empty() {
+            String::new()
+        } else {
+            format!(" title=\"{}\"", escape(title))
+        };
+        html.push_str(&format!(
+            "<img src=\"{}\" srcset=\"{}\" sizes=\"{sizes}\" width=\"{}\" \
+             height=\"{}\" alt=\"{}\"{title} loading=\"lazy\" \
+             decoding=\"async\"></picture>",
+            escape(&format!(
+                "{}/{}.{}",
+                self.base, processed.width, processed.extension
+            )),
+            escape(&srcset(processed.extension)),
+            processed.width,
+            processed.height,
+            escape(alt),
+        ));
+        html
+    }
+}
+
+/// The variant at `path`, relative to [`PREFIX`] (e.g. `cat.jpg/960.webp`).
+pub fn variant(
+    content_path: &Path,
+    path: &str,
+    config: &ImagesConfig,
+) -> Option<Vec<u8>> {
+    if !config.enabled {
+        return None;
+    }
+    let (asset, name) = path.rsplit_once('/')?;
+    let source = source(content_path, asset)?;
+    process(&source, config)?.variants.get(name).cloned()
+}
+
+/// Every variant made of the images in `content_path`, by their path relative
+/// to the site's root (e.g. `.images/cat.jpg/960.webp`).
+pub fn variants(content_path: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let Some(assets) = crate::assets_dir(content_path)
+        .and_then(|x| std::fs::canonicalize(x).ok())
+    else {
+        return vec![];
+    };
+    let processed = PROCESSED.lock().unwrap();
+    let mut variants = vec![];
+    for (path, image) in processed.iter() {
+        let Ok(asset) = path.strip_prefix(&assets) else {
+            continue;
+        };
+        for (name, contents) in &image.variants {
+            let path = Path::new(&PREFIX[1..]).join(asset).join(name);
+            variants.push((path, contents.clone()));
+        }
+    }
+    variants
+}
+
+/// The path of `asset` in the assets directory, if it's an image that's
+/// processed.
+fn source(content_path: &Path, asset: &str) -> Option<PathBuf> {
+    let dir = std::fs::canonicalize(crate::assets_dir(content_path)?).ok()?;
+    let path = std::fs::canonicalize(dir.join(asset)).ok()?;
+    let hidden = path
+        .strip_prefix(&dir)
+        .ok()?
+        .components()
+        .any(|x| x.as_os_str().as_encoded_bytes().starts_with(b"."));
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let processed = matches!(extension.as_str(), "jpg" | "jpeg" | "png");
+    (!hidden && processed && path.is_file()).then_some(path)
+}
+
+/// The variants of the image at `path`, made now unless they already were.
+fn process(path: &Path, config: &ImagesConfig) -> Option<Arc<Processed>> {
+    let modified = std::fs::metadata(path).and_then(|x| x.modified()).ok()?;
+    if let Some(processed) = PROCESSED.lock().unwrap().get(path)
+        && processed.modified == modified
+        && processed.config == *config
+    {
+        return Some(processed.clone());
+    }
+
+    // Not holding the lock while encoding, so that other images can be
+    // processed at the same time.
+    debug!("Processing \"{}\"", path.display());
+    let processed = Arc::new(
+        encode(path, modified, config)
+            .inspect_err(|e| {
+                warn!("Failed to process \"{}\": {e}", path.display());
+            })
+            .ok()?,
+    );
+    PROCESSED
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), processed.clone());
+    Some(processed)
+}
+
+fn encode(
+    path: &Path,
+    modified: SystemTime,
+    config: &ImagesConfig,
+) -> eyre::Result<Processed> {
+    let mut decoder = ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    // Phone cameras save photos sideways and say which way is up.
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let mut widths = config
+        .widths
+        .iter()
+        .copied()
+        .filter(|x| *x > 0 && *x < image.width())
+        .collect::<Vec<_>>();
+    if widths.is_empty() || config.widths.iter().any(|x| *x >= image.width()) {
+        widths.push(image.width());
+    }
+    widths.sort_unstable();
+    widths.dedup();
+
+    let png = path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("png"));
+    let extension = if png { "png" } else { "jpg" };
+    let mut variants = HashMap::new();
+    let mut size = (image.width(), image.height());
+    for &width in &widths {
+        let resized = if width == image.width() {
+            image.clone()
+        } else {
+            image.resize(width, u32::MAX, FilterType::Lanczos3)
+        };
+        size = (resized.width(), resized.height());
+
+        let mut own = vec![];
+        if png {
+            resized.write_to(
+                &mut std::io::Cursor::new(&mut own),
+                image::ImageFormat::Png,
+            )?;
+        } else {
+            let encoder =
+                JpegEncoder::new_with_quality(&mut own, config.quality);
+            DynamicImage::from(resized.to_rgb8())
+                .write_with_encoder(encoder)?;
+        }
+        variants.insert(format!("{width}.{extension}"), own);
+
+        for &format in &config.formats {
+            let contents = match format {
+                Format::Webp => {
+                    let rgba = resized.to_rgba8();
+                    webp::Encoder::from_rgba(&rgba, size.0, size.1)
+                        .encode(config.quality.into())
+                        .to_vec()
+                }
+                Format::Avif => {
+                    let mut contents = vec![];
+                    let encoder = AvifEncoder::new_with_speed_quality(
+                        &mut contents,
+                        8,
+                        config.quality,
+                    );
+                    resized.write_with_encoder(encoder)?;
+                    contents
+                }
+            };
+            variants
+                .insert(format!("{width}.{}", format.extension()), contents);
+        }
+    }
+
+    Ok(Processed {
+        modified,
+        config: config.clone(),
+        width: size.0,
+        height: size.1,
+        widths,
+        extension,
+        variants,
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
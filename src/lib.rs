@@ -0,0 +1,4203 @@
+//! A site engine that serves a directory of markdown documents.
+//!
+//! [`Server`] serves content directories the way the `site` binary does, and
+//! can be embedded in other programs. The rendering can be used on its own by
+//! loading a [`State`] and passing documents to [`markdown_to_document`].
+
+use chrono::{DateTime, Local, NaiveDate};
+use eyre::eyre;
+use include_dir::include_dir;
+use log::{debug, error, info, warn};
+use rinja::Template;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant, SystemTime};
+use tiny_http::{Header, Method, Request, Response, ResponseBox, StatusCode};
+use url::Url;
+
+pub mod access;
+mod access_log;
+mod activitypub;
+mod asciidoc;
+mod auth;
+pub mod backend;
+mod background;
+mod base_path;
+pub mod build;
+mod cache;
+pub mod check;
+mod emoji;
+mod feed;
+mod fingerprint;
+mod gitignore;
+mod highlight;
+mod html_pages;
+mod http;
+mod images;
+pub mod listen;
+mod listing;
+pub mod logging;
+mod math;
+mod mermaid;
+mod notebook;
+mod og;
+mod org;
+mod overrides;
+mod pool;
+mod ratelimit;
+mod sanitize;
+pub mod scaffold;
+mod search;
+mod security;
+mod server;
+mod shortcodes;
+mod sitemap;
+mod stats;
+mod suggest;
+mod systemd;
+mod tables;
+mod timeout;
+mod webmention;
+
+pub use server::{Handle, Server};
+
+static ASSETS: include_dir::Dir<'_> =
+    include_dir!("$CARGO_MANIFEST_DIR/static-assets");
+
+static STYLES: include_dir::Dir<'_> =
+    include_dir!("$CARGO_MANIFEST_DIR/styles");
+// const STYLES: &str = include_str!("../styles/styles.css");
+
+/// Where the stylesheet for highlighted code is served.
+const HIGHLIGHT_CSS: &str = "/.styles/highlight.css";
+/// The script behind the copy buttons of code blocks.
+const COPY_JS: &str = "/.static-assets/scripts/copy.js";
+const EMBED_JS: &str = "/.static-assets/scripts/embed.js";
+const SORT_JS: &str = "/.static-assets/scripts/sort.js";
+
+/// Embedded files only change with the binary, so let clients keep them for a
+/// week.
+const EMBEDDED_CACHE_CONTROL: &str = "public, max-age=604800";
+
+/// A content directory and what's loaded from it.
+#[derive(Debug)]
+pub struct Site {
+    /// The host the site is served for (see `--vhost`), `None` for the main
+    /// content, which is served for every other host.
+    host: Option<String>,
+    content_path: Arc<Path>,
+    /// Shared with the background jobs that update it, like verifying a
+    /// webmention.
+    state: Arc<RwLock<State>>,
+}
+
+impl Site {
+    /// The host the site is served for, `None` for the main content.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    pub fn content_path(&self) -> &Path {
+        &self.content_path
+    }
+
+    /// What's currently loaded, which is replaced when the site is reloaded.
+    pub fn state(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unwrap()
+    }
+
+    /// The site to serve for requests to `host`.
+    fn for_host<'a>(sites: &'a [Site], host: Option<&str>) -> &'a Site {
+        host.and_then(|host| {
+            sites.iter().find(|x| x.host.as_deref() == Some(host))
+        })
+        .unwrap_or(&sites[0])
+    }
+
+    fn reload(&self, options: &LoadOptions) {
+        info!("Reloading state for \"{}\"...", self.content_path.display());
+        let mut state = self.state.write().unwrap();
+        match State::load(&self.content_path, options, Some(&state)) {
+            Ok(s) => {
+                info!("State reloaded sucessfully!");
+                if let Some(outgoing) = webmention::Outgoing::new(&state, &s) {
+                    std::thread::spawn(|| outgoing.send());
+                }
+                if let Some(outgoing) = activitypub::Outgoing::new(&state, &s) {
+                    std::thread::spawn(|| outgoing.send());
+                }
+                if let Some(ping) = feed::Ping::new(&state, &s) {
+                    std::thread::spawn(|| ping.send());
+                }
+                *state = s;
+            }
+            Err(e) => {
+                error!("Failed to reload state (retaining previous state): {e}")
+            }
+        }
+    }
+}
+
+/// How long the content directory must go unchanged before `--watch` reloads.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watch `content_path` for changes, recording when the last one happened.
+/// Changes stop being watched when the returned watcher is dropped.
+fn watch(
+    content_path: &Path,
+    last_change: Arc<Mutex<Option<Instant>>>,
+) -> eyre::Result<notify::RecommendedWatcher> {
+    use notify::{EventKind, RecursiveMode, Watcher};
+    let content_path_ = content_path.to_path_buf();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<_>| {
+            let event: notify::Event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    error!("Failed to watch content directory: {e}");
+                    return;
+                }
+            };
+            if !matches!(
+                event.kind,
+                EventKind::Create(_)
+                    | EventKind::Modify(_)
+                    | EventKind::Remove(_)
+            ) {
+                return;
+            }
+            // Git touches its own directory constantly, none of which
+            // matters to us.
+            let in_git_dir = |p: &Path| {
+                p.strip_prefix(&content_path_)
+                    .is_ok_and(|x| x.starts_with(".git"))
+            };
+            if event.paths.iter().all(|p| in_git_dir(p)) {
+                return;
+            }
+            debug!("Content changed: {:?}", event.paths);
+            *last_change.lock().unwrap() = Some(Instant::now());
+        })?;
+    watcher.watch(content_path, RecursiveMode::Recursive)?;
+    info!("Watching \"{}\" for changes", content_path.display());
+    Ok(watcher)
+}
+
+/// Flags shared between the serve threads and the main loop.
+#[derive(Debug)]
+struct Control {
+    /// The bearer token the control endpoints require, they're disabled when
+    /// this is `None`.
+    token: Option<String>,
+    /// The secret `/.reload` requires, it's disabled when this is `None`.
+    webhook_secret: Option<String>,
+    /// Whether webhooks pull the content before reloading.
+    git_pull: bool,
+    reload: Arc<AtomicBool>,
+    /// Run `git pull` before the next reload.
+    pull: AtomicBool,
+    shutdown: Arc<AtomicBool>,
+    /// Reopen the access log, set by `SIGUSR1`.
+    reopen_log: Arc<AtomicBool>,
+}
+
+impl Control {
+    /// Handle a request to `/.control/<action>`.
+    fn handle(&self, rq: Request, action: &str) {
+        let Some(token) = &self.token else {
+            respond(rq, Response::new_empty(StatusCode(404)));
+            return;
+        };
+        let flag = match action {
+            "reload" => &*self.reload,
+            "shutdown" => &*self.shutdown,
+            _ => {
+                respond(rq, Response::new_empty(StatusCode(404)));
+                return;
+            }
+        };
+        if *rq.method() != Method::Post {
+            respond(
+                rq,
+                Response::new_empty(StatusCode(405))
+                    .with_header(http::make_header("Allow", "POST")),
+            );
+            return;
+        }
+
+        let authorized = http::header(&rq, "Authorization")
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .is_some_and(|x| constant_time_eq(x.trim(), token));
+        if !authorized {
+            respond(
+                rq,
+                Response::new_empty(StatusCode(401)).with_header(
+                    http::make_header("WWW-Authenticate", "Bearer"),
+                ),
+            );
+            return;
+        }
+
+        info!("Control request: {action}");
+        flag.store(true, Ordering::Relaxed);
+        respond(rq, Response::new_empty(StatusCode(202)));
+    }
+}
+
+impl Control {
+    /// Handle a request to `/.stats`, which needs the control token either as
+    /// a bearer token or as the password of basic authentication, so that
+    /// browsers can ask for it.
+    fn stats(&self, rq: Request, site: &Site, options: &ServeOptions) {
+        use base64::Engine;
+        let state = site.state.read().unwrap();
+        let Some(token) = &self.token else {
+            respond(rq, error_response(&state, 404));
+            return;
+        };
+
+        let authorization = http::header(&rq, "Authorization");
+        let bearer = authorization
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .is_some_and(|x| constant_time_eq(x.trim(), token));
+        let basic = authorization
+            .and_then(|x| x.strip_prefix("Basic "))
+            .and_then(|x| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(x.trim())
+                    .ok()
+            })
+            .and_then(|x| String::from_utf8(x).ok())
+            .is_some_and(|x| {
+                x.split_once(':').is_some_and(|(_, password)| {
+                    constant_time_eq(password, token)
+                })
+            });
+        if !bearer && !basic {
+            respond(
+                rq,
+                Response::new_empty(StatusCode(401)).with_header(
+                    http::make_header(
+                        "WWW-Authenticate",
+                        "Basic realm=\"stats\"",
+                    ),
+                ),
+            );
+            return;
+        }
+
+        let html = StatsTemplate::stats(
+            &state,
+            &options.stats,
+            options.pool.snapshot(),
+            site.host.as_deref(),
+        );
+        let Some(html) = html else {
+            respond(rq, error_response(&state, 500));
+            return;
+        };
+        let mut response = content_response(
+            &rq,
+            &state.config,
+            html.into_bytes(),
+            Some("text/html; charset=utf-8"),
+            None,
+        );
+        response.add_header(http::make_header("Cache-Control", "no-store"));
+        respond(rq, response);
+    }
+
+    /// Handle a webhook request to `/.reload`.
+    fn webhook(&self, mut rq: Request) {
+        /// Push payloads are small, but don't let anyone make us buffer
+        /// arbitrary amounts of data.
+        const MAX_BODY: u64 = 4 * 1024 * 1024;
+        use std::io::Read;
+
+        let Some(secret) = &self.webhook_secret else {
+            respond(rq, Response::new_empty(StatusCode(404)));
+            return;
+        };
+        if *rq.method() != Method::Post {
+            respond(
+                rq,
+                Response::new_empty(StatusCode(405))
+                    .with_header(http::make_header("Allow", "POST")),
+            );
+            return;
+        }
+
+        let mut body = vec![];
+        if let Err(e) = timeout::reader(rq.as_reader())
+            .take(MAX_BODY)
+            .read_to_end(&mut body)
+        {
+            error!("Failed to read webhook body: {e}");
+            respond(rq, Response::new_empty(StatusCode(400)));
+            return;
+        }
+
+        let bearer = http::header(&rq, "Authorization")
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .is_some_and(|x| constant_time_eq(x.trim(), secret));
+        let signature = http::header(&rq, "X-Hub-Signature-256")
+            .and_then(|x| x.strip_prefix("sha256="))
+            .or_else(|| http::header(&rq, "X-Forgejo-Signature"))
+            .or_else(|| http::header(&rq, "X-Gitea-Signature"));
+        let signed = signature.is_some_and(|x| verify_hmac(secret, &body, x));
+        if !bearer && !signed {
+            respond(rq, Response::new_empty(StatusCode(401)));
+            return;
+        }
+
+        info!("Reload requested by webhook");
+        if self.git_pull {
+            self.pull.store(true, Ordering::Relaxed);
+        }
+        self.reload.store(true, Ordering::Relaxed);
+        respond(rq, Response::new_empty(StatusCode(202)));
+    }
+}
+
+/// Whether `signature` (in hex) is the HMAC-SHA256 of `body` keyed by
+/// `secret`.
+fn verify_hmac(secret: &str, body: &[u8], signature: &str) -> bool {
+    use hmac::{Hmac, Mac};
+    let Ok(signature) = hex::decode(signature.trim()) else {
+        return false;
+    };
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any size");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Update the content directory from its upstream, only fast-forwarding so
+/// that local changes are never merged or lost.
+fn git_pull(content_path: &Path) {
+    info!("Pulling \"{}\"", content_path.display());
+    let output = std::process::Command::new("git")
+        .current_dir(content_path)
+        .args(["pull", "--ff-only"])
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            debug!("git pull: {}", String::from_utf8_lossy(&output.stdout));
+        }
+        Ok(output) => error!(
+            "git pull failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => error!("Failed to run git pull: {e}"),
+    }
+}
+
+/// Compare two secrets without short-circuiting on the first difference.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Options that affect how the state is loaded.
+#[derive(Debug, Default)]
+pub struct LoadOptions {
+    /// Look up each document's last commit.
+    pub git_metadata: bool,
+    /// Where documents' histories are, see `--repo-url`.
+    pub repo_url: Option<String>,
+    /// The maximum size of the render cache, in bytes.
+    pub cache_size: usize,
+    /// Where template overrides are read from instead of the content
+    /// directory's `.templates/`.
+    pub templates: Option<PathBuf>,
+}
+
+/// A document in the index.
+#[derive(Debug)]
+pub struct IndexEntry {
+    pub meta: Meta,
+    pub section: String,
+    /// The path of the document's file, relative to the content directory.
+    pub path: String,
+    /// Where the document is served, see [`document_url`].
+    pub url: String,
+    pub git: Option<GitMeta>,
+    /// How many words the document's text has.
+    pub words: usize,
+    /// The paths of the documents that link to this one, newest first.
+    pub linked_from: Vec<String>,
+}
+
+/// An old URL as it's given in `aliases` or the `redirects` table, e.g.
+/// `old/path.md` or `/2023/old-slug/`, as the path it's requested at.
+fn alias_path(old: &str) -> String {
+    format!("/{}", old.trim_matches('/'))
+}
+
+/// The URL path (with a leading slash) a document at `path` is served at:
+/// its path without the extension, or with the file name replaced by `slug`.
+fn document_url(path: &str, slug: Option<&str>) -> String {
+    let path = Path::new(path);
+    let name = match slug {
+        Some(slug) => slug.trim_matches('/'),
+        None => path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap_or_default(),
+    };
+    match path.parent().and_then(|x| x.to_str()) {
+        Some(parent) if !parent.is_empty() => format!("/{parent}/{name}"),
+        _ => format!("/{name}"),
+    }
+}
+
+/// Everything loaded from a content directory.
+#[derive(Debug)]
+pub struct State {
+    config: SiteConfig,
+    /// Where the content was loaded from.
+    content_path: PathBuf,
+    /// Sorted for display, the root index comes first.
+    sections: Vec<Section>,
+    index: Vec<IndexEntry>,
+    /// Documents with `draft = true`, which are only served for previews.
+    drafts: Vec<IndexEntry>,
+    /// Every tag used by a document and how many documents use it, sorted by
+    /// name.
+    tags: Vec<(String, usize)>,
+    search: search::SearchIndex,
+    /// Rendered documents, dropped with the rest of the state on reload.
+    cache: cache::RenderCache,
+    /// Templates from `.templates/` that replace the embedded ones.
+    templates: overrides::Overrides,
+    /// The stylesheet for highlighted code, from the configured themes.
+    highlight_css: String,
+    /// The fingerprinted URL of `highlight_css`.
+    highlight_url: String,
+    /// What the 404 page says instead of the default message, rendered from
+    /// `404.md` in the content root.
+    not_found: Option<String>,
+    /// What's shown above each section's index, by section name.
+    landings: HashMap<String, Landing>,
+    /// When `site.toml` was last modified, if there is one.
+    config_modified: Option<SystemTime>,
+    /// When this was loaded, which is when the templates and backlinks that
+    /// every page shows last changed.
+    loaded: SystemTime,
+    /// Every document as it was read, by its absolute path.
+    parsed: HashMap<PathBuf, Parsed>,
+    /// The webmentions documents have received.
+    mentions: webmention::Mentions,
+    /// The site's ActivityPub actor and its followers.
+    activitypub: activitypub::ActivityPub,
+}
+
+/// What's read from a document when the state is loaded, kept so that a
+/// reload only has to read the documents that changed since.
+#[derive(Debug, Clone)]
+struct Parsed {
+    modified: SystemTime,
+    meta: Option<Meta>,
+    words: usize,
+    links: Vec<LinkTarget>,
+    /// The document's text for the search index.
+    text: String,
+    /// What git knew about the document when `HEAD` was at the commit it's
+    /// with, so that it's only asked again once something's been committed.
+    git: Option<(String, Option<GitMeta>)>,
+}
+
+/// The introduction at the top of a section's index, from an `_index.md` or
+/// `index.md` in the section's directory (or the content root for the root
+/// index).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Landing {
+    /// Replaces the section's title.
+    title: Option<String>,
+    /// Replaces the section's description.
+    desc: Option<String>,
+    #[serde(skip)]
+    html: String,
+}
+
+impl Landing {
+    fn load(path: &Path) -> std::io::Result<Self> {
+        let contents = read_document(path)?;
+        let (html, meta) = markdown_fragment(&contents);
+        let mut landing = match meta {
+            Some((text, true)) => {
+                serde_yaml::from_str(&text).map_err(|e| e.to_string())
+            }
+            Some((text, false)) => {
+                toml::de::from_str(&text).map_err(|e| e.to_string())
+            }
+            None => Ok(Landing::default()),
+        }
+        .unwrap_or_else(|e| {
+            error!("Failed to parse metadata of \"{}\": {e}", path.display());
+            Landing::default()
+        });
+        landing.html = html;
+        Ok(landing)
+    }
+}
+
+/// The section whose index `path` is the landing of, if it is one.
+fn landing_section(content_path: &Path, path: &Path) -> Option<String> {
+    let name = path.file_name()?;
+    if name != "_index.md" && name != "index.md" {
+        return None;
+    }
+    let dir = path.parent()?.strip_prefix(content_path).ok()?;
+    match dir.components().count() {
+        0 | 1 => Some(dir.to_str()?.to_string()),
+        _ => None,
+    }
+}
+
+/// Site-wide settings, read from `site.toml` in the content root.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct SiteConfig {
+    /// Shown in the header and used in page titles and feeds.
+    title: Option<String>,
+    /// Who wrote the documents that don't name their author.
+    author: Option<String>,
+    /// Where the byline links to for documents by `author`.
+    author_url: Option<String>,
+    /// The public URL of the site, e.g. `https://example.com`. Absolute links
+    /// are made from the request's `Host` when this isn't set.
+    base_url: Option<String>,
+    /// Redirect requests for other hosts, like the server's address, to
+    /// `base_url`.
+    redirect_to_base_url: bool,
+    /// The default `lang` of pages, overridden by a document's `lang`.
+    language: Option<String>,
+    /// Text shown at the bottom of every page.
+    footer: Option<String>,
+    /// The contents of `/robots.txt`, which allows everything by default.
+    robots: Option<String>,
+    /// How many documents each index page lists, `0` lists them all on one
+    /// page.
+    page_size: Option<usize>,
+    /// How many words a minute readers are assumed to read, for the reading
+    /// time of documents.
+    words_per_minute: Option<usize>,
+    /// Which themes code is highlighted with.
+    highlight: highlight::HighlightConfig,
+    markdown: MarkdownConfig,
+    /// How diagrams are rendered.
+    mermaid: mermaid::MermaidConfig,
+    /// How images are resized.
+    images: images::ImagesConfig,
+    /// Whether documents get social previews.
+    og: og::OgConfig,
+    /// Serve listings of directories, and the files in them that aren't
+    /// documents.
+    listings: bool,
+    /// Hidden files and directories whose files are served anyway, e.g.
+    /// `.well-known` for ACME challenges.
+    serve_hidden: Vec<String>,
+    /// The extensions of files that are always downloaded rather than shown,
+    /// e.g. `["zip", "tar.gz"]`.
+    downloads: Vec<String>,
+    /// Whether documents receive webmentions.
+    webmention: webmention::WebmentionConfig,
+    /// Whether the site can be followed with ActivityPub.
+    activitypub: activitypub::ActivityPubConfig,
+    /// The WebSub hub feeds are advertised with, which is pinged when they
+    /// change, e.g. `https://pubsubhubbub.appspot.com/`.
+    websub_hub: Option<String>,
+    /// The headers HTML responses get.
+    security_headers: security::SecurityHeaders,
+    /// Paths that need a password.
+    protected: Vec<auth::Protected>,
+    /// The addresses that can make requests, as well as the ones allowed by
+    /// the command line.
+    access: access::Access,
+    /// `Cache-Control` values by path, e.g. `"/.styles/*"` (a trailing `*`
+    /// matches anything), or by content type, e.g. `"text/html"` or
+    /// `"image/*"`.
+    cache_control: HashMap<String, String>,
+    /// Old paths and where they've moved to, e.g. `"/2023/post" =
+    /// "/blog/post"`, besides the `aliases` of documents.
+    redirects: HashMap<String, String>,
+}
+
+impl SiteConfig {
+    fn load(content_path: &Path) -> eyre::Result<SiteConfig> {
+        let path = content_path.join("site.toml");
+        if !path.is_file() {
+            return Ok(SiteConfig::default());
+        }
+        let config = std::fs::read_to_string(&path)?;
+        toml::de::from_str(&config)
+            .map_err(|e| eyre!("Invalid \"{}\": {e}", path.display()))
+    }
+
+    /// The base URL links are made absolute with: the configured one, or the
+    /// request's `origin` (e.g. `https://example.com`). Has no trailing slash.
+    fn base<'a>(&'a self, origin: &'a str) -> &'a str {
+        self.base_url
+            .as_deref()
+            .unwrap_or(origin)
+            .trim_end_matches('/')
+    }
+
+    /// The URL search engines are told to use for the page at `path`, if
+    /// there's a `base_url`.
+    fn canonical(&self, path: &str) -> Option<String> {
+        let base = self.base_url.as_deref()?.trim_end_matches('/');
+        Some(format!("{base}{}", http::encode_path(path)))
+    }
+
+    /// Where requests for `url` are redirected to, without the path, if
+    /// they're for another host than `base_url` and that's asked for.
+    fn redirect_host(&self, url: &Url) -> Option<&str> {
+        let base = self
+            .base_url
+            .as_deref()
+            .filter(|_| self.redirect_to_base_url)?;
+        let canonical = Url::parse(base).ok()?;
+        let same = canonical.host_str() == url.host_str()
+            && canonical.port() == url.port();
+        (!same).then(|| base.trim_end_matches('/'))
+    }
+
+    /// Whether the file at `relative` (to the content directory) is served
+    /// even though it's hidden.
+    fn serves_hidden(&self, relative: &Path) -> bool {
+        self.serve_hidden
+            .iter()
+            .map(|x| x.trim_matches('/'))
+            .any(|x| !x.is_empty() && relative.starts_with(x))
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_size.unwrap_or(20)
+    }
+
+    /// How many minutes it takes to read `words` words, at least one.
+    fn reading_time(&self, words: usize) -> usize {
+        let rate = self.words_per_minute.unwrap_or(200).max(1);
+        words.div_ceil(rate).max(1)
+    }
+
+    fn language(&self) -> &str {
+        self.language.as_deref().unwrap_or("en-US")
+    }
+
+    /// Whether the file called `name` is always downloaded.
+    fn is_download(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.downloads.iter().any(|x| {
+            name.strip_suffix(&x.trim_start_matches('.').to_ascii_lowercase())
+                .is_some_and(|x| x.ends_with('.'))
+        })
+    }
+
+    /// The configured `Cache-Control` for the response to a request for
+    /// `path`. The longest matching path pattern wins, then an exact match of
+    /// `content_type` and then a `type/*` wildcard.
+    fn cache_control_for(
+        &self,
+        path: &str,
+        content_type: Option<&str>,
+    ) -> Option<&str> {
+        let by_path = self
+            .cache_control
+            .iter()
+            .filter(|(pattern, _)| {
+                pattern.starts_with('/')
+                    && match pattern.strip_suffix('*') {
+                        Some(prefix) => path.starts_with(prefix),
+                        None => path == *pattern,
+                    }
+            })
+            .max_by_key(|(pattern, _)| pattern.len());
+        if let Some((_, value)) = by_path {
+            return Some(value);
+        }
+
+        let essence =
+            content_type?.split(';').next().unwrap_or_default().trim();
+        let wildcard = essence.split_once('/').map(|(ty, _)| format!("{ty}/*"));
+        self.cache_control
+            .get(essence)
+            .or_else(|| self.cache_control.get(wildcard.as_deref()?))
+            .map(String::as_str)
+    }
+}
+
+/// Which markdown extensions are enabled, from the `[markdown]` table of
+/// `site.toml`. All but math are by default.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct MarkdownConfig {
+    /// `[^name]` references to footnotes, which are listed at the end of the
+    /// document.
+    footnotes: bool,
+    /// `- [ ]` and `- [x]` list items.
+    task_lists: bool,
+    /// Curly quotes, dashes and ellipses from their ASCII forms.
+    smart_punctuation: bool,
+    /// `$inline$` and `$$display$$` TeX math and ```` ```math ```` blocks.
+    /// Off by default since dollar signs are common outside of math.
+    math: bool,
+    /// `:shortcode:` emoji, e.g. `:tada:`.
+    emoji: bool,
+    /// Remove scripts, event handlers and `javascript:` links from the HTML
+    /// in documents, for content from people who aren't trusted.
+    sanitize: bool,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        Self {
+            footnotes: true,
+            task_lists: true,
+            smart_punctuation: true,
+            math: false,
+            emoji: true,
+            sanitize: false,
+        }
+    }
+}
+
+impl MarkdownConfig {
+    /// The options documents are parsed with.
+    fn options(&self) -> pulldown_cmark::Options {
+        use pulldown_cmark::Options;
+        let mut options = Options::empty();
+        options.insert(Options::ENABLE_GFM);
+        options.insert(Options::ENABLE_YAML_STYLE_METADATA_BLOCKS);
+        options.insert(Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
+        options.set(Options::ENABLE_FOOTNOTES, self.footnotes);
+        options.set(Options::ENABLE_TASKLISTS, self.task_lists);
+        options.set(Options::ENABLE_SMART_PUNCTUATION, self.smart_punctuation);
+        options.set(Options::ENABLE_MATH, self.math);
+        options
+    }
+}
+
+/// A top-level directory of the content path with a `.section.toml`. The root
+/// index is a section with an empty name.
+#[derive(Debug, Default)]
+struct Section {
+    name: String,
+    config: SectionConfig,
+}
+
+impl Section {
+    fn title(&self) -> &str {
+        self.config.title.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// A section's settings, from its `.section.toml`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct SectionConfig {
+    /// Shown instead of the directory name.
+    title: Option<String>,
+    /// Shown at the top of the section's index.
+    description: Option<String>,
+    /// Sections are ordered by this, then by name.
+    order: i64,
+    /// Leave the section out of the header, the root index and the site's
+    /// feed. Its index and documents are still reachable.
+    hidden: bool,
+    /// The metadata `new` gives the section's documents.
+    defaults: scaffold::Defaults,
+    /// Whether the section's directories are listed, overriding `site.toml`.
+    listings: Option<bool>,
+}
+
+impl State {
+    /// The documents that are listed, newest first.
+    pub fn documents(&self) -> &[IndexEntry] {
+        &self.index
+    }
+
+    fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|x| x.name == name)
+    }
+
+    /// Whether documents in `section` are left out of site-wide listings.
+    fn is_hidden(&self, section: &str) -> bool {
+        self.section(section).is_some_and(|x| x.config.hidden)
+    }
+
+    /// Whether `entry` can be listed on the page at `path`, which it can't
+    /// if it needs a password that the page doesn't.
+    fn is_listed_at(&self, entry: &IndexEntry, path: &str) -> bool {
+        auth::listable(&self.config.protected, &entry.url, path)
+    }
+
+    /// Where a request for `path` is redirected to if it's an old URL, from
+    /// the `redirects` table or the `aliases` of a document.
+    fn redirect(&self, path: &str) -> Option<String> {
+        let matches = |old: &String| alias_path(old) == path;
+        if let Some((_, to)) =
+            self.config.redirects.iter().find(|(old, _)| matches(old))
+        {
+            return Some(to.clone());
+        }
+        self.index
+            .iter()
+            .find(|x| x.meta.aliases.iter().any(matches))
+            .map(|x| http::encode_path(&x.url))
+    }
+
+    /// Load the content at `content_path`. Documents that haven't been
+    /// modified since `previous` was loaded aren't read again, unless
+    /// `site.toml` has changed.
+    pub fn load(
+        content_path: &Path,
+        options: &LoadOptions,
+        previous: Option<&State>,
+    ) -> eyre::Result<State> {
+        let found_git = find_program("git").is_some();
+        let config = SiteConfig::load(content_path)?;
+        let config_modified = std::fs::metadata(content_path.join("site.toml"))
+            .and_then(|x| x.modified())
+            .ok();
+        let previous = previous
+            .filter(|x| x.config_modified == config_modified)
+            .map(|x| &x.parsed);
+        let templates = overrides::Overrides::load(
+            options
+                .templates
+                .as_deref()
+                .unwrap_or(&content_path.join(".templates")),
+        )?;
+        let highlight_css = highlight::css(&config.highlight)?;
+        let mentions =
+            webmention::Mentions::load(content_path, &config.webmention)?;
+        let activitypub =
+            activitypub::ActivityPub::load(content_path, &config)?;
+        let highlight_url = fingerprint::with_hash(
+            HIGHLIGHT_CSS,
+            http::etag(highlight_css.as_bytes()).trim_matches('"'),
+        );
+        let not_found_path = content_path.join("404.md");
+        let not_found = if not_found_path.is_file() {
+            let contents = read_document(&not_found_path)?;
+            Some(markdown_fragment(&contents).0)
+        } else {
+            None
+        };
+
+        let mut index: Vec<IndexEntry> = vec![];
+        let mut texts = HashMap::new();
+        let mut sections = vec![];
+        let mut documents = vec![];
+        let mut parsed = HashMap::new();
+        let mut landings = HashMap::new();
+
+        walk(content_path, &mut |is_dir, path| {
+            if let Some(file_name) = path.file_name() {
+                if file_name == ".section.toml" && !is_dir {
+                    let section_cfg = std::fs::read_to_string(path)?;
+                    let section_cfg = match toml::de::from_str::<SectionConfig>(
+                        &section_cfg,
+                    ) {
+                        Ok(c) => c,
+                        Err(e) => {
+                            error!(
+                                "Failed to parse section configuration at path \"{}\": {e}",
+                                path.display()
+                            );
+                            SectionConfig::default()
+                        }
+                    };
+                    let path = path
+                        .strip_prefix(content_path)
+                        .expect("is a subdir of content path");
+                    if let Some(section_name) = path
+                        .components()
+                        .next()
+                        .map(|x| x.as_os_str())
+                        .map(|x| x.to_str().unwrap().to_string())
+                    {
+                        sections.push(Section {
+                            name: section_name,
+                            config: section_cfg,
+                        });
+                    }
+                }
+
+                if file_name.as_encoded_bytes().starts_with(b".") {
+                    return Ok(false);
+                }
+                // User assets aren't documents.
+                if is_dir && path == content_path.join("assets") {
+                    return Ok(false);
+                }
+            }
+
+            if is_dir {
+                return Ok(true);
+            }
+            // The 404 page isn't a document of its own.
+            if path == not_found_path {
+                return Ok(true);
+            }
+            // Neither are section landings. `_index.md` wins over `index.md`.
+            if let Some(section) = landing_section(content_path, path) {
+                if path.file_name() == Some("_index.md".as_ref())
+                    || !landings.contains_key(&section)
+                {
+                    landings.insert(section, Landing::load(path)?);
+                }
+                return Ok(true);
+            }
+
+            if is_markdown(path)
+                || html_pages::is_html(path)
+                || org::is_org(path)
+                || asciidoc::is_asciidoc(path)
+                || notebook::is_notebook(path)
+            {
+                debug_assert!(path.is_absolute());
+                documents.push(path.to_path_buf());
+            }
+
+            Ok(true)
+        })?;
+
+        // Reading the documents is most of the work, and highlighting their
+        // code makes it slow, so it's spread across threads.
+        let header = HeaderTemplate {
+            site: &config,
+            sections: &[],
+            templates: &templates,
+            highlight_url: &highlight_url,
+            content_path,
+            mentions: &mentions,
+        };
+        let parse = |path: &Path| -> std::io::Result<(Parsed, bool)> {
+            let modified =
+                std::fs::metadata(path).and_then(|x| x.modified())?;
+            if let Some(doc) = previous
+                .and_then(|x| x.get(path))
+                .filter(|x| x.modified == modified)
+            {
+                return Ok((doc.clone(), true));
+            }
+            let contents = read_document(path)?;
+            let Rendered {
+                meta, words, links, ..
+            } = renderer(path)(&header, &contents, None, None, None);
+            let text = if html_pages::is_html(path) {
+                html_pages::text(&contents)
+            } else if org::is_org(path) {
+                org::search_text(&contents)
+            } else if asciidoc::is_asciidoc(path) {
+                asciidoc::search_text(&contents)
+            } else if notebook::is_notebook(path) {
+                notebook::search_text(&contents)
+            } else {
+                search::markdown_text(&contents)
+            };
+            Ok((
+                Parsed {
+                    modified,
+                    meta,
+                    words,
+                    links,
+                    text,
+                    git: None,
+                },
+                false,
+            ))
+        };
+        let threads =
+            std::thread::available_parallelism().map_or(1, |x| x.get());
+        let chunk_size = documents.len().div_ceil(threads).max(1);
+        let docs = std::thread::scope(|scope| {
+            let parse = &parse;
+            let workers = documents
+                .chunks(chunk_size)
+                .map(|paths| {
+                    scope.spawn(move || {
+                        paths
+                            .iter()
+                            .map(|x| parse(x))
+                            .collect::<std::io::Result<Vec<_>>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .map(|x| x.join().expect("parsing doesn't panic"))
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+
+        let mut reused = 0;
+        for (path, (doc, unchanged)) in
+            documents.iter().zip(docs.into_iter().flatten())
+        {
+            reused += usize::from(unchanged);
+            parsed.insert(path.clone(), doc.clone());
+            let Parsed {
+                meta, words, text, ..
+            } = doc;
+            let Some(meta) = meta else {
+                continue;
+            };
+            let path = path
+                .strip_prefix(content_path)
+                .expect("is a subdir of content path");
+            let section = path
+                .components()
+                .next()
+                .map(|x| x.as_os_str())
+                .map(|x| x.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            let path = path.to_str().unwrap().to_string();
+            let section = if section == path {
+                String::new()
+            } else {
+                section
+            };
+
+            let url = document_url(&path, meta.slug.as_deref());
+            if let Some(other) = index.iter().find(|x| x.url == url) {
+                error!(
+                    "\"{path}\" and \"{}\" are both served at \"{url}\", ignoring the former",
+                    other.path
+                );
+                continue;
+            }
+
+            texts.insert(path.clone(), text);
+            index.push(IndexEntry {
+                meta,
+                section,
+                path,
+                url,
+                git: None,
+                words,
+                linked_from: vec![],
+            });
+        }
+        if previous.is_some() {
+            debug!("Reused {reused} unchanged documents");
+        }
+
+        sections.retain(|s| {
+            index.iter().any(|i| i.section == s.name && !i.meta.draft)
+        });
+        if !sections.is_empty() {
+            let ignored = gitignore::filter_ignored(
+                content_path,
+                &sections.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(),
+            );
+            debug!("Removing ignored sections: {ignored:?}");
+            sections
+                .retain(|s| !ignored.iter().any(|x| *x == Path::new(&s.name)));
+        }
+
+        if !index.is_empty() {
+            let ignored = gitignore::filter_ignored(
+                content_path,
+                &index.iter().map(|x| x.path.as_str()).collect::<Vec<_>>(),
+            );
+            debug!("Removing ignored documents from the index: {ignored:?}");
+            index.retain(|i| !ignored.iter().any(|x| *x == Path::new(&i.path)));
+        }
+
+        // Git is asked for the dates documents don't give, and about the
+        // rest only if their history is shown. What it says is kept until
+        // the document changes or there's a new commit.
+        let prefix = found_git.then(|| git_prefix(content_path)).flatten();
+        let head = prefix.as_ref().and_then(|_| git_head(content_path));
+        for entry in &mut index {
+            let undated =
+                entry.meta.date.is_none() || entry.meta.updated.is_none();
+            let mut doc = parsed.get_mut(&content_path.join(&entry.path));
+            let git = match (&prefix, &head) {
+                (Some(prefix), Some(head))
+                    if undated || options.git_metadata =>
+                {
+                    let cached = doc
+                        .as_ref()
+                        .and_then(|x| x.git.as_ref())
+                        .filter(|(at, _)| at == head);
+                    match cached {
+                        Some((_, git)) => git.clone(),
+                        None => {
+                            let git = git_metadata(
+                                content_path,
+                                prefix,
+                                &entry.path,
+                                options.repo_url.as_deref(),
+                            )
+                            .unwrap_or_else(|e| {
+                                error!(
+                                    "Failed to get git metadata for \"{}\": {e}",
+                                    entry.path
+                                );
+                                None
+                            });
+                            if let Some(doc) = &mut doc {
+                                doc.git = Some((head.clone(), git.clone()));
+                            }
+                            git
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let modified = doc.map(|x| x.modified);
+            let meta = &mut entry.meta;
+            if meta.date.is_none() {
+                meta.date = match &git {
+                    Some(git) => Some(git.created),
+                    None => modified
+                        .map(|x| DateTime::<Local>::from(x).date_naive()),
+                };
+            }
+            if meta.updated.is_none() {
+                // Documents that haven't changed since they were published
+                // weren't updated.
+                meta.updated = git
+                    .as_ref()
+                    .map(|x| x.date)
+                    .filter(|x| Some(*x) != meta.date);
+            }
+            if options.git_metadata {
+                entry.git = git;
+            }
+        }
+
+        sections.push(Section::default()); // Blank is the root index
+        sections.sort_by(|a, b| {
+            (!a.name.is_empty(), a.config.order, &a.name).cmp(&(
+                !b.name.is_empty(),
+                b.config.order,
+                &b.name,
+            ))
+        });
+        let (drafts, mut index): (Vec<_>, Vec<_>) =
+            index.into_iter().partition(|x| x.meta.draft);
+        index.sort_by_key(|x| std::cmp::Reverse(x.meta.date));
+
+        // Links can only be resolved once everything is indexed.
+        let mut linked_from = HashMap::<String, Vec<String>>::new();
+        for entry in &index {
+            let Some(doc) = parsed.get(&content_path.join(&entry.path)) else {
+                continue;
+            };
+            let links = Links {
+                from: &entry.path,
+                index: &index,
+            };
+            for target in doc.links.iter().filter_map(|x| links.target(x)) {
+                let from = linked_from.entry(target.path.clone()).or_default();
+                if target.path != entry.path && !from.contains(&entry.path) {
+                    from.push(entry.path.clone());
+                }
+            }
+        }
+        for entry in &mut index {
+            entry.linked_from =
+                linked_from.remove(&entry.path).unwrap_or_default();
+        }
+
+        let mut search = search::SearchIndex::default();
+        for entry in &index {
+            if let Some(text) = texts.remove(&entry.path) {
+                search.insert(entry, text);
+            }
+        }
+
+        let mut tags = std::collections::BTreeMap::<String, usize>::new();
+        for tag in index.iter().flat_map(|x| &x.meta.tags) {
+            *tags.entry(tag.clone()).or_default() += 1;
+        }
+        Ok(State {
+            config,
+            content_path: content_path.to_path_buf(),
+            sections,
+            index,
+            drafts,
+            tags: tags.into_iter().collect(),
+            search,
+            cache: cache::RenderCache::new(options.cache_size),
+            templates,
+            highlight_css,
+            highlight_url,
+            not_found,
+            landings,
+            config_modified,
+            loaded: SystemTime::now(),
+            parsed,
+            mentions,
+            activitypub,
+        })
+    }
+}
+
+fn walk(
+    p: impl AsRef<std::path::Path>,
+    callback: &mut dyn FnMut(bool, &std::path::Path) -> std::io::Result<bool>,
+) -> Result<(), std::io::Error> {
+    let dir = p.as_ref();
+    if dir.is_dir() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_dir() {
+                if callback(true, &path)? {
+                    walk(path, callback)?;
+                }
+            } else {
+                callback(false, &path)?;
+            }
+        }
+    } else {
+        // We don't want to ignore the first item if it's a file
+        callback(false, dir)?;
+    }
+    Ok(())
+}
+
+#[derive(Template)]
+#[template(ext = "html", path = "header.html")]
+pub struct HeaderTemplate<'a> {
+    site: &'a SiteConfig,
+    sections: &'a [Section],
+    templates: &'a overrides::Overrides,
+    /// Where pages link to the stylesheet for highlighted code.
+    highlight_url: &'a str,
+    /// Where shortcodes include files from.
+    content_path: &'a Path,
+    /// What's listed under documents as linking to them.
+    mentions: &'a webmention::Mentions,
+}
+
+impl<'a> HeaderTemplate<'a> {
+    pub fn new(state: &'a State) -> Self {
+        Self {
+            site: &state.config,
+            sections: &state.sections,
+            templates: &state.templates,
+            highlight_url: &state.highlight_url,
+            content_path: &state.content_path,
+            mentions: &state.mentions,
+        }
+    }
+
+    /// Render the header, using the override if there is one.
+    fn html(&self) -> String {
+        let sections = self
+            .sections
+            .iter()
+            .map(|x| {
+                minijinja::context! {
+                    name => x.name,
+                    title => x.title(),
+                    config => x.config,
+                }
+            })
+            .collect::<Vec<_>>();
+        let context = minijinja::context! {
+            site => self.site,
+            sections => sections,
+        };
+        self.templates
+            .render("header.html", context)
+            .or_else(|| render(self, "header.html"))
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Template)]
+#[template(ext = "html", escape = "none", path = "index.html")]
+struct IndexTemplate<'a> {
+    header: String,
+    site: &'a SiteConfig,
+    styles: &'static str,
+    /// Shown above the list, e.g. for tag pages.
+    heading: Option<&'a str>,
+    description: Option<&'a str>,
+    /// The section's landing, shown above the list on its first page.
+    landing: Option<&'a str>,
+    feed: &'a str,
+    /// The URL search engines are told to use, if there's a `base_url`.
+    canonical: Option<String>,
+    docs: &'a [IndexTemplateEntryData<'a>],
+    pagination: Option<Pagination>,
+}
+
+/// Where an index page is in its index, only given when there's more than
+/// one page.
+#[derive(Serialize)]
+struct Pagination {
+    page: usize,
+    pages: usize,
+    /// The URL of the page with newer documents.
+    prev: Option<String>,
+    /// The URL of the page with older documents.
+    next: Option<String>,
+}
+
+/// The URL of page `page` (counting from 1) of `section`'s index.
+fn index_url(section: &str, page: usize) -> String {
+    match (section.is_empty(), page) {
+        (true, 1) => "/index.html".to_string(),
+        (true, _) => format!("/page/{page}/index.html"),
+        (false, 1) => format!("/{section}/index.html"),
+        (false, _) => format!("/{section}/page/{page}/index.html"),
+    }
+}
+
+/// Split the path of an index page, without the leading slash and trailing
+/// `/index.html`, into the section and page, e.g. `blog/page/2` into
+/// `("blog", 2)`.
+fn parse_index_path(path: &str) -> (&str, Option<usize>) {
+    let paged = path.rsplit_once('/').and_then(|(rest, page)| {
+        let section = match rest {
+            "page" => "",
+            _ => rest.strip_suffix("/page")?,
+        };
+        Some((section, page.parse().ok().filter(|x| *x > 0)))
+    });
+    paged.unwrap_or((path, Some(1)))
+}
+#[derive(Serialize)]
+struct IndexTemplateEntryData<'a> {
+    meta: &'a Meta,
+    section: &'a str,
+    section_title: &'a str,
+    url: &'a str,
+    words: usize,
+    /// In minutes.
+    reading_time: usize,
+}
+
+impl<'a> IndexTemplateEntryData<'a> {
+    fn new(state: &'a State, ie: &'a IndexEntry) -> Self {
+        Self {
+            meta: &ie.meta,
+            section: ie.section.as_str(),
+            section_title: state
+                .section(&ie.section)
+                .map_or(ie.section.as_str(), Section::title),
+            url: ie.url.as_str(),
+            words: ie.words,
+            reading_time: state.config.reading_time(ie.words),
+        }
+    }
+}
+
+impl IndexTemplate<'_> {
+    /// The number of pages in the index of `section`.
+    fn pages(state: &State, section: Option<&str>) -> usize {
+        let count = Self::entries(state, section).count();
+        match state.config.page_size() {
+            0 => 1,
+            size => count.div_ceil(size).max(1),
+        }
+    }
+
+    fn entries<'a>(
+        state: &'a State,
+        section: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a IndexEntry> {
+        let path = match section {
+            Some(section) => format!("/{section}/index.html"),
+            None => "/index.html".to_string(),
+        };
+        state
+            .index
+            .iter()
+            .filter(move |x| match section {
+                Some(section) => x.section == section,
+                None => !state.is_hidden(&x.section),
+            })
+            .filter(move |x| state.is_listed_at(x, &path))
+    }
+
+    /// Page `page` (counting from 1) of the index of `section`, or `None` if
+    /// there's no such page.
+    fn index(
+        state: &State,
+        section: Option<&str>,
+        page: usize,
+    ) -> Option<String> {
+        let pages = Self::pages(state, section);
+        if page == 0 || page > pages {
+            return None;
+        }
+        let size = match state.config.page_size() {
+            0 => usize::MAX,
+            size => size,
+        };
+        let docs: Vec<IndexTemplateEntryData> = Self::entries(state, section)
+            .skip((page - 1) * size)
+            .take(size)
+            .map(|x| IndexTemplateEntryData::new(state, x))
+            .collect();
+        let pagination = (pages > 1).then(|| {
+            let name = section.unwrap_or_default();
+            Pagination {
+                page,
+                pages,
+                prev: (page > 1).then(|| index_url(name, page - 1)),
+                next: (page < pages).then(|| index_url(name, page + 1)),
+            }
+        });
+        let feed = match section {
+            Some(section) => format!("/{section}/feed.xml"),
+            None => "/feed.xml".to_string(),
+        };
+        let config = section.and_then(|x| state.section(x)).map(|x| &x.config);
+        let landing = state.landings.get(section.unwrap_or_default());
+        Self::render_docs(
+            state,
+            landing
+                .and_then(|x| x.title.as_deref())
+                .or(config.and_then(|x| x.title.as_deref())),
+            landing
+                .and_then(|x| x.desc.as_deref())
+                .or(config.and_then(|x| x.description.as_deref())),
+            landing.map(|x| x.html.as_str()).filter(|_| page == 1),
+            &feed,
+            &index_url(section.unwrap_or_default(), page),
+            &docs,
+            pagination,
+        )
+    }
+
+    /// The index of documents tagged with `tag`, or `None` if no document is.
+    fn tag(state: &State, tag: &str) -> Option<String> {
+        let docs: Vec<IndexTemplateEntryData> = state
+            .index
+            .iter()
+            .filter(|x| x.meta.tags.iter().any(|t| t == tag))
+            .filter(|x| state.is_listed_at(x, "/tags/"))
+            .map(|x| IndexTemplateEntryData::new(state, x))
+            .collect();
+        if docs.is_empty() {
+            return None;
+        }
+        let heading = format!("Tagged \u{201c}{tag}\u{201d}");
+        Self::render_docs(
+            state,
+            Some(&heading),
+            None,
+            None,
+            "/feed.xml",
+            &format!("/tags/{tag}/index.html"),
+            &docs,
+            None,
+        )
+    }
+
+    /// Render an index page, which is at `path`, or `None` if it can't be.
+    #[allow(clippy::too_many_arguments)]
+    fn render_docs(
+        state: &State,
+        heading: Option<&str>,
+        description: Option<&str>,
+        landing: Option<&str>,
+        feed: &str,
+        path: &str,
+        docs: &[IndexTemplateEntryData],
+        pagination: Option<Pagination>,
+    ) -> Option<String> {
+        let template = IndexTemplate {
+            header: HeaderTemplate::new(state).html(),
+            site: &state.config,
+            styles: fingerprint::styles(),
+            heading,
+            description,
+            landing,
+            feed,
+            canonical: state.config.canonical(path),
+            docs,
+            pagination,
+        };
+
+        let context = minijinja::context! {
+            header => overrides::safe(template.header.as_str()),
+            site => template.site,
+            language => template.site.language(),
+            styles => overrides::safe(template.styles),
+            heading => template.heading,
+            description => template.description,
+            landing => template.landing.map(overrides::safe),
+            feed => template.feed,
+            canonical => template.canonical,
+            docs => template.docs,
+            pagination => template.pagination,
+        };
+        state
+            .templates
+            .render("index.html", context)
+            .or_else(|| render(&template, "index.html"))
+    }
+}
+
+#[derive(Template)]
+#[template(ext = "html", path = "search.html")]
+struct SearchTemplate<'a> {
+    header: String,
+    site: &'a SiteConfig,
+    styles: &'static str,
+    query: &'a str,
+    results: &'a [search::SearchResult<'a>],
+}
+
+impl SearchTemplate<'_> {
+    fn search(state: &State, query: &str) -> Option<String> {
+        let mut results = state.search.search(&state.index, query);
+        results.retain(|x| state.is_listed_at(x.entry, "/search"));
+        let template = SearchTemplate {
+            header: HeaderTemplate::new(state).html(),
+            site: &state.config,
+            styles: fingerprint::styles(),
+            query,
+            results: &results,
+        };
+
+        render(&template, "search.html")
+    }
+}
+
+#[derive(Template)]
+#[template(ext = "html", path = "tags.html")]
+struct TagsTemplate<'a> {
+    header: String,
+    site: &'a SiteConfig,
+    styles: &'static str,
+    tags: &'a [(String, usize)],
+    /// The URL search engines are told to use, if there's a `base_url`.
+    canonical: Option<String>,
+}
+
+impl TagsTemplate<'_> {
+    fn tags(state: &State) -> Option<String> {
+        let template = TagsTemplate {
+            header: HeaderTemplate::new(state).html(),
+            site: &state.config,
+            styles: fingerprint::styles(),
+            tags: &state.tags,
+            canonical: state.config.canonical("/tags/index.html"),
+        };
+
+        render(&template, "tags.html")
+    }
+}
+
+#[derive(Template)]
+#[template(ext = "html", path = "error.html")]
+struct ErrorTemplate<'a> {
+    header: String,
+    site: &'a SiteConfig,
+    styles: &'static str,
+    status: u16,
+    reason: &'static str,
+    /// Replaces the default message.
+    body: Option<&'a str>,
+    /// Documents with URLs like the one that wasn't found.
+    suggestions: Vec<Neighbour>,
+    /// The ID the request was logged with, for reporting server errors.
+    request_id: Option<u64>,
+}
+
+impl<'a> ErrorTemplate<'a> {
+    fn new(state: &'a State, status: u16) -> Self {
+        ErrorTemplate {
+            header: HeaderTemplate::new(state).html(),
+            site: &state.config,
+            styles: fingerprint::styles(),
+            status,
+            reason: StatusCode(status).default_reason_phrase(),
+            body: state.not_found.as_deref().filter(|_| status == 404),
+            suggestions: vec![],
+            request_id: logging::request_id().filter(|_| status >= 500),
+        }
+    }
+
+    /// The page for responses with the error `status`.
+    fn error(state: &State, status: u16) -> String {
+        ErrorTemplate::new(state, status).html()
+    }
+
+    /// The 404 page for `path`, suggesting the documents it might have meant.
+    fn not_found(state: &State, path: &str) -> String {
+        let mut template = ErrorTemplate::new(state, 404);
+        template.suggestions = suggest::similar(state, path)
+            .into_iter()
+            .map(Neighbour::new)
+            .collect();
+        template.html()
+    }
+
+    /// The page, or just the reason if it can't be rendered.
+    fn html(&self) -> String {
+        render(self, "error.html").unwrap_or_else(|| self.reason.to_string())
+    }
+}
+
+/// Respond with the error page for `status`.
+fn error_response(state: &State, status: u16) -> ResponseBox {
+    html_error(ErrorTemplate::error(state, status), status)
+}
+
+/// Respond with the 404 page for `path`.
+fn not_found(state: &State, path: &str) -> ResponseBox {
+    html_error(ErrorTemplate::not_found(state, path), 404)
+}
+
+/// Render the built-in `template`, called `name` in the log if it fails.
+fn render(template: &impl Template, name: &str) -> Option<String> {
+    template
+        .render()
+        .inspect_err(|e| error!("Failed to render \"{name}\": {e}"))
+        .ok()
+}
+
+fn html_error(html: String, status: u16) -> ResponseBox {
+    Response::from_data(html.into_bytes())
+        .with_status_code(status)
+        .with_header(http::make_header(
+            "Content-Type",
+            "text/html; charset=utf-8",
+        ))
+        .boxed()
+}
+
+#[derive(Template)]
+#[template(ext = "html", path = "stats.html")]
+struct StatsTemplate<'a> {
+    header: String,
+    site: &'a SiteConfig,
+    styles: &'static str,
+    /// The most viewed documents' URLs, titles and view counts.
+    top: Vec<(String, &'a str, u64)>,
+    recent: Vec<stats::View>,
+    pool: pool::Snapshot,
+}
+
+impl StatsTemplate<'_> {
+    /// How many of the most viewed documents are listed.
+    const TOP: usize = 20;
+
+    /// The stats of the site for `host`, whose state is `state`.
+    fn stats(
+        state: &State,
+        stats: &stats::Stats,
+        pool: pool::Snapshot,
+        host: Option<&str>,
+    ) -> Option<String> {
+        let top = stats
+            .top(host, Self::TOP)
+            .into_iter()
+            .map(|(url, count)| {
+                let title = state
+                    .index
+                    .iter()
+                    .find(|x| x.url == url)
+                    .map_or("", |x| x.meta.title.as_str());
+                (url, title, count)
+            })
+            .collect();
+        let template = StatsTemplate {
+            header: HeaderTemplate::new(state).html(),
+            site: &state.config,
+            styles: fingerprint::styles(),
+            top,
+            recent: stats.recent(host),
+            pool,
+        };
+
+        render(&template, "stats.html")
+    }
+}
+
+/// Settings for the serve threads.
+#[derive(Debug)]
+struct ServeOptions {
+    /// Views of the documents served.
+    stats: stats::Stats,
+    /// How busy the serve threads are.
+    pool: Arc<pool::Usage>,
+    preview_token: Option<String>,
+    /// Believe the `X-Forwarded-*` headers.
+    trust_proxy: bool,
+    rate_limiter: Option<ratelimit::RateLimiter>,
+    /// The addresses that can make requests to every site.
+    access: access::Access,
+    /// How long a request has to be answered.
+    request_timeout: Option<Duration>,
+    /// The path the sites are served under, e.g. `/blog`, or `""`.
+    base_path: String,
+    hooks: server::Hooks,
+}
+
+impl ServeOptions {
+    /// The value of a forwarded header, if the proxy is trusted. Proxies
+    /// append to these, so the first value is the original one.
+    fn forwarded<'a>(&self, rq: &'a Request, name: &str) -> Option<&'a str> {
+        if !self.trust_proxy {
+            return None;
+        }
+        http::header(rq, name)
+            .and_then(|x| x.split(',').next())
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+    }
+
+    /// The URL the client requested, or `None` if the request is missing its
+    /// host or is otherwise invalid.
+    fn request_url(&self, rq: &Request) -> Option<Url> {
+        // The host header is required: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Host
+        let host = self
+            .forwarded(rq, "X-Forwarded-Host")
+            .or_else(|| http::header(rq, "Host"))?;
+        let scheme = match self.forwarded(rq, "X-Forwarded-Proto") {
+            Some("https") => "https",
+            _ => "http",
+        };
+        // Tiny URL gives me a fake URL, so I have to first construct a URL,
+        // then deconstruct it.
+        // Routes are matched without the base path.
+        let path = base_path::strip(rq.url(), &self.base_path);
+        let url = format!("{scheme}://{host}{path}");
+        Url::parse(&url)
+            .inspect_err(|e| error!("Invalid URL \"{url}\": {e}"))
+            .ok()
+    }
+
+    /// The address of the client, or of the proxy if it isn't trusted.
+    fn client_addr(&self, rq: &Request) -> Option<std::net::IpAddr> {
+        self.forwarded(rq, "X-Forwarded-For")
+            .and_then(|x| x.parse().ok())
+            .or_else(|| rq.remote_addr().map(|x| x.ip()))
+    }
+
+    /// Whether the request carries the preview token, allowing it to see
+    /// drafts.
+    fn is_preview(&self, rq: &Request, url: &Url) -> bool {
+        let Some(token) = &self.preview_token else {
+            return false;
+        };
+        http::header(rq, "X-Preview-Token")
+            .into_iter()
+            .map(Cow::Borrowed)
+            .chain(
+                url.query_pairs()
+                    .filter(|(k, _)| k == "preview")
+                    .map(|(_, v)| v),
+            )
+            .any(|x| constant_time_eq(&x, token))
+    }
+}
+
+/// Accept requests and queue them for the pool, answering `503 Service
+/// Unavailable` while it's saturated.
+fn accept(
+    server: &tiny_http::Server,
+    pool: &pool::Pool,
+    control: &Control,
+    timeouts: Option<&listen::Timeouts>,
+) -> eyre::Result<()> {
+    loop {
+        let rq = match server.recv() {
+            Ok(rq) => rq,
+            // `recv` fails when the server is unblocked for shutdown.
+            Err(_) if control.shutdown.load(Ordering::Relaxed) => return Ok(()),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock
+                        | std::io::ErrorKind::Interrupted
+                ) =>
+            {
+                warn!("Failed to accept a connection: {e}");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let (Some(timeouts), Some(peer)) = (timeouts, rq.remote_addr()) {
+            timeouts.apply(*peer);
+        }
+        if let Err(rq) = pool.submit(rq) {
+            logging::begin_request();
+            respond(
+                rq,
+                Response::new_empty(StatusCode(503))
+                    .with_header(http::make_header("Retry-After", "1")),
+            );
+        }
+    }
+}
+
+fn serve(
+    rq: Request,
+    sites: &[Site],
+    control: &Control,
+    options: &ServeOptions,
+) {
+    let started = Instant::now();
+    logging::begin_request();
+    timeout::begin(started, options.request_timeout);
+    base_path::begin(&options.base_path);
+    let Some(url) = options.request_url(&rq) else {
+        respond(rq, Response::new_empty(StatusCode(400)));
+        return;
+    };
+    let site = Site::for_host(sites, url.host_str());
+    let (state, content_dir) = (&site.state, &site.content_path);
+    {
+        let config = &state.read().unwrap().config;
+        security::begin(&config.security_headers, &config.mermaid);
+    }
+    let client = options.client_addr(&rq);
+    access_log::begin(started, client);
+    debug!(
+        "{} {} {url}",
+        client.map_or_else(|| "-".to_string(), |x| x.to_string()),
+        rq.method(),
+    );
+
+    if let Some(limiter) = &options.rate_limiter
+        && let Some(client) = client
+        && let Err(wait) = limiter.check(client)
+    {
+        debug!("Rate limited {client}");
+        let retry_after = wait.as_secs_f64().ceil().max(1.0).to_string();
+        respond(
+            rq,
+            Response::new_empty(StatusCode(429))
+                .with_header(http::make_header("Retry-After", &retry_after)),
+        );
+        return;
+    }
+
+    for hook in &options.hooks.request {
+        if let Some(response) = hook(&rq) {
+            respond(rq, response);
+            return;
+        }
+    }
+
+    // Redirect to the canonical form of the path so that e.g.
+    // `/blog//post.md`, `/blog/./post.md` and `/blog/post.md/` resolve to
+    // `/blog/post.md`, and to the canonical host. The admin endpoints are left
+    // alone, they're often used from the server itself.
+    let normalized = http::normalize_path(url.path());
+    let host = if access::is_admin(&normalized) {
+        None
+    } else {
+        let config = &state.read().unwrap().config;
+        config.redirect_host(&url).map(str::to_string)
+    };
+    if normalized != url.path() || host.is_some() {
+        let mut location = host.unwrap_or_default() + &normalized;
+        if let Some(query) = url.query() {
+            location = format!("{location}?{query}");
+        }
+        respond(
+            rq,
+            Response::new_empty(StatusCode(308))
+                .with_header(http::make_header("Location", &location)),
+        );
+        return;
+    }
+
+    // Documents and tags are matched by their names, which may contain
+    // spaces and other characters that are encoded in the URL.
+    let path =
+        match percent_encoding::percent_decode_str(url.path()).decode_utf8() {
+            Ok(path) => path,
+            Err(_) => {
+                respond(rq, Response::new_empty(StatusCode(400)));
+                return;
+            }
+        };
+    let path = path.as_ref();
+
+    {
+        let state_l = state.read().unwrap();
+        if !options.access.permits(client, path)
+            || !state_l.config.access.permits(client, path)
+        {
+            debug!("Denied access to {path}");
+            respond(rq, error_response(&state_l, 403));
+            return;
+        }
+    }
+
+    // `tiny_http` leaves the body out of responses to `HEAD` requests, so
+    // they're handled like `GET`s. The control, webhook, webmention and
+    // inbox endpoints check the method themselves.
+    if !matches!(rq.method(), Method::Get | Method::Head)
+        && !path.starts_with("/.control/")
+        && path != "/.reload"
+        && path != webmention::PATH
+        && path != activitypub::INBOX
+    {
+        respond(
+            rq,
+            Response::new_empty(StatusCode(405))
+                .with_header(http::make_header("Allow", "GET, HEAD")),
+        );
+        return;
+    }
+
+    // The control and webhook endpoints have their own credentials.
+    if !access::is_admin(path) {
+        let checked =
+            auth::check(&rq, &state.read().unwrap().config.protected, path);
+        if let Err(response) = checked {
+            respond(rq, response);
+            return;
+        }
+    }
+
+    // Old URLs of moved documents and pages.
+    if let Some(location) = state.read().unwrap().redirect(path) {
+        respond(
+            rq,
+            Response::new_empty(StatusCode(301))
+                .with_header(http::make_header("Location", &location)),
+        );
+        return;
+    }
+
+    match path {
+        "/" => {
+            respond(
+                rq,
+                Response::new_empty(StatusCode(308)).with_header(
+                    Header::from_bytes(b"location", b"/index.html").unwrap(),
+                ),
+            );
+            return;
+        }
+        "/tags/index.html" => {
+            let state_l = state.read().unwrap();
+            let Some(html) = TagsTemplate::tags(&state_l) else {
+                respond(rq, error_response(&state_l, 500));
+                return;
+            };
+            let response = content_response(
+                &rq,
+                &state_l.config,
+                html.into_bytes(),
+                Some("text/html; charset=utf-8"),
+                None,
+            );
+            respond(rq, response);
+            return;
+        }
+        _ if path.starts_with("/tags/") && path.ends_with("/index.html") => {
+            let tag = &path["/tags/".len()..path.len() - "/index.html".len()];
+            let Some(html) = IndexTemplate::tag(&state.read().unwrap(), tag)
+            else {
+                respond(rq, not_found(&state.read().unwrap(), path));
+                return;
+            };
+            let response = content_response(
+                &rq,
+                &state.read().unwrap().config,
+                html.into_bytes(),
+                Some("text/html; charset=utf-8"),
+                None,
+            );
+            respond(rq, response);
+            return;
+        }
+        "/search" => {
+            let query = url
+                .query_pairs()
+                .find(|(k, _)| k == "q")
+                .map(|(_, v)| v)
+                .unwrap_or_default();
+            let state_l = state.read().unwrap();
+            let Some(html) = SearchTemplate::search(&state_l, &query) else {
+                respond(rq, error_response(&state_l, 500));
+                return;
+            };
+            let response = content_response(
+                &rq,
+                &state_l.config,
+                html.into_bytes(),
+                Some("text/html; charset=utf-8"),
+                None,
+            );
+            respond(rq, response);
+            return;
+        }
+        "/index.html" => {
+            let state_l = state.read().unwrap();
+            // The root index always has a first page.
+            let Some(html) = IndexTemplate::index(&state_l, None, 1) else {
+                respond(rq, error_response(&state_l, 500));
+                return;
+            };
+            let response = content_response(
+                &rq,
+                &state_l.config,
+                html.into_bytes(),
+                Some("text/html; charset=utf-8"),
+                None,
+            );
+            respond(rq, response);
+            return;
+        }
+        "/sitemap.xml" => {
+            let origin = base_path::origin(&url, &options.base_path);
+            let state_l = state.read().unwrap();
+            let Some(sitemap) = sitemap::sitemap(&state_l, &origin) else {
+                respond(rq, error_response(&state_l, 500));
+                return;
+            };
+            let response = content_response(
+                &rq,
+                &state_l.config,
+                sitemap.into_bytes(),
+                Some("application/xml; charset=utf-8"),
+                None,
+            );
+            respond(rq, response);
+            return;
+        }
+        "/.graph.json" => {
+            let graph = graph_json(&state.read().unwrap());
+            let response = content_response(
+                &rq,
+                &state.read().unwrap().config,
+                graph.into_bytes(),
+                Some("application/json"),
+                None,
+            );
+            respond(rq, response);
+            return;
+        }
+        "/robots.txt" => {
+            let origin = base_path::origin(&url, &options.base_path);
+            let robots = sitemap::robots(&state.read().unwrap(), &origin);
+            let response = content_response(
+                &rq,
+                &state.read().unwrap().config,
+                robots.into_bytes(),
+                Some("text/plain; charset=utf-8"),
+                None,
+            );
+            respond(rq, response);
+            return;
+        }
+        _ if path.ends_with("/feed.xml") => {
+            let section = path
+                .strip_suffix("/feed.xml")
+                .unwrap()
+                .trim_start_matches('/');
+            let origin = base_path::origin(&url, &options.base_path);
+            let feed = feed::atom(&state.read().unwrap(), &origin, section);
+            let Some(feed) = feed else {
+                respond(rq, error_response(&state.read().unwrap(), 404));
+                return;
+            };
+            let mut response = content_response(
+                &rq,
+                &state.read().unwrap().config,
+                feed.into_bytes(),
+                Some("application/atom+xml; charset=utf-8"),
+                None,
+            );
+            if let Some(link) =
+                feed::hub_header(&state.read().unwrap(), &origin, path)
+            {
+                response.add_header(link);
+            }
+            respond(rq, response);
+            return;
+        }
+        _ if path.ends_with("/feed.json") => {
+            let section = path
+                .strip_suffix("/feed.json")
+                .unwrap()
+                .trim_start_matches('/');
+            let origin = base_path::origin(&url, &options.base_path);
+            let state_l = state.read().unwrap();
+            let response = match feed::json(&state_l, &origin, section) {
+                Some(feed) => {
+                    let mut response = content_response(
+                        &rq,
+                        &state_l.config,
+                        feed.into_bytes(),
+                        Some("application/feed+json; charset=utf-8"),
+                        None,
+                    );
+                    if let Some(link) =
+                        feed::hub_header(&state_l, &origin, path)
+                    {
+                        response.add_header(link);
+                    }
+                    response
+                }
+                None => error_response(&state_l, 404),
+            };
+            respond(rq, response);
+            return;
+        }
+        _ if path.ends_with("/index.html") => {
+            let (section, page) = parse_index_path(
+                &path.strip_suffix("/index.html").unwrap()[1..],
+            );
+            let state_l = state.read().unwrap();
+            let section = (!section.is_empty()).then_some(section);
+            let Some(html) =
+                page.and_then(|x| IndexTemplate::index(&state_l, section, x))
+            else {
+                respond(rq, not_found(&state_l, path));
+                return;
+            };
+            let response = content_response(
+                &rq,
+                &state.read().unwrap().config,
+                html.into_bytes(),
+                Some("text/html; charset=utf-8"),
+                None,
+            );
+            respond(rq, response);
+            return;
+        }
+        _ if path.starts_with("/.og/") => {
+            let state_l = state.read().unwrap();
+            let png = og::document_url(path)
+                .and_then(|url| state_l.index.iter().find(|x| x.url == url))
+                .filter(|x| state_l.is_listed_at(x, path))
+                .filter(|x| state_l.config.og.enabled && x.meta.cover.is_none())
+                .and_then(|entry| {
+                    og::png(
+                        &state_l.config,
+                        &state_l.templates,
+                        content_dir,
+                        entry,
+                    )
+                });
+            let response = match png {
+                Some(png) => content_response(
+                    &rq,
+                    &state_l.config,
+                    png.to_vec(),
+                    Some("image/png"),
+                    None,
+                ),
+                None => error_response(&state_l, 404),
+            };
+            respond(rq, response);
+            return;
+        }
+        _ if path.starts_with(images::PREFIX) => {
+            let state_l = state.read().unwrap();
+            let response = match images::variant(
+                content_dir,
+                &path[images::PREFIX.len()..],
+                &state_l.config.images,
+            ) {
+                Some(image) => content_response(
+                    &rq,
+                    &state_l.config,
+                    image,
+                    Some(http::content_type(Path::new(path))),
+                    None,
+                ),
+                None => error_response(&state_l, 404),
+            };
+            respond(rq, response);
+            return;
+        }
+        _ if path.starts_with("/assets/") => {
+            let state_l = state.read().unwrap();
+            serve_user_asset(
+                rq,
+                &state_l,
+                content_dir,
+                &path["/assets/".len()..],
+                &url,
+            );
+            return;
+        }
+        "/.reload" => {
+            control.webhook(rq);
+            return;
+        }
+        webmention::PATH => {
+            let origin = base_path::origin(&url, &options.base_path);
+            webmention::receive(rq, state, &origin);
+            return;
+        }
+        _ if path == activitypub::WEBFINGER
+            || path.starts_with(activitypub::PREFIX) =>
+        {
+            activitypub::serve(rq, state, path, &url);
+            return;
+        }
+        "/.stats" => {
+            control.stats(rq, site, options);
+            return;
+        }
+        _ if path.starts_with("/.control/") => {
+            control.handle(rq, &path["/.control/".len()..]);
+            return;
+        }
+        _ if path.starts_with("/.styles/highlight.") => {
+            let state_l = state.read().unwrap();
+            serve_highlight_css(rq, &state_l, path);
+            return;
+        }
+        _ if path.starts_with("/.static-assets")
+            || path.starts_with("/.styles") =>
+        {
+            let state_l = state.read().unwrap();
+            serve_embedded(rq, &state_l, path);
+            return;
+        }
+        _ => {}
+    }
+
+    let state_l = state.read().unwrap();
+
+    // Redirect the old URLs that had the file extension.
+    if let Some(entry) = state_l.index.iter().find(|x| x.path == path[1..]) {
+        respond(
+            rq,
+            Response::new_empty(StatusCode(308)).with_header(
+                http::make_header("Location", &http::encode_path(&entry.url)),
+            ),
+        );
+        return;
+    }
+
+    // Ensure we don't serve anything that hasn't been indexed, this way
+    // ignore files are honored.
+    let url_path = path.strip_suffix('/').unwrap_or(path);
+    let preview = options.is_preview(&rq, &url);
+    let drafts = if preview {
+        state_l.drafts.as_slice()
+    } else {
+        &[]
+    };
+    let Some(entry) = state_l
+        .index
+        .iter()
+        .chain(drafts)
+        .find(|x| x.url == url_path)
+    else {
+        let response = match listing::resolve(&state_l, content_dir, url_path) {
+            Some(listing::Listed::Directory(dir)) => {
+                match listing::html(&state_l, url_path, &dir) {
+                    Some(html) => content_response(
+                        &rq,
+                        &state_l.config,
+                        html.into_bytes(),
+                        Some("text/html; charset=utf-8"),
+                        None,
+                    ),
+                    None => error_response(&state_l, 500),
+                }
+            }
+            Some(listing::Listed::File(file)) => file_response(
+                &rq,
+                &state_l,
+                &file,
+                Some(http::content_type(&file)),
+            ),
+            None => not_found(&state_l, url_path),
+        };
+        respond(rq, response);
+        return;
+    };
+    let path = entry.path.as_str();
+
+    let path = match std::path::absolute(content_dir.join(path)) {
+        Err(_) => {
+            respond(rq, error_response(&state_l, 404));
+            return;
+        }
+        Ok(p) => p,
+    };
+
+    if !path.starts_with(content_dir)
+        || path
+            .file_name()
+            .is_some_and(|x| x.as_encoded_bytes().starts_with(b"."))
+        || !path.is_file()
+    {
+        respond(rq, error_response(&state_l, 404));
+        return;
+    }
+
+    info!("Responding to request for \"{}\"", path.display());
+    // HTML documents are served as they are unless they're wrapped.
+    let rendered = !html_pages::is_html(&path) || entry.meta.wrap;
+    if rendered {
+        if is_markdown(&path) && http::wants_markdown(&rq, &url) {
+            let mut response = file_response(
+                &rq,
+                &state_l,
+                &path,
+                Some("text/markdown; charset=utf-8"),
+            );
+            response.add_header(http::make_header("Vary", "Accept"));
+            if entry.meta.draft {
+                response
+                    .add_header(http::make_header("Cache-Control", "no-store"));
+            }
+            respond(rq, response);
+            return;
+        }
+
+        let modified = std::fs::metadata(&path).and_then(|x| x.modified());
+        // The page links to itself, so it's cached by its full URL in
+        // case the origin comes from the request.
+        let page_url = format!(
+            "{}{}",
+            state_l
+                .config
+                .base(&base_path::origin(&url, &options.base_path)),
+            entry.url
+        );
+        let cached = modified
+            .as_ref()
+            .ok()
+            .and_then(|m| state_l.cache.get(&page_url, *m));
+        let html = match cached {
+            Some(html) => html,
+            None => {
+                let contents = match read_document(&path) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        error!("Error getting \"{}\": {e}", path.display());
+                        respond(rq, error_response(&state_l, 500));
+                        return;
+                    }
+                };
+                let Rendered { html, .. } = renderer(&path)(
+                    &HeaderTemplate::new(&state_l),
+                    &contents,
+                    Some(&page_url),
+                    Some(&Links {
+                        from: &entry.path,
+                        index: &state_l.index,
+                    }),
+                    entry.git.as_ref(),
+                );
+                let Some(html) = html else {
+                    warn!("Failed to render \"{}\"", path.display());
+                    respond(rq, error_response(&state_l, 500));
+                    return;
+                };
+                let html: Arc<str> = html.into();
+                if let Ok(modified) = modified {
+                    state_l.cache.insert(&page_url, modified, html.clone());
+                }
+                html
+            }
+        };
+        // The page changes with the file and with what else it shows, which
+        // changes on reload or when a mention is received.
+        let last_modified = [
+            modified.as_ref().ok().copied(),
+            Some(state_l.loaded),
+            state_l.mentions.changed(),
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+        let mut response = content_response(
+            &rq,
+            &state_l.config,
+            html.as_bytes().to_vec(),
+            Some("text/html; charset=utf-8"),
+            last_modified,
+        );
+        response.add_header(http::make_header("Vary", "Accept"));
+        if entry.meta.draft {
+            response.add_header(http::make_header("Cache-Control", "no-store"));
+            response.add_header(http::make_header("X-Robots-Tag", "noindex"));
+        } else if *rq.method() == Method::Get {
+            options.stats.record(site.host.as_deref(), &entry.url);
+        }
+        respond(rq, response);
+        return;
+    }
+
+    let content_type = http::content_type(&path);
+    let response = file_response(&rq, &state_l, &path, Some(content_type));
+    respond(rq, response);
+}
+
+/// Respond with `data` as `content_type`, with validators for conditional
+/// requests and the site's `Cache-Control` policy for the type.
+fn content_response(
+    rq: &Request,
+    config: &SiteConfig,
+    data: Vec<u8>,
+    content_type: Option<&str>,
+    modified: Option<SystemTime>,
+) -> ResponseBox {
+    let mut headers = vec![];
+    if let Some(content_type) = content_type {
+        headers.push(http::make_header("Content-Type", content_type));
+    }
+    let path = http::request_path(rq);
+    if let Some(cache_control) = config.cache_control_for(&path, content_type) {
+        headers.push(http::make_header("Cache-Control", cache_control));
+    }
+    http::etag_response(rq, data, modified, headers)
+}
+
+/// Respond with the file at `path` like [`content_response`] does, streaming
+/// it instead of reading it into memory.
+fn file_response(
+    rq: &Request,
+    state: &State,
+    path: &Path,
+    content_type: Option<&str>,
+) -> ResponseBox {
+    let mut headers = vec![];
+    if let Some(content_type) = content_type {
+        headers.push(http::make_header("Content-Type", content_type));
+    }
+    if let Some(name) = path.file_name().and_then(|x| x.to_str())
+        && (http::wants_download(rq) || state.config.is_download(name))
+    {
+        headers.push(http::attachment(name));
+    }
+    let cache_control = state
+        .config
+        .cache_control_for(&http::request_path(rq), content_type);
+    if let Some(cache_control) = cache_control {
+        headers.push(http::make_header("Cache-Control", cache_control));
+    }
+    match std::fs::File::open(path)
+        .and_then(|x| http::file_response(rq, x, headers))
+    {
+        Ok(response) => response,
+        Err(e) => {
+            error!("Error getting \"{}\": {e}", path.display());
+            error_response(state, 500)
+        }
+    }
+}
+
+/// The content directory's `.assets/` (or `assets/`) directory, if it has
+/// one.
+fn assets_dir(content_dir: &Path) -> Option<PathBuf> {
+    [".assets", "assets"]
+        .into_iter()
+        .map(|x| content_dir.join(x))
+        .find(|x| x.is_dir())
+}
+
+/// Serve a file from the content directory's assets directory. `path` is
+/// relative to that directory. Text files are shown highlighted, and CSV and
+/// TSV files as tables, when a browser goes to them.
+fn serve_user_asset(
+    rq: Request,
+    state: &State,
+    content_dir: &Path,
+    path: &str,
+    url: &Url,
+) {
+    let path = assets_dir(content_dir).and_then(|dir| {
+        let path = std::fs::canonicalize(dir.join(path)).ok()?;
+        let hidden = path
+            .strip_prefix(dir)
+            .ok()?
+            .components()
+            .any(|x| x.as_os_str().as_encoded_bytes().starts_with(b"."));
+        (!hidden && path.is_file()).then_some(path)
+    });
+    let Some(path) = path else {
+        respond(rq, error_response(state, 404));
+        return;
+    };
+
+    let download = path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| state.config.is_download(x));
+    let shown = !download
+        && (tables::delimiter(&path).is_some()
+            || highlight::source_language(&path).is_some());
+    if shown
+        && http::wants_page(&rq, url)
+        && let Some(html) = asset_page(state, &path, url)
+    {
+        let mut response = content_response(
+            &rq,
+            &state.config,
+            html.as_bytes().to_vec(),
+            Some("text/html; charset=utf-8"),
+            std::fs::metadata(&path).and_then(|x| x.modified()).ok(),
+        );
+        response.add_header(http::make_header("Vary", "Accept"));
+        respond(rq, response);
+        return;
+    }
+
+    let content_type = http::content_type(&path);
+    let mut response = file_response(&rq, state, &path, Some(content_type));
+    if shown {
+        response.add_header(http::make_header("Vary", "Accept"));
+    }
+    respond(rq, response);
+}
+
+/// The page showing the file at `path`: a table if it's CSV or TSV, or else
+/// its text highlighted. `None` if it's too big or can't be read, in which
+/// case it's served as it is.
+fn asset_page(state: &State, path: &Path, url: &Url) -> Option<Arc<str>> {
+    const MAX_SIZE: u64 = 1024 * 1024;
+    let metadata = std::fs::metadata(path).ok()?;
+    if metadata.len() > MAX_SIZE {
+        return None;
+    }
+    let modified = metadata.modified().ok()?;
+    if let Some(html) = state.cache.get(url.as_str(), modified) {
+        return Some(html);
+    }
+    let contents = std::fs::read_to_string(path).ok()?;
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    let table = tables::delimiter(path);
+    let body = match table {
+        Some(delimiter) => format!(
+            "{}<p class=\"raw\"><a href=\"?download\">Download</a></p>",
+            tables::html(&contents, delimiter)?
+        ),
+        None => {
+            let fence = highlight::Fence {
+                title: Some(name.clone()),
+                line_numbers: true,
+                ..Default::default()
+            };
+            let lang = highlight::source_language(path)?;
+            format!(
+                "{}<p class=\"raw\"><a href=\"?raw=1\">Raw</a></p>",
+                highlight::html(&contents, lang, &fence)
+            )
+        }
+    };
+    let Rendered { html, .. } = document_page(
+        &HeaderTemplate::new(state),
+        Body {
+            html: body,
+            meta: Some(Meta {
+                title: name,
+                ..Meta::default()
+            }),
+            toc: vec![],
+            words: 0,
+            links: vec![],
+            mermaid: false,
+            code: table.is_none(),
+            embeds: false,
+            tables: table.is_some(),
+        },
+        None,
+        None,
+        None,
+    );
+    let html: Arc<str> = html?.into();
+    state.cache.insert(url.as_str(), modified, html.clone());
+    Some(html)
+}
+
+/// Serve the stylesheet for highlighted code, by its plain or fingerprinted
+/// URL.
+fn serve_highlight_css(rq: Request, state: &State, path: &str) {
+    let hash = match fingerprint::strip(path) {
+        Some((plain, hash)) if plain == HIGHLIGHT_CSS => Some(hash),
+        _ if path == HIGHLIGHT_CSS => None,
+        _ => {
+            respond(rq, error_response(state, 404));
+            return;
+        }
+    };
+    let css = state.highlight_css.clone().into_bytes();
+    let content_type = "text/css; charset=utf-8";
+    // Only the current hash can be cached forever, the themes may have
+    // changed since a page with an older one was loaded.
+    let response = if hash.is_some() && path == state.highlight_url {
+        http::etag_response(
+            &rq,
+            css,
+            None,
+            [
+                http::make_header("Content-Type", content_type),
+                http::make_header("Cache-Control", fingerprint::CACHE_CONTROL),
+            ],
+        )
+    } else {
+        content_response(&rq, &state.config, css, Some(content_type), None)
+    };
+    respond(rq, response);
+}
+
+/// Serve a file from one of the embedded directories, by its plain or
+/// fingerprinted URL.
+fn serve_embedded(rq: Request, state: &State, path: &str) {
+    let Some((file, immutable)) = fingerprint::lookup(path) else {
+        respond(rq, error_response(state, 404));
+        return;
+    };
+
+    let content_type = http::content_type(Path::new(path));
+    let cache_control = if immutable {
+        fingerprint::CACHE_CONTROL
+    } else {
+        state
+            .config
+            .cache_control_for(path, Some(content_type))
+            .unwrap_or(EMBEDDED_CACHE_CONTROL)
+    };
+    let response = http::bytes_response(
+        &rq,
+        &file.contents,
+        &file.etag,
+        [
+            http::make_header("Content-Type", content_type),
+            http::make_header("Cache-Control", cache_control),
+        ],
+    );
+    respond(rq, response);
+}
+
+#[derive(Template)]
+#[template(ext = "html", escape = "none", path = "document.html")]
+struct DocumentTemplate<'a> {
+    header: String,
+    site: &'a SiteConfig,
+    styles: &'static str,
+    /// The fingerprinted URL of the stylesheet for highlighted code.
+    highlight_url: &'a str,
+    meta: Meta,
+    /// The absolute URL of the document, if it's known.
+    url: Option<&'a str>,
+    /// The URL search engines are told to use, if there's a `base_url`.
+    canonical: Option<&'a str>,
+    /// The absolute URL of the document's cover image.
+    cover: Option<String>,
+    /// Who wrote the document, falling back to the site's author.
+    author: Option<String>,
+    author_url: Option<String>,
+    /// The document's schema.org `Article` as JSON-LD, for search engines.
+    json_ld: String,
+    /// The document's headings, if it asked for a table of contents.
+    toc: &'a [TocEntry],
+    /// Every language the document is in, if it's in more than one.
+    translations: Vec<Translation>,
+    /// The script that renders the document's diagrams, if it has any that
+    /// weren't rendered already.
+    mermaid_script: Option<&'a str>,
+    /// The script behind code blocks' copy buttons, if the document has any.
+    copy_script: Option<&'static str>,
+    /// The script that loads video embeds' players, if the document has any.
+    embed_script: Option<&'static str>,
+    /// The script that sorts tables, if the document has any sortable ones.
+    sort_script: Option<&'static str>,
+    git: Option<&'a GitMeta>,
+    /// The older document in the same section.
+    prev: Option<Neighbour>,
+    /// The newer document in the same section.
+    next: Option<Neighbour>,
+    /// The documents that link to this one.
+    linked_from: Vec<Neighbour>,
+    /// The pages on other sites that sent webmentions of this one.
+    mentions: Vec<webmention::Mention>,
+    words: usize,
+    /// In minutes.
+    reading_time: usize,
+    markdown: &'a str,
+    /// Whether the document's source is served with `?raw=1`, which only
+    /// markdown's is.
+    source: bool,
+}
+
+/// The links between documents as JSON: every document, by URL, and which
+/// link to which.
+fn graph_json(state: &State) -> String {
+    let listed = |x: &&IndexEntry| state.is_listed_at(x, "/.graph.json");
+    let nodes = state
+        .index
+        .iter()
+        .filter(listed)
+        .map(|x| {
+            serde_json::json!({
+                "url": x.url,
+                "title": x.meta.title,
+                "section": x.section,
+            })
+        })
+        .collect::<Vec<_>>();
+    let edges = state
+        .index
+        .iter()
+        .filter(listed)
+        .flat_map(|to| {
+            to.linked_from.iter().filter_map(|from| {
+                let from = state
+                    .index
+                    .iter()
+                    .filter(listed)
+                    .find(|x| x.path == *from)?;
+                Some(serde_json::json!({ "from": from.url, "to": to.url }))
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({ "nodes": nodes, "edges": edges }).to_string()
+}
+
+/// A document linked to from the bottom of another.
+#[derive(Debug, Serialize)]
+struct Neighbour {
+    title: String,
+    url: String,
+}
+
+impl Neighbour {
+    fn new(entry: &IndexEntry) -> Self {
+        Self {
+            title: entry.meta.title.clone(),
+            url: http::encode_path(&entry.url),
+        }
+    }
+}
+
+/// A version of a document in another language (or the document itself).
+#[derive(Debug, Serialize)]
+struct Translation {
+    lang: String,
+    /// Absolute if the document's URL is known.
+    url: String,
+    /// Whether this is the document being shown.
+    current: bool,
+}
+
+/// A heading in a document's table of contents.
+#[derive(Debug, Serialize)]
+struct TocEntry {
+    level: u8,
+    title: String,
+    id: String,
+}
+
+/// A document's metadata block.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Meta {
+    pub title: String,
+    /// When the document was published. Taken from its first commit, or the
+    /// file's modification time, if it isn't given.
+    #[serde(default, deserialize_with = "meta_date")]
+    pub date: Option<NaiveDate>,
+    /// When the document was last changed. Taken from its last commit if it
+    /// isn't given.
+    #[serde(default, deserialize_with = "meta_date")]
+    pub updated: Option<NaiveDate>,
+    pub lang: Option<String>,
+    pub desc: Option<String>,
+    /// Overrides the site's author.
+    pub author: Option<String>,
+    /// Where the byline links to, e.g. the author's home page.
+    pub author_url: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Replaces the file name (sans extension) in the document's URL.
+    pub slug: Option<String>,
+    /// Drafts aren't listed anywhere and can only be viewed with the preview
+    /// token.
+    #[serde(default)]
+    pub draft: bool,
+    /// An image shown when the document is shared, either an absolute URL or
+    /// a path on the site (e.g. `/assets/cover.png`).
+    pub cover: Option<String>,
+    /// Show a table of contents above the document.
+    #[serde(default)]
+    pub toc: bool,
+    /// Put an HTML document's body in the site's page instead of serving the
+    /// file as it is.
+    #[serde(default)]
+    pub wrap: bool,
+    /// The document's old URLs, e.g. `old/path.md` or `2023/old-slug`, which
+    /// redirect to it.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+impl Meta {
+    /// Parse a metadata block, which is YAML for `---` frontmatter and TOML
+    /// otherwise.
+    fn parse(text: &str, yaml: bool) -> Result<Meta, String> {
+        if yaml {
+            serde_yaml::from_str(text).map_err(|e| e.to_string())
+        } else {
+            toml::de::from_str(text).map_err(|e| e.to_string())
+        }
+    }
+
+    /// When the document last changed, for `Last-Modified`, feeds and the
+    /// sitemap.
+    pub fn last_updated(&self) -> Option<NaiveDate> {
+        self.updated.or(self.date)
+    }
+}
+
+/// A date in a metadata block, either a string or, in TOML, a date (i.e.
+/// `date = 2024-01-15` rather than `date = "2024-01-15"`). The time of a TOML
+/// date-time is ignored.
+fn meta_date<'de, D>(deserializer: D) -> Result<Option<NaiveDate>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Date {
+        Text(NaiveDate),
+        Toml(toml::value::Datetime),
+    }
+
+    match Option::<Date>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Date::Text(date)) => Ok(Some(date)),
+        Some(Date::Toml(datetime)) => {
+            let date = datetime
+                .date
+                .ok_or_else(|| D::Error::custom("expected a date"))?;
+            NaiveDate::from_ymd_opt(
+                date.year.into(),
+                date.month.into(),
+                date.day.into(),
+            )
+            .map(Some)
+            .ok_or_else(|| D::Error::custom(format!("invalid date {date}")))
+        }
+    }
+}
+
+impl Default for Meta {
+    fn default() -> Self {
+        Self {
+            title: "UNTITLED!".to_string(),
+            date: None,
+            updated: None,
+            lang: None,
+            desc: None,
+            author: None,
+            author_url: None,
+            tags: vec![],
+            slug: None,
+            draft: false,
+            cover: None,
+            toc: false,
+            wrap: false,
+            aliases: vec![],
+        }
+    }
+}
+
+/// Links between documents, which are written as paths to the files (e.g.
+/// `../notes/foo.md`) and have to point to where they're served instead.
+pub struct Links<'a> {
+    /// The path of the document the links are in, relative to the content
+    /// directory.
+    from: &'a str,
+    index: &'a [IndexEntry],
+}
+
+impl<'a> Links<'a> {
+    /// The links in the document at `from`, relative to the content
+    /// directory, to the documents in `state`.
+    pub fn new(state: &'a State, from: &'a str) -> Self {
+        Self {
+            from,
+            index: &state.index,
+        }
+    }
+}
+
+impl Links<'_> {
+    /// The path relative to the content directory that a link to `dest`
+    /// points to, and the link's fragment. `None` for links to other sites or
+    /// within the page.
+    fn local_path<'d>(
+        &self,
+        dest: &'d str,
+    ) -> Option<(PathBuf, Option<&'d str>)> {
+        if dest.is_empty() || dest.starts_with('#') || Url::parse(dest).is_ok()
+        {
+            return None;
+        }
+        let (path, fragment) = match dest.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment)),
+            None => (dest, None),
+        };
+        let path = path.split_once('?').map_or(path, |(path, _)| path);
+        let path = percent_encoding::percent_decode_str(path)
+            .decode_utf8()
+            .ok()?;
+        let path = Path::new(path.as_ref());
+
+        let joined = match path.strip_prefix("/") {
+            Ok(path) => path.to_path_buf(),
+            Err(_) => Path::new(self.from)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(path),
+        };
+        let mut resolved = PathBuf::new();
+        for component in joined.components() {
+            match component {
+                std::path::Component::Normal(x) => resolved.push(x),
+                std::path::Component::ParentDir => {
+                    resolved.pop();
+                }
+                _ => {}
+            }
+        }
+        Some((resolved, fragment))
+    }
+
+    /// The document a link to the markdown file at `path` (relative to the
+    /// content directory) is for.
+    fn document(&self, path: &Path) -> Option<&IndexEntry> {
+        self.index.iter().find(|x| Path::new(&x.path) == path)
+    }
+
+    /// The versions of the document in each language, which are the files
+    /// next to it named like it with a language code before the extension,
+    /// e.g. `post.md`, `post.en.md` and `post.de.md`. Files without a code
+    /// are in their `lang`, or `default_lang`.
+    fn translations<'s>(
+        &'s self,
+        default_lang: &'s str,
+    ) -> Vec<(&'s str, &'s IndexEntry)> {
+        let Some((base, _)) = translation_base(self.from) else {
+            return vec![];
+        };
+        let mut translations = self
+            .index
+            .iter()
+            .filter_map(|x| {
+                let (other, lang) = translation_base(&x.path)?;
+                (other == base).then(|| {
+                    let lang =
+                        lang.or(x.meta.lang.as_deref()).unwrap_or(default_lang);
+                    (lang, x)
+                })
+            })
+            .collect::<Vec<_>>();
+        translations.sort_by_key(|(lang, _)| *lang);
+        translations
+    }
+
+    /// The documents in the same section that are older and newer than this
+    /// one, leaving out its translations.
+    fn neighbours(&self) -> (Option<&IndexEntry>, Option<&IndexEntry>) {
+        let Some(current) = self.index.iter().find(|x| x.path == self.from)
+        else {
+            return (None, None);
+        };
+        let base = translation_base(self.from).map(|(base, _)| base);
+        let siblings = self
+            .index
+            .iter()
+            .filter(|x| x.section == current.section)
+            .filter(|x| {
+                x.path == self.from
+                    || translation_base(&x.path).map(|(base, _)| base) != base
+            })
+            .collect::<Vec<_>>();
+        let Some(i) = siblings.iter().position(|x| x.path == self.from) else {
+            return (None, None);
+        };
+        // The index is newest first.
+        (
+            siblings.get(i + 1).copied(),
+            i.checked_sub(1).map(|x| siblings[x]),
+        )
+    }
+
+    /// The document a wiki link to `target` (without its `#heading`) is for:
+    /// the one at that path from this document or the content root, or else
+    /// the one with that title or file name, ignoring case.
+    fn wiki_document(&self, target: &str) -> Option<&IndexEntry> {
+        let path = if is_markdown(Path::new(target)) {
+            target.to_string()
+        } else {
+            format!("{target}.md")
+        };
+        let here = Path::new(self.from).parent().unwrap_or(Path::new(""));
+        self.document(&here.join(&path))
+            .or_else(|| self.document(Path::new(path.trim_start_matches('/'))))
+            .or_else(|| {
+                self.index
+                    .iter()
+                    .find(|x| x.meta.title.eq_ignore_ascii_case(target))
+            })
+            .or_else(|| {
+                self.index.iter().find(|x| {
+                    Path::new(&x.path)
+                        .file_stem()
+                        .is_some_and(|x| x.eq_ignore_ascii_case(target))
+                })
+            })
+    }
+
+    /// The document a link points to, if it's to one.
+    fn target(&self, target: &LinkTarget) -> Option<&IndexEntry> {
+        match target {
+            LinkTarget::Path(dest) => {
+                let (path, _) = self.local_path(dest)?;
+                self.document(&path)
+            }
+            LinkTarget::Wiki(target) => {
+                let target = target.split_once('#').map_or(&**target, |x| x.0);
+                Some(target.trim())
+                    .filter(|x| !x.is_empty())
+                    .and_then(|x| self.wiki_document(x))
+            }
+        }
+    }
+
+    /// The URL of a wiki link to `target`, or `None` if it doesn't resolve.
+    /// A `#heading` is made into the ID the heading gets.
+    fn wiki_url(&self, target: &str) -> Option<String> {
+        let (target, heading) = match target.split_once('#') {
+            Some((target, heading)) => (target.trim(), Some(heading)),
+            None => (target, None),
+        };
+        let fragment = heading.map(|x| format!("#{}", slugify(x)));
+        if target.is_empty() {
+            return fragment;
+        }
+        let entry = self.wiki_document(target)?;
+        Some(http::encode_path(&entry.url) + fragment.as_deref().unwrap_or(""))
+    }
+
+    /// The URL a link to `dest` should point to, or `None` if it isn't a link
+    /// to a document written in markup.
+    fn resolve(&self, dest: &str) -> Option<String> {
+        let (path, fragment) = self.local_path(dest)?;
+        if !is_markup(&path) {
+            return None;
+        }
+        let Some(entry) = self.document(&path) else {
+            log::warn!(
+                "\"{}\" links to \"{dest}\", which isn't an indexed document",
+                self.from
+            );
+            return None;
+        };
+        let url = http::encode_path(&entry.url);
+        Some(match fragment {
+            Some(fragment) => format!("{url}#{fragment}"),
+            None => url,
+        })
+    }
+}
+
+/// A `[[target]]` or `[[target|label]]` link, as wikis and Obsidian write
+/// them. Targets are paths (with or without the extension) or titles, and can
+/// have a `#heading`.
+#[derive(Debug)]
+struct WikiLink<'t> {
+    /// Where the link is in the text, brackets included.
+    range: std::ops::Range<usize>,
+    target: &'t str,
+    label: &'t str,
+}
+
+/// The wiki links in `text`.
+fn wiki_links(text: &str) -> Vec<WikiLink<'_>> {
+    let mut found = vec![];
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find("[[").map(|x| x + offset) {
+        let inner_start = start + 2;
+        let Some(len) = text[inner_start..].find("]]") else {
+            break;
+        };
+        let inner = &text[inner_start..inner_start + len];
+        // `[[a [[b]]` links to `b`.
+        if let Some(nested) = inner.rfind("[[") {
+            offset = inner_start + nested;
+            continue;
+        }
+        let (target, label) = inner.split_once('|').unwrap_or((inner, inner));
+        offset = inner_start + len + 2;
+        if target.trim().is_empty() && label.trim().is_empty() {
+            continue;
+        }
+        found.push(WikiLink {
+            range: start..offset,
+            target: target.trim(),
+            label: label.trim(),
+        });
+    }
+    found
+}
+
+/// `text` as HTML with its wiki links made into links. Links that don't
+/// resolve are left as their label in a `span.wikilink.unresolved`.
+fn render_wiki_links(text: &str, links: Option<&Links>) -> String {
+    let escape = |x: &str| {
+        x.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let mut html = String::new();
+    let mut end = 0;
+    for link in wiki_links(text) {
+        html.push_str(&escape(&text[end..link.range.start]));
+        end = link.range.end;
+        let label = escape(link.label);
+        match links.and_then(|x| x.wiki_url(link.target)) {
+            Some(url) => {
+                let url = escape(&url);
+                html.push_str(&format!(
+                    "<a class=\"wikilink\" href=\"{url}\">{label}</a>"
+                ));
+            }
+            None => {
+                if let Some(links) = links {
+                    log::warn!(
+                        "\"{}\" links to \"{}\", which isn't an indexed \
+                         document",
+                        links.from,
+                        link.target
+                    );
+                }
+                html.push_str(&format!(
+                    "<span class=\"wikilink unresolved\">{label}</span>"
+                ));
+            }
+        }
+    }
+    html.push_str(&escape(&text[end..]));
+    html
+}
+
+/// The path of a document without its extension and language code (e.g.
+/// `blog/post` for `blog/post.de.md`), and the language code if it has one.
+fn translation_base(path: &str) -> Option<(&str, Option<&str>)> {
+    let stem = path
+        .strip_suffix(".md")
+        .or_else(|| path.strip_suffix(".markdown"))?;
+    let is_code = |code: &str| {
+        let (lang, region) = code.split_once('-').unwrap_or((code, ""));
+        (2..=3).contains(&lang.len())
+            && lang.chars().all(|c| c.is_ascii_alphabetic())
+            && region.len() <= 4
+            && region.chars().all(|c| c.is_ascii_alphanumeric())
+    };
+    match stem.rsplit_once('.') {
+        Some((base, code))
+            if is_code(code) && !base.ends_with('/') && !base.is_empty() =>
+        {
+            Some((base, Some(code)))
+        }
+        _ => Some((stem, None)),
+    }
+}
+
+/// How a document is rendered, given [`markdown_to_document`]'s arguments.
+type Renderer = fn(
+    &HeaderTemplate,
+    &str,
+    Option<&str>,
+    Option<&Links>,
+    Option<&GitMeta>,
+) -> Rendered;
+
+/// The renderer for the document at `path`, by its extension.
+fn renderer(path: &Path) -> Renderer {
+    if html_pages::is_html(path) {
+        html_pages::to_document
+    } else if org::is_org(path) {
+        org::to_document
+    } else if asciidoc::is_asciidoc(path) {
+        asciidoc::to_document
+    } else if notebook::is_notebook(path) {
+        notebook::to_document
+    } else {
+        markdown_to_document
+    }
+}
+
+/// Read the document at `path`, replacing what isn't UTF-8 in it rather than
+/// failing, so that one bad file doesn't keep the rest from being served.
+fn read_document(path: &Path) -> std::io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    Ok(match String::from_utf8(bytes) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!(
+                "\"{}\" isn't valid UTF-8: {}",
+                path.display(),
+                e.utf8_error()
+            );
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+    })
+}
+
+/// Whether the file at `path` is a document that's rendered from markup, so
+/// links to it point to where it's served instead.
+fn is_markup(path: &Path) -> bool {
+    is_markdown(path)
+        || org::is_org(path)
+        || asciidoc::is_asciidoc(path)
+        || notebook::is_notebook(path)
+}
+
+fn is_markdown(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|x| x.to_str()),
+        Some("md" | "markdown")
+    )
+}
+
+/// Render markdown that isn't a document, leaving out its metadata block if
+/// it has one. The block's text is returned with it, and whether it's YAML.
+fn markdown_fragment(contents: &str) -> (String, Option<(String, bool)>) {
+    use pulldown_cmark::{
+        Event, MetadataBlockKind, Options, Parser, Tag, TagEnd,
+    };
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_GFM);
+    options.insert(Options::ENABLE_YAML_STYLE_METADATA_BLOCKS);
+    options.insert(Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
+
+    let mut in_meta = false;
+    let mut meta = None;
+    let parser = Parser::new_ext(contents, options).filter(|event| {
+        match event {
+            Event::Start(Tag::MetadataBlock(kind)) => {
+                in_meta = true;
+                let yaml = matches!(kind, MetadataBlockKind::YamlStyle);
+                meta = Some((String::new(), yaml));
+            }
+            Event::Text(text) if in_meta => {
+                if let Some((meta, _)) = &mut meta {
+                    meta.push_str(text);
+                }
+            }
+            Event::End(TagEnd::MetadataBlock(_)) => {
+                in_meta = false;
+                return false;
+            }
+            _ => {}
+        }
+        !in_meta
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    (html, meta)
+}
+
+/// A markdown document rendered to a page.
+#[derive(Debug)]
+pub struct Rendered {
+    /// The whole page, or `None` if it couldn't be rendered.
+    pub html: Option<String>,
+    /// The document's own HTML, without the page around it.
+    pub body: String,
+    pub meta: Option<Meta>,
+    /// How many words the document's text has, leaving out code blocks.
+    pub words: usize,
+    /// Where the document's links point, before they're resolved.
+    pub(crate) links: Vec<LinkTarget>,
+}
+
+/// Where a link points, as it's written in a document.
+#[derive(Debug, Clone)]
+pub(crate) enum LinkTarget {
+    /// A markdown link's destination.
+    Path(String),
+    /// A wiki link's target.
+    Wiki(String),
+}
+
+/// Render a markdown document. `url` is its absolute URL, which is needed for
+/// the sharing (OpenGraph and Twitter card) metadata.
+pub fn markdown_to_document(
+    header: &HeaderTemplate,
+    contents: &str,
+    url: Option<&str>,
+    links: Option<&Links>,
+    git: Option<&GitMeta>,
+) -> Rendered {
+    use pulldown_cmark::{
+        CodeBlockKind, Event, MetadataBlockKind, Parser, Tag, TagEnd,
+        TextMergeStream,
+    };
+    #[derive(Default)]
+    enum ParseState {
+        #[default]
+        Normal,
+        /// A ```` ```meta ```` block or `+++` frontmatter.
+        Meta,
+        /// `---` frontmatter.
+        MetaYaml,
+        Highlight,
+        /// The alt text of an image that's shown as a `<picture>`.
+        Picture,
+    }
+
+    let options = header.site.markdown.options();
+    let math = header.site.markdown.math;
+    let emoji = header.site.markdown.emoji;
+    let mut needs_mermaid = false;
+    let mut has_code = false;
+    let mut state = ParseState::default();
+    let mut code = String::new();
+    let mut meta = None;
+    let mut lang = String::new();
+    let mut words = 0;
+    let mut targets = vec![];
+    let mut picture = None;
+    let mut alt = String::new();
+    let expanded = shortcodes::expand(
+        contents,
+        options,
+        header.templates,
+        header.content_path,
+    );
+    let contents = expanded.source.as_ref();
+    let parser = Parser::new_ext(contents, options);
+    let parser: Box<dyn Iterator<Item = Event>> =
+        if header.site.markdown.sanitize {
+            Box::new(sanitize::markdown(parser))
+        } else {
+            Box::new(parser)
+        };
+    // Text is merged so that `[[wiki links]]` aren't split at the brackets.
+    let parser = TextMergeStream::new(parser).filter_map(|event| match event {
+        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(block_lang))) => {
+            let block_lang = block_lang.trim();
+            if block_lang == "meta" {
+                state = ParseState::Meta;
+                None
+            } else {
+                state = ParseState::Highlight;
+                lang = block_lang.to_string();
+                None
+            }
+        }
+        Event::Start(Tag::MetadataBlock(kind)) => {
+            state = match kind {
+                MetadataBlockKind::YamlStyle => ParseState::MetaYaml,
+                MetadataBlockKind::PlusesStyle => ParseState::Meta,
+            };
+            None
+        }
+        Event::End(TagEnd::MetadataBlock(_)) => {
+            state = ParseState::Normal;
+            None
+        }
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => {
+            targets.push(LinkTarget::Path(dest_url.to_string()));
+            let dest_url = links
+                .and_then(|x| x.resolve(&dest_url))
+                .map_or(dest_url, Into::into);
+            Some(Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }))
+        }
+        // Images from the assets directory are shown in several sizes.
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => match images::Picture::new(
+            header.content_path,
+            &dest_url,
+            &header.site.images,
+        ) {
+            Some(found) => {
+                state = ParseState::Picture;
+                picture = Some((found, title));
+                None
+            }
+            None => Some(Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            })),
+        },
+        Event::End(TagEnd::Image) if matches!(state, ParseState::Picture) => {
+            state = ParseState::Normal;
+            let (found, title) = picture.take()?;
+            let html = found.html(&alt, &title);
+            alt.clear();
+            Some(Event::InlineHtml(html.into()))
+        }
+        // GitHub's `> [!NOTE]` alerts.
+        Event::Start(Tag::BlockQuote(Some(kind))) => {
+            let (class, title) = alert(kind);
+            Some(Event::Html(
+                format!(
+                    "<aside class=\"alert {class}\" role=\"note\">\
+                         <p class=\"alert-title\">{title}</p>\n"
+                )
+                .into(),
+            ))
+        }
+        Event::End(TagEnd::BlockQuote(Some(_))) => {
+            Some(Event::Html("</aside>\n".into()))
+        }
+        Event::InlineMath(tex) => {
+            Some(Event::InlineHtml(math::html(&tex, false).into()))
+        }
+        Event::DisplayMath(tex) => {
+            Some(Event::InlineHtml(math::html(&tex, true).into()))
+        }
+        Event::Code(text) if matches!(state, ParseState::Picture) => {
+            alt.push_str(&text);
+            None
+        }
+        Event::Code(text) => {
+            words += text.split_whitespace().count();
+            Some(Event::Code(text))
+        }
+        Event::Text(text) => match state {
+            ParseState::Normal => {
+                words += text.split_whitespace().count();
+                let expanded = match emoji.then(|| emoji::expand(&text)) {
+                    Some(Cow::Owned(expanded)) => Some(expanded),
+                    _ => None,
+                };
+                let text = expanded.map_or(text, Into::into);
+                let found = wiki_links(&text);
+                targets.extend(
+                    found
+                        .iter()
+                        .map(|x| LinkTarget::Wiki(x.target.to_string())),
+                );
+                if found.is_empty() {
+                    Some(Event::Text(text))
+                } else {
+                    Some(Event::InlineHtml(
+                        render_wiki_links(&text, links).into(),
+                    ))
+                }
+            }
+            ParseState::Meta | ParseState::MetaYaml => {
+                let yaml = matches!(state, ParseState::MetaYaml);
+                match Meta::parse(&text, yaml) {
+                    Ok(m) => meta = Some(m),
+                    Err(e) => error!("Failed to parse metadata: {e}"),
+                }
+                None
+            }
+            ParseState::Highlight => {
+                code.push_str(&text);
+                None
+            }
+            ParseState::Picture => {
+                alt.push_str(&text);
+                None
+            }
+        },
+        Event::End(TagEnd::CodeBlock) => match state {
+            ParseState::Normal | ParseState::Picture => {
+                Some(Event::End(TagEnd::CodeBlock))
+            }
+            ParseState::Meta | ParseState::MetaYaml => {
+                state = ParseState::Normal;
+                None
+            }
+            ParseState::Highlight => {
+                let (lang, fence) = highlight::Fence::parse(&lang);
+                let html = if math && lang == "math" {
+                    math::html(&code, true)
+                } else if lang == "mermaid" {
+                    let (html, script) =
+                        mermaid::html(&code, &header.site.mermaid);
+                    needs_mermaid |= script;
+                    html
+                } else {
+                    has_code = true;
+                    highlight::html(&code, lang, &fence)
+                };
+                code.clear();
+                state = ParseState::Normal;
+                Some(Event::Html(html.into()))
+            }
+        },
+        // Alt text is plain text, whatever markup is in it.
+        Event::SoftBreak | Event::HardBreak
+            if matches!(state, ParseState::Picture) =>
+        {
+            alt.push(' ');
+            None
+        }
+        _ if matches!(state, ParseState::Picture) => None,
+        _ => Some(event),
+    });
+
+    let (events, toc) = anchor_headings(parser.collect());
+    let events = list_footnotes(events);
+    let mut html_output = String::new();
+    pulldown_cmark::html::push_html(&mut html_output, events.into_iter());
+
+    document_page(
+        header,
+        Body {
+            html: html_output,
+            meta,
+            toc,
+            words,
+            links: targets,
+            mermaid: needs_mermaid,
+            code: has_code,
+            embeds: expanded.embeds,
+            tables: false,
+        },
+        url,
+        links,
+        git,
+    )
+}
+
+/// A document's rendered source, before it's put in the page.
+struct Body {
+    html: String,
+    meta: Option<Meta>,
+    toc: Vec<TocEntry>,
+    words: usize,
+    links: Vec<LinkTarget>,
+    /// Whether there are Mermaid diagrams, code blocks, video embeds and
+    /// sortable tables, which need their scripts.
+    mermaid: bool,
+    code: bool,
+    embeds: bool,
+    tables: bool,
+}
+
+/// Put a document's `body` in the page. The other arguments are
+/// [`markdown_to_document`]'s.
+fn document_page(
+    header: &HeaderTemplate,
+    body: Body,
+    url: Option<&str>,
+    links: Option<&Links>,
+    git: Option<&GitMeta>,
+) -> Rendered {
+    let Body {
+        html: html_output,
+        meta,
+        toc,
+        words,
+        links: targets,
+        mermaid: needs_mermaid,
+        code: has_code,
+        embeds,
+        tables,
+    } = body;
+    let mut meta_or_default = meta.clone().unwrap_or_default();
+    // Dates that aren't given were found when the state was loaded.
+    if let Some(entry) = links.and_then(|x| x.document(Path::new(x.from))) {
+        meta_or_default.date = meta_or_default.date.or(entry.meta.date);
+        meta_or_default.updated =
+            meta_or_default.updated.or(entry.meta.updated);
+    }
+    // Translations can give their language in their file name instead.
+    if meta_or_default.lang.is_none() {
+        meta_or_default.lang = links
+            .and_then(|x| translation_base(x.from))
+            .and_then(|(_, lang)| lang)
+            .map(String::from);
+    }
+    // Indexed documents without a cover get a generated preview.
+    let preview = links
+        .and_then(|x| x.document(Path::new(x.from)))
+        .filter(|_| header.site.og.enabled)
+        .map(|x| og::url(&http::encode_path(&x.url)));
+    let cover =
+        meta_or_default
+            .cover
+            .as_deref()
+            .or(preview.as_deref())
+            .map(|cover| {
+                let base = url.and_then(|x| Url::parse(x).ok());
+                match base.and_then(|x| x.join(cover).ok()) {
+                    Some(cover) => cover.to_string(),
+                    None => cover.to_string(),
+                }
+            });
+    let default_lang = header.site.language();
+    let mut translations = links
+        .map(|x| x.translations(default_lang))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(lang, entry)| {
+            let base = url.and_then(|x| Url::parse(x).ok());
+            let path = http::encode_path(&entry.url);
+            Translation {
+                lang: lang.to_string(),
+                url: match base.and_then(|x| x.join(&path).ok()) {
+                    Some(url) => url.to_string(),
+                    None => path,
+                },
+                current: links.is_some_and(|x| x.from == entry.path),
+            }
+        })
+        .collect::<Vec<_>>();
+    if translations.len() < 2 {
+        translations.clear();
+    }
+    let (prev, next) = links.map(Links::neighbours).unwrap_or_default();
+    let linked_from = links
+        .and_then(|x| Some((x, x.document(Path::new(x.from))?)))
+        .map(|(links, entry)| {
+            entry
+                .linked_from
+                .iter()
+                .filter_map(|x| links.document(Path::new(x)))
+                .filter(|x| {
+                    auth::listable(&header.site.protected, &x.url, &entry.url)
+                })
+                .map(Neighbour::new)
+                .collect()
+        })
+        .unwrap_or_default();
+    let mentions = links
+        .and_then(|x| x.document(Path::new(x.from)))
+        .map(|x| header.mentions.of(&x.url))
+        .unwrap_or_default();
+    let (author, author_url) = match &meta_or_default.author {
+        Some(author) => {
+            (Some(author.clone()), meta_or_default.author_url.clone())
+        }
+        None => (header.site.author.clone(), header.site.author_url.clone()),
+    };
+    let json_ld = json_ld(
+        header.site,
+        &meta_or_default,
+        url,
+        cover.as_deref(),
+        author.as_deref().map(|x| (x, author_url.as_deref())),
+        words,
+    );
+    let template = DocumentTemplate {
+        header: header.html(),
+        site: header.site,
+        styles: fingerprint::styles(),
+        highlight_url: header.highlight_url,
+        toc: if meta_or_default.toc { &toc } else { &[] },
+        translations,
+        mermaid_script: header
+            .site
+            .mermaid
+            .script
+            .as_deref()
+            .filter(|_| needs_mermaid),
+        copy_script: has_code.then(|| fingerprint::url(COPY_JS)),
+        embed_script: embeds.then(|| fingerprint::url(EMBED_JS)),
+        sort_script: tables.then(|| fingerprint::url(SORT_JS)),
+        meta: meta_or_default,
+        url,
+        canonical: url.filter(|_| header.site.base_url.is_some()),
+        cover,
+        author,
+        author_url,
+        json_ld,
+        git,
+        prev: prev.map(Neighbour::new),
+        next: next.map(Neighbour::new),
+        linked_from,
+        mentions,
+        words,
+        reading_time: header.site.reading_time(words),
+        markdown: &html_output,
+        source: links.is_some_and(|x| is_markdown(Path::new(x.from))),
+    };
+    let context = minijinja::context! {
+        header => overrides::safe(template.header.as_str()),
+        site => template.site,
+        language => template.meta.lang.as_deref()
+            .unwrap_or(template.site.language()),
+        styles => overrides::safe(template.styles),
+        highlight_url => template.highlight_url,
+        meta => template.meta,
+        url => template.url,
+        canonical => template.canonical,
+        cover => template.cover,
+        author => template.author,
+        author_url => template.author_url,
+        json_ld => overrides::safe(template.json_ld.as_str()),
+        toc => template.toc,
+        translations => template.translations,
+        mermaid_script => template.mermaid_script,
+        copy_script => template.copy_script,
+        embed_script => template.embed_script,
+        sort_script => template.sort_script,
+        git => template.git.map(|x| minijinja::context! {
+            short_hash => x.short_hash(),
+            ..minijinja::Value::from_serialize(x)
+        }),
+        prev => template.prev,
+        next => template.next,
+        linked_from => template.linked_from,
+        mentions => template.mentions,
+        words => template.words,
+        reading_time => template.reading_time,
+        markdown => overrides::safe(template.markdown),
+        source => template.source,
+    };
+    let html = header
+        .templates
+        .render("document.html", context)
+        .or_else(|| render(&template, "document.html"));
+    Rendered {
+        html,
+        body: html_output,
+        meta,
+        words,
+        links: targets,
+    }
+}
+
+/// The schema.org `Article` describing a document, for a
+/// `<script type="application/ld+json">`. `author` is their name and URL.
+fn json_ld(
+    site: &SiteConfig,
+    meta: &Meta,
+    url: Option<&str>,
+    cover: Option<&str>,
+    author: Option<(&str, Option<&str>)>,
+    words: usize,
+) -> String {
+    use serde_json::{Value, json};
+    let mut article = json!({
+        "@context": "https://schema.org",
+        "@type": "Article",
+        "headline": meta.title,
+    });
+    let fields = [
+        ("url", url.map(Value::from)),
+        ("mainEntityOfPage", url.map(Value::from)),
+        ("datePublished", meta.date.map(|x| x.to_string().into())),
+        (
+            "dateModified",
+            meta.last_updated().map(|x| x.to_string().into()),
+        ),
+        ("description", meta.desc.as_deref().map(Value::from)),
+        ("image", cover.map(Value::from)),
+        ("inLanguage", meta.lang.as_deref().map(Value::from)),
+        (
+            "keywords",
+            (!meta.tags.is_empty()).then(|| meta.tags.join(", ").into()),
+        ),
+        ("wordCount", (words > 0).then(|| words.into())),
+        (
+            "author",
+            author.map(|(name, url)| {
+                let mut author = json!({ "@type": "Person", "name": name });
+                if let Some(url) = url {
+                    author["url"] = url.into();
+                }
+                author
+            }),
+        ),
+        (
+            "publisher",
+            site.title
+                .as_deref()
+                .map(|name| json!({ "@type": "Organization", "name": name })),
+        ),
+    ];
+    for (key, value) in fields.into_iter().filter_map(|(k, v)| Some((k, v?))) {
+        article[key] = value;
+    }
+    // `</script>` in a string would end the script early.
+    article.to_string().replace('<', "\\u003c")
+}
+
+/// The class and title of an alert of `kind`.
+fn alert(kind: pulldown_cmark::BlockQuoteKind) -> (&'static str, &'static str) {
+    use pulldown_cmark::BlockQuoteKind;
+    match kind {
+        BlockQuoteKind::Note => ("note", "Note"),
+        BlockQuoteKind::Tip => ("tip", "Tip"),
+        BlockQuoteKind::Important => ("important", "Important"),
+        BlockQuoteKind::Warning => ("warning", "Warning"),
+        BlockQuoteKind::Caution => ("caution", "Caution"),
+    }
+}
+
+/// Give every heading an ID and a permalink to itself, returning the headings
+/// for the table of contents.
+fn anchor_headings(
+    events: Vec<pulldown_cmark::Event<'_>>,
+) -> (Vec<pulldown_cmark::Event<'_>>, Vec<TocEntry>) {
+    use pulldown_cmark::{Event, Tag, TagEnd};
+    let mut output = Vec::with_capacity(events.len());
+    let mut toc = vec![];
+    // The IDs given so far, so that repeated titles get unique ones.
+    let mut used = HashSet::<String>::new();
+    let mut events = events.into_iter();
+    while let Some(event) = events.next() {
+        let Event::Start(Tag::Heading {
+            level,
+            id,
+            classes,
+            attrs,
+        }) = event
+        else {
+            output.push(event);
+            continue;
+        };
+        let inner = events
+            .by_ref()
+            .take_while(|x| !matches!(x, Event::End(TagEnd::Heading(_))))
+            .collect::<Vec<_>>();
+        let title = inner
+            .iter()
+            .filter_map(|x| match x {
+                Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect::<String>();
+        let id = match id {
+            Some(id) => id.to_string(),
+            None => {
+                // Suffixes can collide too, e.g. a third "Foo" after "Foo"
+                // and "Foo 1", so count up until the ID is free.
+                let slug = slugify(&title);
+                let mut id = slug.clone();
+                let mut n = 0;
+                while used.contains(&id) {
+                    n += 1;
+                    id = format!("{slug}-{n}");
+                }
+                id
+            }
+        };
+        used.insert(id.clone());
+
+        output.push(Event::Start(Tag::Heading {
+            level,
+            id: Some(id.clone().into()),
+            classes,
+            attrs,
+        }));
+        output.extend(inner);
+        output.push(Event::InlineHtml(
+            format!(
+                r##"<a class="anchor" href="#{}" aria-label="Permalink">#</a>"##,
+                http::encode_path(&id)
+            )
+            .into(),
+        ));
+        output.push(Event::End(TagEnd::Heading(level)));
+        toc.push(TocEntry {
+            level: level as u8,
+            title,
+            id,
+        });
+    }
+    (output, toc)
+}
+
+/// Move footnote definitions into a numbered list at the end, in the order
+/// they're first referenced, and link them back to their references.
+/// Definitions that aren't referenced are left out.
+fn list_footnotes(
+    events: Vec<pulldown_cmark::Event<'_>>,
+) -> Vec<pulldown_cmark::Event<'_>> {
+    use pulldown_cmark::{Event, Tag, TagEnd};
+    let mut output = Vec::with_capacity(events.len());
+    // Referenced footnotes' names, in order.
+    let mut referenced: Vec<String> = vec![];
+    let mut definitions = HashMap::new();
+    let mut events = events.into_iter();
+    while let Some(event) = events.next() {
+        match event {
+            Event::FootnoteReference(name) => {
+                let id = slugify(&name);
+                let (n, first) =
+                    match referenced.iter().position(|x| *x == *name) {
+                        Some(i) => (i + 1, false),
+                        None => {
+                            referenced.push(name.to_string());
+                            (referenced.len(), true)
+                        }
+                    };
+                // Only the first reference is linked back to.
+                let back = if first {
+                    format!(r#" id="fnref-{id}""#)
+                } else {
+                    String::new()
+                };
+                output.push(Event::InlineHtml(
+                    format!(
+                        r##"<sup class="footnote-reference"{back}><a href="#fn-{id}">{n}</a></sup>"##
+                    )
+                    .into(),
+                ));
+            }
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                let content = events
+                    .by_ref()
+                    .take_while(|x| {
+                        !matches!(x, Event::End(TagEnd::FootnoteDefinition))
+                    })
+                    .collect::<Vec<_>>();
+                definitions.insert(name.to_string(), content);
+            }
+            _ => output.push(event),
+        }
+    }
+
+    let listed = referenced
+        .iter()
+        .filter_map(|name| Some((name, definitions.remove(name)?)))
+        .collect::<Vec<_>>();
+    if listed.is_empty() {
+        return output;
+    }
+    output.push(Event::Html("<section class=\"footnotes\">\n<ol>\n".into()));
+    for (name, mut content) in listed {
+        let id = slugify(name);
+        let backref = Event::InlineHtml(
+            format!(
+                r##" <a class="footnote-backref" href="#fnref-{id}" aria-label="Back to the reference">&#x21a9;</a>"##
+            )
+            .into(),
+        );
+        // Keep the link on the same line as the end of the footnote.
+        match content.last() {
+            Some(Event::End(TagEnd::Paragraph)) => {
+                content.insert(content.len() - 1, backref);
+            }
+            _ => content.push(backref),
+        }
+        output.push(Event::Html(format!("<li id=\"fn-{id}\">\n").into()));
+        output.extend(content);
+        output.push(Event::Html("</li>\n".into()));
+    }
+    output.push(Event::Html("</ol>\n</section>\n".into()));
+    output
+}
+
+/// An ID for a heading titled `title`: its words in lowercase, joined with
+/// hyphens.
+fn slugify(title: &str) -> String {
+    let slug = title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    }
+}
+
+fn respond<R: std::io::Read>(
+    request: Request,
+    mut response: Response<R>,
+) -> bool {
+    security::apply(&mut response);
+    if let Some(id) = logging::request_id() {
+        response.add_header(http::make_header("X-Request-Id", &id.to_string()));
+    }
+    let response = base_path::apply(response);
+    access_log::record(
+        &request,
+        response.status_code().0,
+        response.data_length(),
+    );
+    let response = timeout::limit(response);
+    let url = request.url().to_string();
+    let failed = request.respond(response);
+    if let Err(e) = &failed {
+        error!("Failed to respond to request for \"{url}\": {e}");
+    }
+    logging::end_request();
+    failed.is_err()
+}
+
+fn find_program(path: impl AsRef<Path>) -> Option<PathBuf> {
+    let sps = std::env::var_os("PATH")?;
+    for p in std::env::split_paths(&sps) {
+        let path = p.join(&path);
+        if path.is_file() {
+            // I just assume that the file in the path is executable because I
+            // don't want to check for that here.
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// What git knows about a document.
+#[derive(Debug, Clone, Serialize)]
+pub struct GitMeta {
+    hash: String,
+    /// When the last commit touching the document was made.
+    date: NaiveDate,
+    /// When the first one was.
+    created: NaiveDate,
+    history_url: Option<String>,
+}
+
+impl GitMeta {
+    fn short_hash(&self) -> &str {
+        &self.hash[..self.hash.len().min(10)]
+    }
+}
+
+/// The path of `in_dir` relative to the root of its git repository (empty or
+/// ending in a slash), or `None` if it isn't inside one.
+fn git_prefix(in_dir: &Path) -> Option<String> {
+    let output = std::process::Command::new("git")
+        .current_dir(in_dir)
+        .args(["rev-parse", "--show-prefix"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+/// The commit `HEAD` is at in the repository `in_dir` is in, if there is one.
+fn git_head(in_dir: &Path) -> Option<String> {
+    let output = std::process::Command::new("git")
+        .current_dir(in_dir)
+        .args(["rev-parse", "HEAD"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+/// Find the last commit touching `path`. `prefix` is the content directory's
+/// path within the repository (see [`git_prefix`]).
+fn git_metadata(
+    in_dir: &Path,
+    prefix: &str,
+    path: &str,
+    repo_url: Option<&str>,
+) -> eyre::Result<Option<GitMeta>> {
+    let mut git = std::process::Command::new("git");
+    let git = git.current_dir(in_dir).args([
+        "log",
+        "--follow",
+        "--format=%H %cs",
+        "--",
+        path,
+    ]);
+    log::trace!("Running \"git\" with args: {:?}", git.get_args());
+
+    let output = git.output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8(output.stderr)?;
+        return Err(eyre!("'git log' exited unsuccessfully:\n{stderr}"));
+    }
+    let stdout = String::from_utf8(output.stdout)?;
+    // Newest first. Untracked files have no history.
+    let Some((hash, date)) =
+        stdout.lines().next().and_then(|x| x.split_once(' '))
+    else {
+        return Ok(None);
+    };
+    let created = stdout
+        .lines()
+        .last()
+        .and_then(|x| x.split_once(' '))
+        .map_or(date, |(_, date)| date);
+
+    let repo_path = format!("{prefix}{path}");
+    let history_url = repo_url.map(|url| {
+        if url.contains("{path}") {
+            url.replace("{path}", &repo_path)
+        } else {
+            format!("{}/{repo_path}", url.trim_end_matches('/'))
+        }
+    });
+    Ok(Some(GitMeta {
+        hash: hash.to_string(),
+        date: NaiveDate::parse_from_str(date, "%Y-%m-%d")?,
+        created: NaiveDate::parse_from_str(created, "%Y-%m-%d")?,
+        history_url,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_dates() {
+        let quoted =
+            Meta::parse("title = \"A\"\ndate = \"2024-01-15\"", false).unwrap();
+        assert_eq!(quoted.date, NaiveDate::from_ymd_opt(2024, 1, 15));
+        let bare = Meta::parse(
+            "title = \"A\"\ndate = 2024-01-15\nupdated = 2024-02-01T10:00:00Z",
+            false,
+        )
+        .unwrap();
+        assert_eq!(bare.date, NaiveDate::from_ymd_opt(2024, 1, 15));
+        assert_eq!(bare.updated, NaiveDate::from_ymd_opt(2024, 2, 1));
+        assert!(Meta::parse("title = \"A\"\ndate = 10:00:00", false).is_err());
+        assert_eq!(Meta::parse("title = \"A\"", false).unwrap().date, None);
+    }
+
+    #[test]
+    fn yaml_dates() {
+        let meta = Meta::parse("title: A\ndate: 2024-01-15", true).unwrap();
+        assert_eq!(meta.date, NaiveDate::from_ymd_opt(2024, 1, 15));
+        assert_eq!(meta.updated, None);
+    }
+
+    #[test]
+    fn toml_frontmatter() {
+        let markdown = "+++\ntitle = \"A\"\ndate = 2024-01-15\n+++\n\nText.\n";
+        let (_, meta) = markdown_fragment(markdown);
+        let (text, yaml) = meta.expect("frontmatter");
+        let meta = Meta::parse(&text, yaml).unwrap();
+        assert_eq!(meta.title, "A");
+        assert_eq!(meta.date, NaiveDate::from_ymd_opt(2024, 1, 15));
+    }
+}
@@ -0,0 +1,185 @@
+//! Setting up the socket the server listens on.
+
+use eyre::eyre;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tiny_http::Listener;
+
+/// Where to listen, either a TCP address or `unix:` and the path of a Unix
+/// domain socket.
+#[derive(Debug, Clone)]
+pub enum BindAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("expected a path after \"unix:\"".to_string());
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        s.parse().map(Self::Tcp).map_err(|e| format!("{e}"))
+    }
+}
+
+impl std::fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "http://{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Parse the octal permissions of a socket, e.g. `660`.
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 8)
+        .ok()
+        .filter(|x| *x <= 0o7777)
+        .ok_or_else(|| format!("expected octal permissions, got \"{s}\""))
+}
+
+/// Listen on `addr`. A Unix socket replaces any socket left at its path and
+/// is given the permissions `mode`, if set.
+pub fn bind(addr: &BindAddr, mode: Option<u32>) -> eyre::Result<Listener> {
+    let path = match addr {
+        BindAddr::Tcp(addr) => {
+            return std::net::TcpListener::bind(addr)
+                .map(Listener::from)
+                .map_err(|e| eyre!("Failed to bind {addr}: {e}"));
+        }
+        BindAddr::Unix(path) => path,
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        // A previous run that didn't shut down cleanly leaves its socket
+        // behind, which would make binding fail.
+        if std::fs::symlink_metadata(path)
+            .is_ok_and(|x| x.file_type().is_socket())
+        {
+            std::fs::remove_file(path)?;
+        }
+        let listener = std::os::unix::net::UnixListener::bind(path)
+            .map_err(|e| eyre!("Failed to bind \"{}\": {e}", path.display()))?;
+        if let Some(mode) = mode {
+            std::fs::set_permissions(
+                path,
+                std::fs::Permissions::from_mode(mode),
+            )?;
+        }
+        Ok(listener.into())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = mode;
+        Err(eyre!(
+            "Unix sockets aren't supported here: \"{}\"",
+            path.display()
+        ))
+    }
+}
+
+/// Read and write timeouts for the TCP connections tiny_http accepts.
+///
+/// tiny_http doesn't hand out the connections it accepts, and timeouts set on
+/// a listening socket also make `accept` give up, so a connection's socket is
+/// looked up by its peer when its first request arrives and remembered for
+/// the requests after it.
+#[derive(Debug)]
+pub struct Timeouts {
+    read: Option<Duration>,
+    write: Option<Duration>,
+    /// The socket of each connection, by its peer.
+    sockets: Mutex<HashMap<SocketAddr, i32>>,
+}
+
+impl Timeouts {
+    pub fn new(read: Option<Duration>, write: Option<Duration>) -> Self {
+        Self {
+            read,
+            write,
+            sockets: Mutex::default(),
+        }
+    }
+
+    /// Time out reads and writes of the connection from `peer`.
+    pub fn apply(&self, peer: SocketAddr) {
+        #[cfg(unix)]
+        {
+            let mut sockets = self.sockets.lock().unwrap();
+            let fd = match sockets.get(&peer) {
+                // The socket may have been closed and its number reused.
+                Some(&fd) if peer_of(fd) == Some(peer) => fd,
+                _ => {
+                    // Forget the connections that have closed.
+                    sockets.retain(|peer, fd| peer_of(*fd) == Some(*peer));
+                    let Some(fd) = find_socket(peer) else {
+                        log::debug!("Found no socket connected to {peer}");
+                        return;
+                    };
+                    sockets.insert(peer, fd);
+                    fd
+                }
+            };
+            // SAFETY: `fd` is an open socket, which isn't closed here.
+            let stream = std::mem::ManuallyDrop::new(unsafe {
+                <std::net::TcpStream as std::os::fd::FromRawFd>::from_raw_fd(fd)
+            });
+            if let Err(e) = stream
+                .set_read_timeout(self.read)
+                .and_then(|()| stream.set_write_timeout(self.write))
+            {
+                log::warn!("Failed to set the timeouts of {peer}: {e}");
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = peer;
+    }
+}
+
+/// The peer of the TCP socket `fd`, if it is one.
+#[cfg(unix)]
+fn peer_of(fd: i32) -> Option<SocketAddr> {
+    // SAFETY: `stat` is plain data, and is only read once `fstat` has
+    // filled it in.
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd, &mut stat) } != 0
+        || stat.st_mode & libc::S_IFMT != libc::S_IFSOCK
+    {
+        return None;
+    }
+    // SAFETY: `fd` is an open socket, which isn't closed here.
+    let stream = std::mem::ManuallyDrop::new(unsafe {
+        <std::net::TcpStream as std::os::fd::FromRawFd>::from_raw_fd(fd)
+    });
+    stream.peer_addr().ok()
+}
+
+/// The socket this process has connected to `peer`.
+#[cfg(unix)]
+fn find_socket(peer: SocketAddr) -> Option<i32> {
+    std::fs::read_dir("/dev/fd")
+        .ok()?
+        .filter_map(|x| x.ok()?.file_name().to_str()?.parse().ok())
+        .find(|fd| peer_of(*fd) == Some(peer))
+}
+
+/// Remove the socket the server listened on, if it's a Unix socket.
+pub fn cleanup(addr: &BindAddr) {
+    if let BindAddr::Unix(path) = addr
+        && let Err(e) = std::fs::remove_file(path)
+    {
+        log::warn!("Failed to remove \"{}\": {e}", path.display());
+    }
+}
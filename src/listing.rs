@@ -0,0 +1,160 @@
+//! Listings of directories in the content tree, for sites that publish files
+//! that aren't documents, like downloads and images.
+//!
+//! They're off unless `listings = true` is set in `site.toml`, or in a
+//! section's `.section.toml` to only list that section. With them on, the
+//! files in listed directories are served too. Ignored files and the sources
+//! of documents never are, and neither are hidden ones unless they're under
+//! one of the `serve_hidden` paths, which are served with listings off too.
+
+use crate::{HeaderTemplate, SiteConfig, State, fingerprint, gitignore, http};
+use chrono::{DateTime, Local};
+use rinja::Template;
+use std::path::{Path, PathBuf};
+
+#[derive(Template)]
+#[template(ext = "html", path = "listing.html")]
+struct ListingTemplate<'a> {
+    header: String,
+    site: &'a SiteConfig,
+    styles: &'static str,
+    /// The directory's URL, e.g. `/downloads/fonts`.
+    path: &'a str,
+    /// The URL of the directory it's in.
+    parent: Option<String>,
+    /// The URL search engines are told to use, if there's a `base_url`.
+    canonical: Option<String>,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    name: String,
+    url: String,
+    is_dir: bool,
+    /// How big the file is, e.g. `1.2 MiB`. Directories have no size.
+    size: Option<String>,
+    modified: Option<DateTime<Local>>,
+}
+
+/// What a path that isn't a document's URL is, with listings on.
+pub enum Listed {
+    Directory(PathBuf),
+    File(PathBuf),
+}
+
+/// What's at `url_path` (without its trailing slash), if it's listed.
+pub fn resolve(
+    state: &State,
+    content_dir: &Path,
+    url_path: &str,
+) -> Option<Listed> {
+    let relative = url_path.trim_start_matches('/');
+    let section = relative.split('/').next().unwrap_or_default();
+    let enabled = state
+        .section(section)
+        .and_then(|x| x.config.listings)
+        .unwrap_or(state.config.listings);
+    let exposed = state.config.serves_hidden(Path::new(relative));
+    if !(enabled || exposed) || relative.is_empty() {
+        return None;
+    }
+
+    let content_dir = std::fs::canonicalize(content_dir).ok()?;
+    let path = std::fs::canonicalize(content_dir.join(relative)).ok()?;
+    let relative = path.strip_prefix(&content_dir).ok()?;
+    if !is_listed(&state.config, relative)
+        || !gitignore::filter_ignored(&content_dir, &[relative]).is_empty()
+    {
+        return None;
+    }
+    if path.is_dir() {
+        enabled.then_some(Listed::Directory(path))
+    } else {
+        path.is_file().then_some(Listed::File(path))
+    }
+}
+
+/// The listing of `dir`, which is served at `url_path`.
+pub fn html(state: &State, url_path: &str, dir: &Path) -> Option<String> {
+    let content_dir = std::fs::canonicalize(&state.content_path).ok()?;
+    let relative = dir.strip_prefix(&content_dir).ok()?;
+    let listed = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|x| is_listed(&state.config, &relative.join(x.file_name())))
+        .collect::<Vec<_>>();
+    let ignored = gitignore::filter_ignored(
+        &content_dir,
+        &listed
+            .iter()
+            .map(|x| relative.join(x.file_name()))
+            .collect::<Vec<_>>(),
+    );
+    let mut entries = listed
+        .into_iter()
+        .filter(|x| !ignored.contains(&relative.join(x.file_name())))
+        .filter_map(|x| {
+            let name = x.file_name().into_string().ok()?;
+            let metadata = x.metadata().ok()?;
+            let is_dir = metadata.is_dir();
+            Some(Entry {
+                url: http::encode_path(&format!("{url_path}/{name}")),
+                name,
+                is_dir,
+                size: (!is_dir).then(|| size(metadata.len())),
+                modified: metadata.modified().ok().map(DateTime::from),
+            })
+        })
+        .collect::<Vec<_>>();
+    // Directories first.
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then(a.name.cmp(&b.name)));
+
+    let parent = url_path
+        .rsplit_once('/')
+        .map(|(parent, _)| http::encode_path(parent))
+        .map(|x| if x.is_empty() { "/".to_string() } else { x });
+    let template = ListingTemplate {
+        header: HeaderTemplate::new(state).html(),
+        site: &state.config,
+        styles: fingerprint::styles(),
+        path: url_path,
+        parent,
+        canonical: state.config.canonical(url_path),
+        entries,
+    };
+    crate::render(&template, "listing.html")
+}
+
+/// Whether the file or directory at `relative` (to the content directory) is
+/// listed and served, unless it's ignored.
+fn is_listed(config: &SiteConfig, relative: &Path) -> bool {
+    let hidden = relative
+        .components()
+        .any(|x| x.as_os_str().as_encoded_bytes().starts_with(b"."))
+        && !config.serves_hidden(relative);
+    // The assets are served from `/assets/`, and the site's settings aren't
+    // for readers.
+    let reserved =
+        relative.starts_with("assets") || relative == Path::new("site.toml");
+    let source =
+        crate::is_markup(relative) || crate::html_pages::is_html(relative);
+    !hidden && !reserved && !source
+}
+
+/// `bytes` in the largest unit it's at least one of.
+fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for x in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = x;
+    }
+    format!("{size:.1} {unit}")
+}
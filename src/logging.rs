@@ -0,0 +1,88 @@
+//! Setting up the application log, either as text for people or as JSON for
+//! log collectors.
+//!
+//! Every request gets an ID, which the records logged while handling it are
+//! tagged with and which is sent back in the `X-Request-Id` header, so that a
+//! failed request can be found in the log.
+
+use std::cell::Cell;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// The ID of the request being handled by this thread, which records
+    /// logged while handling it are tagged with.
+    static REQUEST: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Like `[<timestamp> <level> <target> #<request ID>] <message>`.
+    #[default]
+    Text,
+    /// One JSON object per line, with `timestamp`, `level`, `target`,
+    /// `message` and, while handling a request, `request_id`.
+    Json,
+}
+
+/// Start logging at `level`, or what `RUST_LOG` asks for (`info` if it's
+/// unset) if `None`.
+pub fn init(level: Option<log::LevelFilter>, format: LogFormat) {
+    let mut logger = env_logger::Builder::from_default_env();
+    match level {
+        Some(level) => {
+            logger.filter(None, level);
+        }
+        None if std::env::var_os("RUST_LOG").is_none() => {
+            logger.filter(None, log::LevelFilter::Info);
+        }
+        None => {}
+    }
+    match format {
+        LogFormat::Text => logger.format(|buf, record| {
+            let level = buf.default_level_style(record.level());
+            write!(
+                buf,
+                "[{} {level}{:<5}{level:#} {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+            )?;
+            if let Some(id) = REQUEST.get() {
+                write!(buf, " #{id}")?;
+            }
+            writeln!(buf, "] {}", record.args())
+        }),
+        LogFormat::Json => logger.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": chrono::Utc::now().to_rfc3339_opts(
+                    chrono::SecondsFormat::Millis,
+                    true,
+                ),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+                "request_id": REQUEST.get(),
+            });
+            writeln!(buf, "{line}")
+        }),
+    };
+    logger.init();
+}
+
+/// Give the request this thread is about to handle a new ID.
+pub fn begin_request() {
+    REQUEST.set(Some(NEXT_REQUEST.fetch_add(1, Ordering::Relaxed)));
+}
+
+/// The ID of the request this thread is handling, if it's handling one.
+pub fn request_id() -> Option<u64> {
+    REQUEST.get()
+}
+
+/// Note that this thread is done with its request.
+pub fn end_request() {
+    REQUEST.set(None);
+}
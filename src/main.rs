@@ -1,25 +1,9 @@
-#![feature(str_split_remainder)]
-
-use chrono::NaiveDate;
-use clap::Parser;
-use eyre::eyre;
-use include_dir::include_dir;
-use log::{debug, error, info};
-use rinja::Template;
-use serde::Deserialize;
-use signal_hook::consts::signal::SIGHUP;
-use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, RwLock};
-use tiny_http::{Header, Request, Response, Server, StatusCode};
-use url::Url;
-
-static ASSETS: include_dir::Dir<'_> =
-    include_dir!("$CARGO_MANIFEST_DIR/static-assets");
-
-static STYLES: include_dir::Dir<'_> =
-    include_dir!("$CARGO_MANIFEST_DIR/styles");
-// const STYLES: &str = include_str!("../styles/styles.css");
+use clap::{Parser, Subcommand};
+use site::access::{Access, Cidr, Policy};
+use site::backend::{Backend, Tls};
+use site::{LoadOptions, Server, State, listen, logging};
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(version)]
@@ -27,603 +11,289 @@ struct Args {
     /// Where to serve content from (the current working directory is used if
     /// omitted).
     content_path: Option<PathBuf>,
-    /// Which socket address and port to use
+    /// Which socket address and port to use, or `unix:` and the path of a
+    /// Unix domain socket. Can be given more than once, e.g. for an IPv4 and
+    /// an IPv6 address.
     #[arg(long, default_value = "127.0.0.2:6969")]
-    bind: std::net::SocketAddr,
+    bind: Vec<listen::BindAddr>,
+    /// The permissions of the Unix sockets, in octal (e.g. `660`)
+    #[arg(long, value_parser = listen::parse_mode)]
+    socket_mode: Option<u32>,
+    /// Serve another content directory for requests to a host, e.g.
+    /// `notes.example.com=/srv/notes`. Can be given more than once, hosts
+    /// that aren't listed get the main content.
+    #[arg(long, value_name = "HOST=PATH", value_parser = parse_vhost)]
+    vhost: Vec<(String, PathBuf)>,
     #[arg(short = 't', long, default_value_t = 4)]
     serve_threads: usize,
+    /// How many requests can wait for a serve thread, more are answered with
+    /// `503 Service Unavailable`
+    #[arg(long, default_value_t = 64)]
+    queue_size: usize,
+    /// Enable the `/.control/reload` and `/.control/shutdown` endpoints and
+    /// the `/.stats` page, which require this bearer token (or password, for
+    /// the stats page).
+    #[arg(long, env = "SITE_CONTROL_TOKEN")]
+    control_token: Option<String>,
+    /// Enable `POST /.reload` for push webhooks. It requires this secret,
+    /// either as a bearer token or as the key of the payload's GitHub
+    /// (`X-Hub-Signature-256`) or Forgejo (`X-Forgejo-Signature`) signature.
+    #[arg(long, env = "SITE_WEBHOOK_SECRET")]
+    webhook_secret: Option<String>,
+    /// Run `git pull --ff-only` in the content directory before reloading for
+    /// a webhook.
+    #[arg(long, requires = "webhook_secret")]
+    git_pull: bool,
+    /// Allow viewing drafts by passing this token in the `preview` query
+    /// parameter or the `X-Preview-Token` header.
+    #[arg(long, env = "SITE_PREVIEW_TOKEN")]
+    preview_token: Option<String>,
+    /// Take the host, scheme and client address from the
+    /// `X-Forwarded-Host`, `X-Forwarded-Proto` and `X-Forwarded-For`
+    /// headers. Only use this behind a reverse proxy that sets them.
+    #[arg(long)]
+    trust_proxy: bool,
+    /// Limit each client to this many requests per second on average,
+    /// answering the rest with `429 Too Many Requests`.
+    #[arg(long)]
+    rate_limit: Option<f64>,
+    /// How many requests a client can make in a burst before `--rate-limit`
+    /// applies.
+    #[arg(long, default_value_t = 20, requires = "rate_limit")]
+    rate_burst: u32,
+    /// Close TCP connections that haven't sent anything for this many
+    /// seconds, `0` waits forever.
+    #[arg(long, default_value_t = 30)]
+    read_timeout: u64,
+    /// Close TCP connections that haven't accepted anything for this many
+    /// seconds, `0` waits forever.
+    #[arg(long, default_value_t = 30)]
+    write_timeout: u64,
+    /// Stop sending the response to a request after this many seconds, so
+    /// that slow clients can't hold on to the serve threads. `0` waits
+    /// forever.
+    #[arg(long, default_value_t = 300)]
+    request_timeout: u64,
+    /// What accepts connections. `async` needs site to be built with the
+    /// `async` feature.
+    #[arg(long, value_enum, default_value_t)]
+    backend: Backend,
+    /// Serve the site under this path, e.g. `/blog`, for a reverse proxy
+    /// that passes it part of a larger site. Links and redirects get it too.
+    #[arg(long)]
+    base_path: Option<String>,
+    /// Serve HTTPS with the certificate (chain) in this PEM file, offering
+    /// HTTP/2. Needs `--backend async`.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// The PEM file of the private key of `--tls-cert`.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Only serve the content to clients in this address range, e.g.
+    /// `192.168.1.0/24`. Can be given more than once.
+    #[arg(long, value_name = "CIDR")]
+    allow: Vec<Cidr>,
+    /// Don't serve the content to clients in this address range. Can be given
+    /// more than once.
+    #[arg(long, value_name = "CIDR")]
+    deny: Vec<Cidr>,
+    /// Only let clients in this address range use the control and webhook
+    /// endpoints and the stats page. Can be given more than once.
+    #[arg(long, value_name = "CIDR")]
+    admin_allow: Vec<Cidr>,
+    /// Don't let clients in this address range use the control and webhook
+    /// endpoints and the stats page. Can be given more than once.
+    #[arg(long, value_name = "CIDR")]
+    admin_deny: Vec<Cidr>,
+    /// Show each document's last commit (spawns git once per document when
+    /// loading).
+    #[arg(long)]
+    git_metadata: bool,
+    /// Link to a document's history, `{path}` is replaced by the document's
+    /// path in the repository (it's appended if absent). E.g.
+    /// `https://github.com/user/repo/commits/main/{path}`.
+    #[arg(long, requires = "git_metadata")]
+    repo_url: Option<String>,
+    /// How much rendered HTML to keep cached, in MiB
+    #[arg(long, default_value_t = 32)]
+    cache_size: usize,
+    /// Reload automatically when something in the content directory changes.
+    #[arg(short, long)]
+    watch: bool,
+    /// Keep the view counts shown on `/.stats` in this file, so that they
+    /// survive restarts. It's written on reload and shutdown.
+    #[arg(long)]
+    stats_file: Option<PathBuf>,
+    /// Write the access log to this file instead of standard output. It's
+    /// reopened on `SIGUSR1`, for log rotation.
+    #[arg(long)]
+    access_log: Option<PathBuf>,
+    /// Only log errors (takes precedence over `--verbose`)
+    #[arg(short, long)]
+    quiet: bool,
+    /// Increase logging verbosity (`-v` info, `-vv` debug, `-vvv` trace).
+    /// Overrides `RUST_LOG` when given.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Log at this level (`off`, `error`, `warn`, `info`, `debug` or
+    /// `trace`). Overrides `RUST_LOG` when given.
+    #[arg(long, conflicts_with_all = ["quiet", "verbose"])]
+    log_level: Option<log::LevelFilter>,
+    /// How log records are written: `text`, or `json` for one object per
+    /// line.
+    #[arg(long, value_enum, default_value_t)]
+    log_format: logging::LogFormat,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() -> eyre::Result<()> {
-    let args = Args::parse();
-    env_logger::Builder::from_default_env()
-        .filter(None, log::LevelFilter::Trace)
-        .init();
-
-    let reload_state = Arc::new(AtomicBool::new(false));
-    signal_hook::flag::register(SIGHUP, reload_state.clone())?;
-
-    let content_path: Arc<Path> =
-        std::fs::canonicalize(args.content_path.unwrap_or_else(|| {
-            std::env::current_dir().expect("current directory")
-        }))?
-        .as_path()
-        .into();
-
-    let state = Arc::new(RwLock::new(State::load(&content_path)?));
-    let server = Arc::new(Server::http(args.bind).map_err(|e| eyre!("{e}"))?);
-    info!("Spawned server on address: http://{}", server.server_addr());
-
-    for _ in 0..args.serve_threads {
-        let server = server.clone();
-        let content_path = content_path.clone();
-        let state = state.clone();
-
-        std::thread::spawn(move || serve(server, state, content_path));
-    }
-
-    loop {
-        if reload_state.swap(false, Ordering::Relaxed) {
-            info!("Reloading state...");
-            let mut state = state.write().unwrap();
-            match State::load(&content_path) {
-                Ok(s) => {
-                    info!("State reloaded sucessfully!");
-                    *state = s;
-                }
-                Err(e) => error!(
-                    "Failed to reload state (retaining previous state): {e}"
-                ),
-            }
-        }
-
-        std::thread::sleep(std::time::Duration::from_millis(256));
-    }
-}
-
-#[derive(Debug)]
-struct IndexEntry {
-    meta: Meta,
-    section: String,
-    path: String,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Render the site into a directory of static files instead of serving
+    /// it.
+    Build {
+        /// Where to write the rendered site
+        #[arg(short, long, default_value = "public")]
+        output: PathBuf,
+    },
+    /// Check the content for invalid metadata and broken links and images,
+    /// exiting unsuccessfully if there are any.
+    Check {
+        /// Also report `[[wiki links]]` that don't resolve
+        #[arg(long)]
+        wiki_links: bool,
+    },
+    /// Create a document with a metadata block for today, e.g. `new
+    /// blog/my-post` for `blog/my-post.md`.
+    New {
+        /// The document's section and slug, or just the slug for one at the
+        /// root
+        path: String,
+    },
 }
 
-#[derive(Debug)]
-struct State {
-    sections: Vec<String>,
-    index: Vec<IndexEntry>,
-}
-
-impl State {
-    fn load(content_path: &Path) -> eyre::Result<State> {
-        let found_git = find_program("git").is_some();
-
-        let mut index = vec![];
-        let mut sections = vec![];
-
-        walk(content_path, &mut |is_dir, path| {
-            if let Some(file_name) = path.file_name() {
-                if file_name == ".section.toml" && !is_dir {
-                    // TODO: REWORK sections.
-                    /*let section_cfg = std::fs::read_to_string(path)?;
-                    let section_cfg = match toml::de::from_str::<Section>(&section_cfg) {
-                        Ok(c) => c,
-                        Err(e) => {
-                            error!("Failed to parse section configuration at path \"{}\": {}");
-                            Section::default()
-                        }
-                    };*/
-                    let path = path
-                            .strip_prefix(content_path)
-                            .expect("is a subdir of content path");
-                    if let Some(section_name) = path
-                        .components()
-                        .next()
-                        .map(|x| x.as_os_str())
-                        .map(|x| x.to_str().unwrap().to_string())
-                    {
-                        sections.push(section_name);
-                    }
-                }
-
-                if file_name.as_encoded_bytes().starts_with(b".") {
-                    return Ok(false);
-                }
-            }
-
-            if is_dir {
-                return Ok(true);
-            }
-
-            match path.extension().and_then(|x| x.to_str()) {
-                Some("md" | "markdown") => {
-                    debug_assert!(path.is_absolute());
-                    let contents = std::fs::read_to_string(path)?;
-                    if let (_, Some(meta)) =
-                        markdown_to_document(&sections, &contents)
-                    {
-                        let path = path
-                            .strip_prefix(content_path)
-                            .expect("is a subdir of content path");
-                        let section = path
-                            .components()
-                            .next()
-                            .map(|x| x.as_os_str())
-                            .map(|x| x.to_str().unwrap().to_string())
-                            .unwrap_or_default();
-                        let path = path.to_str().unwrap().to_string();
-                        let section = if section == path {
-                            String::new()
-                        } else {
-                            section
-                        };
-
-                        index.push(IndexEntry {
-                            meta,
-                            section,
-                            path,
-                        });
-                    }
-                }
-                _ => {}
-            }
-            
-            Ok(true)
-        })?;
-
-        sections.retain(|s| index.iter().any(|i| i.section == *s));
-        if found_git {
-            if !sections.is_empty() {
-                let ignored =
-                    filter_ignored(content_path, sections.as_slice())?;
-                debug!("Removing ignored sections: {ignored:?}");
-                sections
-                    .retain(|s| !ignored.iter().any(|x| *x == Path::new(s)));
-            }
-
-            if !index.is_empty() {
-                let ignored = filter_ignored(
-                    content_path,
-                    &index.iter().map(|x| x.path.as_str()).collect::<Vec<_>>(),
-                )?;
-                debug!(
-                    "Removing ignored documents from the index: {ignored:?}"
-                );
-                index.retain(|i| {
-                    !ignored.iter().any(|x| *x == Path::new(&i.path))
-                });
-            }
-        }
-
-        sections.push(String::new()); // Blank is the root index
-        sections.sort();
-        index.sort_by(|r, l| l.meta.date.cmp(&r.meta.date));
-        Ok(State { sections, index })
+fn parse_vhost(s: &str) -> Result<(String, PathBuf), String> {
+    let (host, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected HOST=PATH, got \"{s}\""))?;
+    let host = host.trim();
+    if host.is_empty() || path.is_empty() {
+        return Err(format!("expected HOST=PATH, got \"{s}\""));
     }
+    Ok((host.to_ascii_lowercase(), PathBuf::from(path)))
 }
 
-fn walk(
-    p: impl AsRef<std::path::Path>,
-    callback: &mut dyn FnMut(bool, &std::path::Path) -> std::io::Result<bool>,
-) -> Result<(), std::io::Error> {
-    let dir = p.as_ref();
-    if dir.is_dir() {
-        for entry in std::fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                if callback(true, &path)? {
-                    walk(path, callback)?;
-                }
-            } else {
-                callback(false, &path)?;
-            }
-        }
-    } else {
-        // We don't want to ignore the first item if it's a file
-        callback(false, dir)?;
+/// Map the `--quiet`/`--verbose` flags to a log level, if either was given.
+fn verbosity_level(quiet: bool, verbose: u8) -> Option<log::LevelFilter> {
+    if quiet {
+        return Some(log::LevelFilter::Error);
     }
-    Ok(())
-}
-
-#[derive(Template)]
-#[template(ext = "html", path = "header.html")]
-struct HeaderTemplate<'a> {
-    sects: &'a [&'a str],
-}
-
-#[derive(Template)]
-#[template(ext = "html", escape = "none", path = "index.html")]
-struct IndexTemplate<'a> {
-    header: HeaderTemplate<'a>,
-    styles: &'static str,
-    docs: &'a [IndexTemplateEntryData<'a>],
-}
-struct IndexTemplateEntryData<'a> {
-    meta: &'a Meta,
-    section: &'a str,
-    path: &'a str,
-}
-
-impl<'a> From<&'a IndexEntry> for IndexTemplateEntryData<'a> {
-    fn from(ie: &'a IndexEntry) -> Self {
-        Self {
-            meta: &ie.meta,
-            section: ie.section.as_str(),
-            path: ie.path.as_str(),
-        }
+    match verbose {
+        0 => None,
+        1 => Some(log::LevelFilter::Info),
+        2 => Some(log::LevelFilter::Debug),
+        _ => Some(log::LevelFilter::Trace),
     }
 }
 
-impl IndexTemplate<'_> {
-    fn index(
-        sections: &[String],
-        docs: &[IndexEntry],
-        section: Option<&str>,
-    ) -> String {
-        let docs: Vec<IndexTemplateEntryData> = if let Some(section) = section {
-            docs.iter()
-                .filter(|x| x.path.starts_with(section))
-                .map(|x| x.into())
-                .collect()
-        } else {
-            docs.iter().map(|x| x.into()).collect()
-        };
-        let sections = sections.iter().map(String::as_str).collect::<Vec<_>>();
-        let template = IndexTemplate {
-            header: HeaderTemplate {
-                sects: sections.as_slice(),
-            },
-            styles: STYLES
-                .get_file("styles.css")
-                .and_then(include_dir::File::contents_utf8)
-                .unwrap(),
-            docs: docs.as_slice(),
-        };
-
-        template.render().unwrap()
+fn main() -> eyre::Result<()> {
+    let args = Args::parse();
+    if args.quiet && args.verbose > 0 {
+        eprintln!("warning: both --quiet and --verbose given, --quiet wins");
     }
-}
-
-fn serve(
-    server: Arc<Server>,
-    state: Arc<RwLock<State>>,
-    content_dir: Arc<Path>,
-) -> eyre::Result<()> {
-    let html_header =
-        Header::from_bytes(b"Content-Type", b"text/html").unwrap();
-    loop {
-        let rq = server.recv().unwrap();
-        let headers = rq.headers();
-        // Why is tiny_http using this `AsciiStr` haufen scheiße?
-        let Some(host) = headers
-            .iter()
-            .find(|x| x.field.as_str().as_str().eq_ignore_ascii_case("Host"))
-        else {
-            // The host header is required: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Host
-            respond(rq, Response::new_empty(StatusCode(400)));
-            continue;
-        };
-        // Tiny URL gives me a fake URL, so I have to first construct a URL,
-        // then deconstruct it.
-        let url = format!("http://{}{}", host.value, rq.url());
-        let url = match Url::parse(&url) {
-            Ok(url) => url,
-            Err(e) => {
-                error!("Invalid URL \"{url}\": {e}");
-                continue;
-            }
-        };
-
-        let path = url.path();
-        match path {
-            "/" => {
-                respond(
-                    rq,
-                    Response::new_empty(StatusCode(308)).with_header(
-                        Header::from_bytes(b"location", b"/index.html")
-                            .unwrap(),
-                    ),
-                );
-                continue;
-            }
-            "/index.html" => {
-                let state_l = state.read().unwrap();
-                respond(
-                    rq,
-                    Response::from_string(IndexTemplate::index(
-                        state_l.sections.as_slice(),
-                        state_l.index.as_slice(),
-                        None,
-                    ))
-                    .with_header(html_header.clone()),
-                );
-                continue;
-            }
-            _ if path.ends_with("/index.html") => {
-                let section = &path.strip_suffix("/index.html").unwrap()[1..];
-                let state_l = state.read().unwrap();
-                respond(
-                    rq,
-                    Response::from_string(IndexTemplate::index(
-                        state_l.sections.as_slice(),
-                        state_l.index.as_slice(),
-                        Some(section),
-                    ))
-                    .with_header(html_header.clone()),
-                );
-                continue;
-            }
-            _ if path.starts_with("/.static-assets") => {
-                let mut segments = url.path_segments().unwrap();
-                let _ = segments.next(); // I can't use Skip::remainder if I use iter::skip ????
-                let Some(remainder) = segments.remainder() else {
-                    respond(rq, Response::new_empty(StatusCode(404)));
-                    continue;
-                };
-                if let Some(a) = ASSETS.get_file(remainder) {
-                    respond(rq, Response::from_data(a.contents()));
-                } else {
-                    respond(rq, Response::new_empty(StatusCode(404)));
-                };
-                continue;
-            }
-
-            _ if path.starts_with("/.styles") => {
-                let mut segments = url.path_segments().unwrap();
-                let _ = segments.next(); // I can't use Skip::remainder if I use iter::skip ????
-                let Some(remainder) = segments.remainder() else {
-                    respond(rq, Response::new_empty(StatusCode(404)));
-                    continue;
-                };
-                if let Some(a) = STYLES.get_file(remainder) {
-                    respond(rq, Response::from_data(a.contents()));
-                } else {
-                    respond(rq, Response::new_empty(StatusCode(404)));
-                };
-                continue;
-            }
-            _ => {}
-        }
+    logging::init(
+        args.log_level
+            .or_else(|| verbosity_level(args.quiet, args.verbose)),
+        args.log_format,
+    );
 
-        let path = &path[1..];
-        let state_l = state.read().unwrap();
-
-        // Ensure we don't serve anything that hasn't been indexed, this way
-        // ignore files are honored.
-        if !state_l.index.iter().any(|x| x.path == path) {
-            respond(rq, Response::new_empty(StatusCode(404)));
-            continue;
-        }
-
-        let path = match std::path::absolute(content_dir.join(path)) {
-            Err(_) => {
-                respond(rq, Response::new_empty(StatusCode(404)));
-                continue;
-            }
-            Ok(p) => p,
-        };
+    let content_path =
+        std::fs::canonicalize(args.content_path.unwrap_or_else(|| {
+            std::env::current_dir().expect("current directory")
+        }))?;
 
-        if !path.starts_with(&content_dir)
-            || path
-                .file_name()
-                .is_some_and(|x| x.as_encoded_bytes().starts_with(b"."))
-            || !path.is_file()
-        {
-            respond(rq, Response::new_empty(StatusCode(404)));
-            continue;
+    let load_options = LoadOptions {
+        git_metadata: args.git_metadata,
+        repo_url: args.repo_url,
+        cache_size: args.cache_size * 1024 * 1024,
+        templates: None,
+    };
+    match &args.command {
+        Some(Command::New { path }) => {
+            return site::scaffold::new(&content_path, path);
         }
-
-        info!("Responding to request for \"{}\"", path.display());
-        let contents = match std::fs::read(&path) {
-            Ok(c) => c,
-            Err(e) => {
-                error!("Error getting \"{}\": {e}", path.display());
-                continue;
-            }
-        };
-        match path.extension().and_then(|x| x.to_str()) {
-            Some("md" | "markdown") => {
-                let contents = String::from_utf8(contents).unwrap();
-                let state = state.read().unwrap();
-                let (contents, _) =
-                    markdown_to_document(&state.sections, &contents);
-                if respond(
-                    rq,
-                    Response::from_string(contents)
-                        .with_header(html_header.clone()),
-                ) {
-                    continue;
-                }
-            }
-            None | Some(_) => {
-                if respond(rq, Response::from_data(contents)) {
-                    continue;
-                }
-            }
+        Some(Command::Build { output }) => {
+            let state = State::load(&content_path, &load_options, None)?;
+            return site::build::build(&state, &content_path, output);
         }
-    }
-}
-
-#[derive(Template)]
-#[template(ext = "html", escape = "none", path = "document.html")]
-struct DocumentTemplate<'a> {
-    header: HeaderTemplate<'a>,
-    styles: &'static str,
-    meta: Meta,
-    markdown: &'a str,
-}
-
-#[derive(Debug, Clone, Deserialize)]
-struct Meta {
-    title: String,
-    date: NaiveDate,
-    lang: Option<String>,
-    desc: Option<String>,
-}
-
-impl Default for Meta {
-    fn default() -> Self {
-        Self {
-            title: "UNTITLED!".to_string(),
-            date: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
-            lang: None,
-            desc: None,
+        Some(Command::Check { wiki_links }) => {
+            let state = State::load(&content_path, &load_options, None)?;
+            return site::check::check(&state, &content_path, *wiki_links);
         }
+        None => {}
     }
-}
-
-fn markdown_to_document(
-    header_sections: &[String],
-    contents: &str,
-) -> (String, Option<Meta>) {
-    use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
-    use std::sync::LazyLock;
-    use syntect::highlighting::{Theme, ThemeSet};
-    use syntect::parsing::SyntaxSet;
-    static SYNTAX_SET: LazyLock<SyntaxSet> =
-        LazyLock::new(SyntaxSet::load_defaults_newlines);
-    static THEME: LazyLock<Theme> = LazyLock::new(|| {
-        let theme_set = ThemeSet::load_defaults();
-        theme_set.themes["base16-ocean.dark"].clone()
-    });
-
-    #[derive(Default)]
-    enum ParseState {
-        #[default]
-        Normal,
-        Meta,
-        Highlight,
-    }
-
-    let mut options = Options::empty();
-    options.insert(Options::ENABLE_GFM);
 
-    let mut state = ParseState::default();
-    let mut code = String::new();
-    let mut meta = None;
-    let mut syntax = SYNTAX_SET.find_syntax_plain_text();
-    let parser =
-        Parser::new_ext(contents, options).filter_map(|event| match event {
-            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) => {
-                let lang = lang.trim();
-                if lang == "meta" {
-                    state = ParseState::Meta;
-                    None
-                } else {
-                    state = ParseState::Highlight;
-                    syntax = SYNTAX_SET
-                        .find_syntax_by_token(lang)
-                        .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
-                    None
-                }
-            }
-            Event::Text(text) => match state {
-                ParseState::Normal => Some(Event::Text(text)),
-                ParseState::Meta => {
-                    match toml::de::from_str::<Meta>(&text) {
-                        Ok(m) => meta = Some(m),
-                        Err(e) => error!("Failed to parse metadata: {e}"),
-                    }
-                    None
-                }
-                ParseState::Highlight => {
-                    code.push_str(&text);
-                    None
-                }
+    let mut server = Server::new(content_path)
+        .socket_mode(args.socket_mode)
+        .threads(args.serve_threads, args.queue_size)
+        .load_options(load_options)
+        .control_token(args.control_token)
+        .webhook(args.webhook_secret, args.git_pull)
+        .preview_token(args.preview_token)
+        .trust_proxy(args.trust_proxy)
+        .rate_limit(args.rate_limit, args.rate_burst)
+        .timeouts(
+            Duration::from_secs(args.read_timeout),
+            Duration::from_secs(args.write_timeout),
+            Duration::from_secs(args.request_timeout),
+        )
+        .access(Access {
+            content: Policy {
+                allow: args.allow,
+                deny: args.deny,
             },
-            Event::End(TagEnd::CodeBlock) => match state {
-                ParseState::Normal => Some(Event::End(TagEnd::CodeBlock)),
-                ParseState::Meta => {
-                    state = ParseState::Normal;
-                    None
-                }
-                ParseState::Highlight => {
-                    let html = syntect::html::highlighted_html_for_string(
-                        &code,
-                        &SYNTAX_SET,
-                        syntax,
-                        &THEME,
-                    )
-                    .unwrap_or(code.clone());
-                    code.clear();
-                    state = ParseState::Normal;
-                    Some(Event::Html(html.into()))
-                }
+            admin: Policy {
+                allow: args.admin_allow,
+                deny: args.admin_deny,
             },
-            _ => Some(event),
-        });
-
-    let mut html_output = String::new();
-    pulldown_cmark::html::push_html(&mut html_output, parser);
-
-    let sections = header_sections
-        .iter()
-        .map(String::as_str)
-        .collect::<Vec<_>>();
-    let template = DocumentTemplate {
-        header: HeaderTemplate {
-            sects: sections.as_slice(),
-        },
-        styles: STYLES
-            .get_file("styles.css")
-            .and_then(include_dir::File::contents_utf8)
-            .unwrap(),
-        meta: meta.clone().unwrap_or_default(),
-        markdown: &html_output,
-    };
-    let html = template.render().unwrap();
-    (html, meta)
-}
-
-fn respond<R: std::io::Read>(request: Request, response: Response<R>) -> bool {
-    let url = request.url().to_string();
-    if let Err(e) = request.respond(response) {
-        error!("Failed to respond to request for \"{url}\": {e}");
-        return true;
+        })
+        .backend(args.backend)
+        .base_path(args.base_path)
+        .tls(
+            args.tls_cert
+                .zip(args.tls_key)
+                .map(|(cert, key)| Tls { cert, key }),
+        )
+        .watch(args.watch)
+        .stats_file(args.stats_file)
+        .access_log(args.access_log)
+        .handle_signals(true);
+    for addr in args.bind {
+        server = server.bind(addr);
     }
-    false
-}
-
-fn find_program(path: impl AsRef<Path>) -> Option<PathBuf> {
-    let sps = std::env::var_os("PATH")?;
-    for p in std::env::split_paths(&sps) {
-        let path = p.join(&path);
-        if path.is_file() {
-            // I just assume that the file in the path is executable because I
-            // don't want to check for that here.
-            return Some(path);
-        }
+    for (host, path) in args.vhost {
+        server = server.vhost(host, path);
     }
-    None
+    server.run()
 }
 
-fn filter_ignored(
-    in_dir: &Path,
-    paths: &[impl AsRef<Path>],
-) -> eyre::Result<Vec<PathBuf>> {
-    let paths = paths.iter().map(|x| x.as_ref()).collect::<Vec<_>>();
-    let mut git = std::process::Command::new("git");
-    let git = git
-        .current_dir(in_dir)
-        .args(["check-ignore", "--"])
-        .args(paths.as_slice());
-    log::trace!("Running \"git\" with args: {:?}", git.get_args());
+#[cfg(test)]
+mod tests {
+    use super::verbosity_level;
+    use log::LevelFilter;
+
+    #[test]
+    fn verbosity() {
+        assert_eq!(verbosity_level(false, 0), None);
+        assert_eq!(verbosity_level(false, 1), Some(LevelFilter::Info));
+        assert_eq!(verbosity_level(false, 2), Some(LevelFilter::Debug));
+        assert_eq!(verbosity_level(false, 3), Some(LevelFilter::Trace));
+        assert_eq!(verbosity_level(false, 10), Some(LevelFilter::Trace));
+    }
 
-    let output = git.output()?;
-    let stdout = String::from_utf8(output.stdout)?;
-    let code = output
-        .status
-        .code()
-        .ok_or_else(|| eyre!("git didn't exit with a code"))?;
-    if code == 128 {
-        let stderr = String::from_utf8(output.stderr)?;
-        return Err(eyre!(
-            "'Git check-ignore' exited uncuccessfully with output:\nstdout:{stdout}\nstderr:\n{stderr}"
-        ));
+    #[test]
+    fn quiet_wins() {
+        assert_eq!(verbosity_level(true, 0), Some(LevelFilter::Error));
+        assert_eq!(verbosity_level(true, 3), Some(LevelFilter::Error));
     }
-    Ok(stdout
-        .lines()
-        .map(|line| PathBuf::from(line.trim()))
-        .collect())
 }
@@ -0,0 +1,32 @@
+//! Rendering TeX math.
+//!
+//! Math is rendered with KaTeX when the page is, as MathML, which browsers
+//! display by themselves. Pages don't need any scripts, stylesheets or fonts
+//! for it.
+
+/// Render the TeX in `tex` as MathML, as a block if `display` is set. Math
+/// that fails to render is shown as its source.
+pub fn html(tex: &str, display: bool) -> String {
+    let opts = katex::Opts::builder()
+        .display_mode(display)
+        .output_type(katex::OutputType::Mathml)
+        .build()
+        .expect("options are valid");
+    match katex::render_with_opts(tex, &opts) {
+        Ok(html) => html,
+        Err(e) => {
+            log::error!("Failed to render math \"{tex}\": {e}");
+            let escaped = tex
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            if display {
+                format!(
+                    "<pre class=\"math-error\"><code>{escaped}</code></pre>"
+                )
+            } else {
+                format!("<code class=\"math-error\">{escaped}</code>")
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Mermaid diagrams in ```` ```mermaid ```` blocks.
+//!
+//! Diagrams are rendered to SVG by an external command (mermaid-cli's `mmdc`)
+//! if one is configured. Otherwise they're left for a script to render in the
+//! browser, which is only included on pages with diagrams.
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+/// Rendered diagrams by their source, so that they're only rendered once
+/// rather than every time the document is. Diagrams that failed to render
+/// are `None`.
+static RENDERED: LazyLock<Mutex<HashMap<String, Option<String>>>> =
+    LazyLock::new(Default::default);
+
+/// The `[mermaid]` table of `site.toml`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MermaidConfig {
+    /// The command that renders diagrams, e.g. `mmdc` or
+    /// `mmdc --backgroundColor transparent`. It's given `-i <input> -o
+    /// <output.svg>`.
+    pub command: Option<String>,
+    /// The URL of the Mermaid script that renders diagrams in the browser
+    /// when there's no command, e.g. `/assets/mermaid.min.js`.
+    pub script: Option<String>,
+}
+
+/// The HTML for the diagram `source`, and whether it needs the script to be
+/// rendered.
+pub fn html(source: &str, config: &MermaidConfig) -> (String, bool) {
+    if let Some(command) = &config.command {
+        let rendered = RENDERED
+            .lock()
+            .unwrap()
+            .entry(source.to_string())
+            .or_insert_with(|| render(source, command))
+            .clone();
+        if let Some(svg) = rendered {
+            return (
+                format!("<figure class=\"mermaid\">{svg}</figure>"),
+                false,
+            );
+        }
+    }
+    let escaped = source
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    (
+        format!("<pre class=\"mermaid\">{escaped}</pre>"),
+        config.script.is_some(),
+    )
+}
+
+/// Render `source` to SVG with `command`.
+fn render(source: &str, command: &str) -> Option<String> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let mut args = command.split_whitespace();
+    let program = args.next()?;
+
+    let name = format!(
+        "site-mermaid-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    let input = std::env::temp_dir().join(format!("{name}.mmd"));
+    let output = std::env::temp_dir().join(format!("{name}.svg"));
+    let result = std::fs::write(&input, source).and_then(|()| {
+        std::process::Command::new(program)
+            .args(args)
+            .arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(&output)
+            .output()
+    });
+    let svg = match result {
+        Ok(out) if out.status.success() => std::fs::read_to_string(&output)
+            .inspect_err(|e| error!("Failed to read rendered diagram: {e}"))
+            .ok(),
+        Ok(out) => {
+            error!(
+                "\"{command}\" failed to render a diagram: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            None
+        }
+        Err(e) => {
+            error!("Failed to run \"{command}\": {e}");
+            None
+        }
+    };
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+    svg
+}
@@ -0,0 +1,288 @@
+//! Jupyter notebooks.
+//!
+//! A notebook is turned into markdown and rendered like any other document:
+//! markdown cells as they are, code cells as fenced code blocks in the
+//! kernel's language, and their outputs as HTML after them, with images
+//! inlined as `data:` URLs. Its metadata is a `+++` block at the top of its
+//! first markdown cell, or else the notebook's own `title` and `authors`.
+
+use crate::{GitMeta, HeaderTemplate, Links, Rendered};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The types of output that are shown, in the order they're preferred in.
+const OUTPUT_TYPES: [&str; 7] = [
+    "image/svg+xml",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "text/html",
+    "text/markdown",
+    "text/plain",
+];
+
+#[derive(Deserialize)]
+struct Notebook {
+    cells: Vec<Cell>,
+    #[serde(default)]
+    metadata: NotebookMeta,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct NotebookMeta {
+    title: Option<String>,
+    authors: Vec<Author>,
+    kernelspec: Option<Language>,
+    language_info: Option<Language>,
+}
+
+#[derive(Deserialize)]
+struct Author {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Language {
+    #[serde(alias = "name")]
+    language: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "cell_type", rename_all = "lowercase")]
+enum Cell {
+    Markdown {
+        source: Text,
+        #[serde(default)]
+        attachments: HashMap<String, HashMap<String, Value>>,
+    },
+    Code {
+        source: Text,
+        #[serde(default)]
+        outputs: Vec<Output>,
+    },
+    /// Meant for tools that convert notebooks, not for readers.
+    Raw {},
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "output_type", rename_all = "snake_case")]
+enum Output {
+    Stream {
+        #[serde(default)]
+        name: String,
+        text: Text,
+    },
+    DisplayData {
+        data: HashMap<String, Value>,
+    },
+    ExecuteResult {
+        data: HashMap<String, Value>,
+    },
+    Error {
+        traceback: Vec<String>,
+    },
+}
+
+/// Notebooks keep multiline text as a string or as a list of lines.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Text {
+    One(String),
+    Lines(Vec<String>),
+}
+
+impl Text {
+    fn join(&self) -> String {
+        match self {
+            Text::One(x) => x.clone(),
+            Text::Lines(x) => x.concat(),
+        }
+    }
+}
+
+/// The `+++` block made from the notebook's own metadata.
+#[derive(Serialize)]
+struct Frontmatter<'a> {
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<&'a str>,
+}
+
+pub fn is_notebook(path: &Path) -> bool {
+    path.extension().and_then(|x| x.to_str()) == Some("ipynb")
+}
+
+/// Render a notebook, like [`crate::markdown_to_document`] does a markdown
+/// document.
+pub fn to_document(
+    header: &HeaderTemplate,
+    contents: &str,
+    url: Option<&str>,
+    links: Option<&Links>,
+    git: Option<&GitMeta>,
+) -> Rendered {
+    crate::markdown_to_document(header, &markdown(contents), url, links, git)
+}
+
+/// The text of the notebook, without its markup, for the search index.
+pub fn search_text(contents: &str) -> String {
+    crate::search::markdown_text(&markdown(contents))
+}
+
+/// The notebook as markdown, which is empty if it can't be read.
+fn markdown(contents: &str) -> String {
+    let notebook = match serde_json::from_str::<Notebook>(contents) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to read a notebook: {e}");
+            return String::new();
+        }
+    };
+    let meta = &notebook.metadata;
+    let language = meta
+        .language_info
+        .as_ref()
+        .or(meta.kernelspec.as_ref())
+        .map_or("", |x| x.language.as_str());
+
+    let mut markdown = String::new();
+    let has_frontmatter = notebook.cells.first().is_some_and(|x| {
+        matches!(x, Cell::Markdown { source, .. }
+            if source.join().trim_start().starts_with("+++"))
+    });
+    if !has_frontmatter && let Some(title) = &meta.title {
+        let frontmatter = Frontmatter {
+            title,
+            author: meta.authors.first().map(|x| x.name.as_str()),
+        };
+        match toml::ser::to_string(&frontmatter) {
+            Ok(x) => {
+                let _ = write!(markdown, "+++\n{x}+++\n\n");
+            }
+            Err(e) => error!("Failed to write a notebook's metadata: {e}"),
+        }
+    }
+
+    for cell in &notebook.cells {
+        match cell {
+            Cell::Markdown {
+                source,
+                attachments,
+            } => {
+                let mut source = source.join();
+                // `![](attachment:plot.png)`
+                for (name, data) in attachments {
+                    let Some((mime, data)) = data.iter().next() else {
+                        continue;
+                    };
+                    source = source.replace(
+                        &format!("attachment:{name}"),
+                        &format!("data:{mime};base64,{}", text(data).trim()),
+                    );
+                }
+                markdown.push_str(&source);
+            }
+            Cell::Code { source, outputs } => {
+                let source = source.join();
+                let fence = fence(&source);
+                let _ = write!(
+                    markdown,
+                    "{fence}{language}\n{}\n{fence}\n\n",
+                    source.trim_end()
+                );
+                for output in outputs {
+                    markdown.push_str(&output_html(output));
+                    markdown.push_str("\n\n");
+                }
+            }
+            Cell::Raw {} => {}
+        }
+        markdown.push_str("\n\n");
+    }
+    markdown
+}
+
+/// An output as a block of HTML.
+fn output_html(output: &Output) -> String {
+    match output {
+        Output::Stream { name, text } => format!(
+            "<pre class=\"output {}\">{}</pre>",
+            escape(name),
+            escape(text.join().trim_end())
+        ),
+        Output::DisplayData { data } | Output::ExecuteResult { data } => {
+            let Some((mime, value)) =
+                OUTPUT_TYPES.iter().find_map(|x| Some((*x, data.get(*x)?)))
+            else {
+                return String::new();
+            };
+            let value = text(value);
+            let html = match mime {
+                "image/svg+xml" | "text/html" => value,
+                "text/markdown" => crate::markdown_fragment(&value).0,
+                "text/plain" => {
+                    format!("<pre>{}</pre>", escape(value.trim_end()))
+                }
+                _ => format!(
+                    "<img src=\"data:{mime};base64,{}\" alt=\"\">",
+                    value.trim()
+                ),
+            };
+            // A blank line would end the block.
+            let html = html
+                .lines()
+                .filter(|x| !x.trim().is_empty())
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("<div class=\"output\">{html}</div>")
+        }
+        Output::Error { traceback } => format!(
+            "<pre class=\"output error\">{}</pre>",
+            escape(&strip_ansi(&traceback.join("\n")))
+        ),
+    }
+}
+
+/// A value of an output's data, which is a string or a list of lines except
+/// for JSON.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(x) => x.clone(),
+        Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => value.to_string(),
+    }
+}
+
+/// A code fence longer than any run of backticks in `source`.
+fn fence(source: &str) -> String {
+    let longest = source.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+/// Remove the colours from a traceback, which IPython writes as ANSI escape
+/// sequences.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // `ESC [ ... m`, ending at the first letter.
+            chars.by_ref().find(char::is_ascii_alphabetic);
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
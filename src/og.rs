@@ -0,0 +1,153 @@
+//! Social previews: an image for each document without a cover, with its
+//! title drawn onto the `og.svg` template, served at `/.og/<url>.png`.
+//!
+//! The template can be overridden like the HTML ones. Text is drawn with the
+//! system's fonts and any in the assets directory.
+
+use crate::overrides::Overrides;
+use crate::{IndexEntry, SiteConfig};
+use log::{debug, error};
+use minijinja::Environment;
+use resvg::{tiny_skia, usvg};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+
+pub const PREFIX: &str = "/.og";
+
+/// How many characters fit on a line of the title, roughly.
+const LINE_LENGTH: usize = 28;
+const MAX_LINES: usize = 3;
+
+/// Rendered previews by their SVG. Ones that failed to render are `None`.
+type Rendered = HashMap<String, Option<Arc<[u8]>>>;
+
+/// Rendered previews, so that each is only rendered once rather than on every
+/// request.
+static RENDERED: LazyLock<Mutex<Rendered>> = LazyLock::new(Default::default);
+
+/// The fonts previews are drawn with, by content directory.
+static FONTS: LazyLock<Mutex<HashMap<PathBuf, Arc<usvg::fontdb::Database>>>> =
+    LazyLock::new(Default::default);
+
+static DEFAULT: LazyLock<Environment<'static>> = LazyLock::new(|| {
+    let mut env = Environment::new();
+    env.set_auto_escape_callback(crate::overrides::auto_escape);
+    env.add_template("og.svg", include_str!("../templates/og.svg"))
+        .expect("og.svg is valid");
+    env
+});
+
+/// The `[og]` table of `site.toml`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OgConfig {
+    /// Whether documents without a cover get a preview.
+    pub enabled: bool,
+}
+
+impl Default for OgConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// The path of the preview of the document served at `url`.
+pub fn url(url: &str) -> String {
+    format!("{PREFIX}{url}.png")
+}
+
+/// The URL of the document whose preview is at `path`.
+pub fn document_url(path: &str) -> Option<&str> {
+    path.strip_prefix(PREFIX)?.strip_suffix(".png")
+}
+
+/// The preview of `entry` as a PNG.
+pub fn png(
+    config: &SiteConfig,
+    templates: &Overrides,
+    content_path: &Path,
+    entry: &IndexEntry,
+) -> Option<Arc<[u8]>> {
+    let context = minijinja::context! {
+        site => config.title,
+        title => entry.meta.title,
+        lines => wrap(&entry.meta.title),
+        section => entry.section,
+        date => entry.meta.date.map(|x| x.to_string()),
+        desc => entry.meta.desc,
+    };
+    let svg = templates.render("og.svg", context.clone()).or_else(|| {
+        DEFAULT
+            .get_template("og.svg")
+            .and_then(|x| x.render(context))
+            .inspect_err(|e| error!("Failed to render og.svg: {e}"))
+            .ok()
+    })?;
+    let fonts = fonts(content_path);
+    RENDERED
+        .lock()
+        .unwrap()
+        .entry(svg)
+        .or_insert_with_key(|svg| render(svg, fonts))
+        .clone()
+}
+
+/// Split `title` into lines, ending the last with an ellipsis if it doesn't
+/// fit.
+fn wrap(title: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for word in title.split_whitespace() {
+        match lines.last_mut() {
+            Some(line)
+                if line.chars().count() + 1 + word.chars().count()
+                    <= LINE_LENGTH =>
+            {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    if lines.len() > MAX_LINES {
+        lines.truncate(MAX_LINES);
+        lines[MAX_LINES - 1].push('…');
+    }
+    lines
+}
+
+fn fonts(content_path: &Path) -> Arc<usvg::fontdb::Database> {
+    FONTS
+        .lock()
+        .unwrap()
+        .entry(content_path.to_path_buf())
+        .or_insert_with(|| {
+            let mut fonts = usvg::fontdb::Database::new();
+            fonts.load_system_fonts();
+            if let Some(assets) = crate::assets_dir(content_path) {
+                fonts.load_fonts_dir(assets);
+            }
+            debug!("Loaded {} fonts for previews", fonts.len());
+            Arc::new(fonts)
+        })
+        .clone()
+}
+
+fn render(svg: &str, fonts: Arc<usvg::fontdb::Database>) -> Option<Arc<[u8]>> {
+    let options = usvg::Options {
+        fontdb: fonts,
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_str(svg, &options)
+        .inspect_err(|e| error!("Invalid og.svg: {e}"))
+        .ok()?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap
+        .encode_png()
+        .inspect_err(|e| error!("Failed to encode a preview: {e}"))
+        .ok()
+        .map(Into::into)
+}
@@ -0,0 +1,174 @@
+//! Org-mode documents.
+//!
+//! Their metadata comes from the `#+TITLE:`, `#+DATE:`, `#+AUTHOR:`,
+//! `#+DESCRIPTION:`, `#+LANGUAGE:` and `#+FILETAGS:` keywords, along with
+//! `#+SLUG:`, `#+ALIASES:` (separated by spaces) and `#+DRAFT:` for the
+//! settings markdown documents have. Files without a title aren't documents.
+//! Source blocks are highlighted like code blocks, and `file:` links to other
+//! documents point to where they're served.
+
+use crate::{
+    Body, GitMeta, HeaderTemplate, LinkTarget, Links, Meta, Rendered, highlight,
+};
+use chrono::NaiveDate;
+use orgize::export::{DefaultHtmlHandler, HtmlEscape, HtmlHandler};
+use orgize::{Element, Event, Org};
+use std::io::Write;
+use std::path::Path;
+
+pub fn is_org(path: &Path) -> bool {
+    path.extension().and_then(|x| x.to_str()) == Some("org")
+}
+
+/// Render an Org document, like [`crate::markdown_to_document`] does a
+/// markdown one.
+pub fn to_document(
+    header: &HeaderTemplate,
+    contents: &str,
+    url: Option<&str>,
+    links: Option<&Links>,
+    git: Option<&GitMeta>,
+) -> Rendered {
+    let org = Org::parse(contents);
+    let mut handler = Handler {
+        links,
+        targets: vec![],
+        has_code: false,
+        default: DefaultHtmlHandler,
+    };
+    let mut html = vec![];
+    if let Err(e) = org.write_html_custom(&mut html, &mut handler) {
+        log::error!("Failed to render an Org document: {e}");
+    }
+    crate::document_page(
+        header,
+        Body {
+            html: String::from_utf8_lossy(&html).into_owned(),
+            meta: meta(&org),
+            toc: vec![],
+            words: text(&org).split_whitespace().count(),
+            links: handler.targets,
+            mermaid: false,
+            code: handler.has_code,
+            embeds: false,
+            tables: false,
+        },
+        url,
+        links,
+        git,
+    )
+}
+
+/// The text of the document, without its markup, for the search index.
+pub fn search_text(contents: &str) -> String {
+    text(&Org::parse(contents))
+}
+
+fn text(org: &Org) -> String {
+    let mut text = String::new();
+    for event in org.iter() {
+        if let Event::Start(
+            Element::Text { value }
+            | Element::Code { value }
+            | Element::Verbatim { value },
+        ) = event
+        {
+            text.push_str(value);
+            text.push(' ');
+        }
+    }
+    text
+}
+
+fn meta(org: &Org) -> Option<Meta> {
+    let mut meta = Meta::default();
+    let mut title = None;
+    for keyword in org.keywords() {
+        let value = keyword.value.trim();
+        match keyword.key.to_ascii_uppercase().as_str() {
+            "TITLE" => title = Some(value.to_string()),
+            "DATE" => meta.date = date(value),
+            "AUTHOR" => meta.author = Some(value.to_string()),
+            "DESCRIPTION" => meta.desc = Some(value.to_string()),
+            "LANGUAGE" => meta.lang = Some(value.to_string()),
+            // `:emacs:notes:`
+            "FILETAGS" => {
+                meta.tags = value
+                    .split(':')
+                    .filter(|x| !x.is_empty())
+                    .map(String::from)
+                    .collect();
+            }
+            "SLUG" => meta.slug = Some(value.to_string()),
+            "ALIASES" => {
+                meta.aliases =
+                    value.split_whitespace().map(String::from).collect();
+            }
+            "DRAFT" => meta.draft = matches!(value, "t" | "true" | "yes"),
+            _ => {}
+        }
+    }
+    meta.title = title?;
+    Some(meta)
+}
+
+/// A date as Org writes them, e.g. `<2024-01-15 Mon>`, or a plain one.
+fn date(value: &str) -> Option<NaiveDate> {
+    value.trim_start_matches(['<', '[']).get(..10)?.parse().ok()
+}
+
+#[derive(Default)]
+struct Handler<'a> {
+    links: Option<&'a Links<'a>>,
+    /// Where the document's links point, before they're resolved.
+    targets: Vec<LinkTarget>,
+    has_code: bool,
+    default: DefaultHtmlHandler,
+}
+
+impl HtmlHandler<std::io::Error> for Handler<'_> {
+    fn start<W: Write>(
+        &mut self,
+        mut w: W,
+        element: &Element,
+    ) -> std::io::Result<()> {
+        match element {
+            Element::SourceBlock(block) => {
+                self.has_code = true;
+                let fence = highlight::Fence::default();
+                w.write_all(
+                    highlight::html(&block.contents, &block.language, &fence)
+                        .as_bytes(),
+                )
+            }
+            Element::Link(link) => {
+                let path =
+                    link.path.strip_prefix("file:").unwrap_or(&link.path);
+                self.targets.push(LinkTarget::Path(path.to_string()));
+                let href = self
+                    .links
+                    .and_then(|x| x.resolve(path))
+                    .unwrap_or_else(|| path.to_string());
+                let label = link.desc.as_deref().unwrap_or(path);
+                write!(
+                    w,
+                    "<a href=\"{}\">{}</a>",
+                    HtmlEscape(&href),
+                    HtmlEscape(label)
+                )
+            }
+            _ => self.default.start(w, element),
+        }
+    }
+
+    fn end<W: Write>(
+        &mut self,
+        w: W,
+        element: &Element,
+    ) -> std::io::Result<()> {
+        match element {
+            Element::SourceBlock(_) | Element::Link(_) => Ok(()),
+            _ => self.default.end(w, element),
+        }
+    }
+}
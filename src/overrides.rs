@@ -0,0 +1,89 @@
+//! Templates from the content directory's `.templates/` that replace the
+//! compiled-in ones.
+//!
+//! Overrides are rendered with `minijinja`, which understands most of the
+//! Jinja syntax the embedded templates use. The values given to each template
+//! are listed in the README. Any other `.html` files in the directory can be
+//! `{% include %}`d by the overrides, and the ones in `shortcodes/` are
+//! shortcodes (see [`crate::shortcodes`]).
+
+use log::{debug, error};
+use minijinja::Environment;
+use std::path::Path;
+
+/// The templates that can be overridden.
+const OVERRIDABLE: &[&str] =
+    &["index.html", "document.html", "header.html", "og.svg"];
+
+#[derive(Debug, Default)]
+pub struct Overrides {
+    env: Environment<'static>,
+}
+
+impl Overrides {
+    /// Load the templates in `dir` (usually the content directory's
+    /// `.templates/`), if there is one.
+    pub fn load(dir: &Path) -> eyre::Result<Self> {
+        let mut env = Environment::new();
+        env.set_auto_escape_callback(auto_escape);
+        let shortcodes = dir.join("shortcodes");
+        for (dir, prefix) in [(dir, ""), (&*shortcodes, "shortcodes/")] {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                let Some(name) = path.file_name().and_then(|x| x.to_str())
+                else {
+                    continue;
+                };
+                if !(name.ends_with(".html") || name == "og.svg")
+                    || !path.is_file()
+                {
+                    continue;
+                }
+                let name = format!("{prefix}{name}");
+                let source = std::fs::read_to_string(&path)?;
+                if OVERRIDABLE.contains(&name.as_str()) {
+                    debug!("Overriding the \"{name}\" template");
+                }
+                env.add_template_owned(name, source).map_err(|e| {
+                    eyre::eyre!("Invalid \"{}\": {e}", path.display())
+                })?;
+            }
+        }
+        Ok(Self { env })
+    }
+
+    /// Render the override for the template `name` with `context`, or `None`
+    /// if it isn't overridden or fails to render, in which case the embedded
+    /// template should be used.
+    pub fn render(
+        &self,
+        name: &str,
+        context: minijinja::Value,
+    ) -> Option<String> {
+        let template = self.env.get_template(name).ok()?;
+        template
+            .render(context)
+            .inspect_err(|e| {
+                error!("Failed to render the \"{name}\" override: {e}");
+            })
+            .ok()
+    }
+}
+
+/// How values are escaped in the template `name`. Like minijinja's default,
+/// but SVG is escaped too.
+pub fn auto_escape(name: &str) -> minijinja::AutoEscape {
+    if name.ends_with(".svg") {
+        minijinja::AutoEscape::Html
+    } else {
+        minijinja::default_auto_escape_callback(name)
+    }
+}
+
+/// A value that's inserted into an override as-is, without escaping.
+pub fn safe(html: impl Into<String>) -> minijinja::Value {
+    minijinja::Value::from_safe_string(html.into())
+}
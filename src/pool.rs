@@ -0,0 +1,156 @@
+//! The threads requests are handled on, behind a bounded queue so that a
+//! burst of requests is turned away rather than piling up.
+
+use log::{debug, error, info, warn};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tiny_http::Request;
+
+/// How busy the pool is, for the logs and the stats page.
+#[derive(Debug)]
+pub struct Usage {
+    pub threads: usize,
+    /// How many requests can wait for a thread.
+    pub capacity: usize,
+    busy: AtomicUsize,
+    queued: AtomicUsize,
+    rejected: AtomicU64,
+    /// Whether requests are being rejected, so that only the start and end of
+    /// it are logged.
+    saturated: AtomicBool,
+}
+
+/// What [`Usage`] was at one moment.
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    pub threads: usize,
+    pub capacity: usize,
+    pub busy: usize,
+    pub queued: usize,
+    pub rejected: u64,
+}
+
+impl Usage {
+    pub fn new(threads: usize, capacity: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            capacity,
+            busy: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+            saturated: AtomicBool::new(false),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            threads: self.threads,
+            capacity: self.capacity,
+            busy: self.busy.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Pool {
+    queue: SyncSender<Request>,
+    usage: Arc<Usage>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    /// Start `usage.threads` threads that `handle` the requests submitted to
+    /// the pool.
+    pub fn new(
+        usage: Arc<Usage>,
+        handle: impl Fn(Request) + Send + Sync + 'static,
+    ) -> Self {
+        let (queue, requests) = std::sync::mpsc::sync_channel(usage.capacity);
+        let requests = Arc::new(Mutex::new(requests));
+        let handle = Arc::new(handle);
+        let workers = (0..usage.threads)
+            .map(|_| {
+                let requests = requests.clone();
+                let usage = usage.clone();
+                let handle = handle.clone();
+                std::thread::spawn(move || work(&requests, &usage, &*handle))
+            })
+            .collect();
+        Self {
+            queue,
+            usage,
+            workers,
+        }
+    }
+
+    /// Queue `rq` for a thread, or give it back if the queue is full.
+    #[allow(clippy::result_large_err)]
+    pub fn submit(&self, rq: Request) -> Result<(), Request> {
+        let usage = &self.usage;
+        usage.queued.fetch_add(1, Ordering::Relaxed);
+        match self.queue.try_send(rq) {
+            Ok(()) => {
+                if usage.saturated.swap(false, Ordering::Relaxed) {
+                    info!("Accepting requests again");
+                }
+                Ok(())
+            }
+            Err(TrySendError::Full(rq) | TrySendError::Disconnected(rq)) => {
+                usage.queued.fetch_sub(1, Ordering::Relaxed);
+                usage.rejected.fetch_add(1, Ordering::Relaxed);
+                if !usage.saturated.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        "All {} threads are busy and {} requests are queued, \
+                         rejecting requests",
+                        usage.threads, usage.capacity
+                    );
+                }
+                debug!("Rejected request for \"{}\"", rq.url());
+                Err(rq)
+            }
+        }
+    }
+
+    /// Finish the queued requests and stop the threads.
+    pub fn join(self) {
+        drop(self.queue);
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(
+    requests: &Mutex<Receiver<Request>>,
+    usage: &Usage,
+    handle: &impl Fn(Request),
+) {
+    loop {
+        // The lock is only held while waiting, so one thread waits for the
+        // next request at a time.
+        let Ok(rq) = requests.lock().unwrap().recv() else {
+            return;
+        };
+        usage.queued.fetch_sub(1, Ordering::Relaxed);
+        usage.busy.fetch_add(1, Ordering::Relaxed);
+        // The request is dropped while unwinding, which answers it with
+        // `500 Internal Server Error`, and the thread goes on to the next one.
+        if let Err(panic) = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            handle(rq);
+        })) {
+            let message = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown error");
+            error!("Failed to handle a request: {message}");
+            crate::logging::end_request();
+        }
+        usage.busy.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,309 @@
+//! Removing what could run scripts from the HTML written in markdown, for
+//! sites whose content comes from people who aren't trusted.
+//!
+//! Elements that run or load anything (`<script>`, `<iframe>`, `<object>`,
+//! `<form>` and the like) are dropped, with the contents of the ones whose
+//! contents aren't markup. Event handlers and `style` attributes are dropped
+//! from the rest, as are URLs with a scheme other than `http`, `https`,
+//! `mailto` and `tel` (and `data` for images), in attributes and in markdown
+//! links and images. Everything the renderer adds itself is left alone.
+
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+/// Elements that are dropped.
+const DENIED: [&str; 22] = [
+    "applet", "base", "button", "embed", "form", "frame", "frameset", "iframe",
+    "input", "link", "math", "meta", "noembed", "noframes", "noscript",
+    "object", "script", "select", "style", "svg", "template", "textarea",
+];
+
+/// Dropped elements whose contents go with them.
+const RAW_TEXT: [&str; 8] = [
+    "iframe", "noembed", "noframes", "script", "style", "template", "textarea",
+    "xmp",
+];
+
+const DENIED_ATTRIBUTES: [&str; 3] = ["formaction", "srcdoc", "style"];
+
+/// Attributes with URLs in them. `data-embed` is where video embeds load
+/// their player from.
+const URL_ATTRIBUTES: [&str; 12] = [
+    "action",
+    "background",
+    "cite",
+    "data",
+    "data-embed",
+    "href",
+    "longdesc",
+    "ping",
+    "poster",
+    "src",
+    "srcset",
+    "xlink:href",
+];
+
+/// Sanitize the raw HTML and link destinations in markdown `events`, before
+/// anything else is done with them.
+pub fn markdown<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+) -> impl Iterator<Item = Event<'a>> {
+    // The lines of an HTML block are sanitized together, so that an element
+    // can't be split between them.
+    let mut block: Option<String> = None;
+    events.filter_map(move |event| match event {
+        Event::Start(Tag::HtmlBlock) => {
+            block = Some(String::new());
+            None
+        }
+        Event::Html(raw) if block.is_some() => {
+            block.as_mut()?.push_str(&raw);
+            None
+        }
+        Event::End(TagEnd::HtmlBlock) => {
+            Some(Event::Html(html(&block.take().unwrap_or_default()).into()))
+        }
+        Event::Html(raw) => Some(Event::Html(html(&raw).into())),
+        Event::InlineHtml(raw) => Some(Event::InlineHtml(html(&raw).into())),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Some(Event::Start(Tag::Link {
+            link_type,
+            dest_url: if is_safe_url(&dest_url, false) {
+                dest_url
+            } else {
+                "".into()
+            },
+            title,
+            id,
+        })),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Some(Event::Start(Tag::Image {
+            link_type,
+            dest_url: if is_safe_url(&dest_url, true) {
+                dest_url
+            } else {
+                "".into()
+            },
+            title,
+            id,
+        })),
+        event => Some(event),
+    })
+}
+
+/// Sanitize a fragment of HTML.
+pub fn html(raw: &str) -> String {
+    let mut sanitized = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find('<') {
+        sanitized.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            // Comments are dropped, they're not for readers anyway.
+            rest = comment.find("-->").map_or("", |x| &comment[x + 3..]);
+            continue;
+        }
+        // A `<` that doesn't start a tag is text.
+        let is_tag = match rest[1..].chars().next() {
+            Some('/') => {
+                rest[2..].starts_with(|c: char| c.is_ascii_alphabetic())
+            }
+            Some(c) => c.is_ascii_alphabetic() || c == '!' || c == '?',
+            None => false,
+        };
+        if !is_tag {
+            sanitized.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        }
+        let Some(end) = tag_end(rest) else {
+            // An unfinished tag, which could be finished by whatever comes
+            // after the fragment.
+            rest = "";
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let tag = tag.trim_start_matches('/');
+        let name_end = tag
+            .find(|c: char| c.is_whitespace() || c == '/')
+            .unwrap_or(tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        // `<!DOCTYPE>`, `<?xml?>` and the like.
+        if name.starts_with(['!', '?']) {
+            continue;
+        }
+        if DENIED.contains(&name.as_str()) || RAW_TEXT.contains(&name.as_str())
+        {
+            if !closing && RAW_TEXT.contains(&name.as_str()) {
+                let close = format!("</{name}");
+                rest = match rest.to_ascii_lowercase().find(&close) {
+                    Some(x) => tag_end(&rest[x..])
+                        .map_or("", |end| &rest[x + end + 1..]),
+                    None => "",
+                };
+            }
+            continue;
+        }
+        if closing {
+            sanitized.push_str(&format!("</{name}>"));
+            continue;
+        }
+
+        sanitized.push('<');
+        sanitized.push_str(&name);
+        let attributes = &tag[name_end..];
+        for (key, value) in attributes_of(attributes) {
+            let key = key.to_ascii_lowercase();
+            if key.starts_with("on") || DENIED_ATTRIBUTES.contains(&&*key) {
+                continue;
+            }
+            if let Some(value) = value {
+                let image = key == "src" && name == "img";
+                let safe = if key == "srcset" {
+                    value.split(',').all(|x| {
+                        let url = x.split_whitespace().next().unwrap_or("");
+                        is_safe_url(url, true)
+                    })
+                } else {
+                    !URL_ATTRIBUTES.contains(&&*key)
+                        || is_safe_url(value, image)
+                };
+                if safe {
+                    let value = value.replace('"', "&quot;");
+                    sanitized.push_str(&format!(" {key}=\"{value}\""));
+                }
+            } else {
+                sanitized.push(' ');
+                sanitized.push_str(&key);
+            }
+        }
+        if attributes.trim_end().ends_with('/') {
+            sanitized.push_str(" /");
+        }
+        sanitized.push('>');
+    }
+    sanitized.push_str(rest);
+    sanitized
+}
+
+/// Where the tag at the start of `html` ends, skipping `>`s in quoted
+/// attribute values.
+fn tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The names and raw values of a tag's attributes.
+fn attributes_of(mut rest: &str) -> Vec<(&str, Option<&str>)> {
+    let mut attributes = vec![];
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            return attributes;
+        }
+        let end = rest
+            .find(|c: char| c == '=' || c.is_whitespace() || c == '/')
+            .unwrap_or(rest.len());
+        let key = &rest[..end];
+        let after = rest[end..].trim_start();
+        let Some(value) = after.strip_prefix('=') else {
+            attributes.push((key, None));
+            rest = &rest[end..];
+            continue;
+        };
+        let value = value.trim_start();
+        let (value, after) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &value[1..];
+                let end = value.find(quote).unwrap_or(value.len());
+                (&value[..end], value.get(end + 1..).unwrap_or(""))
+            }
+            _ => value.split_once(char::is_whitespace).unwrap_or((value, "")),
+        };
+        attributes.push((key, Some(value)));
+        rest = after;
+    }
+}
+
+/// Whether `url` (as it's written in an attribute) can't run anything.
+/// `data:` URLs are allowed for `image`s.
+fn is_safe_url(url: &str, image: bool) -> bool {
+    // Browsers skip tabs and newlines in the scheme.
+    let url = url
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect::<String>();
+    let url = url
+        .trim_start_matches(|c: char| c <= ' ')
+        .to_ascii_lowercase();
+    let end = url.find(['/', '?', '#']).unwrap_or(url.len());
+    // Browsers decode character references before looking at the scheme,
+    // and there are too many ways to write one (`&#58`, `&#0000058;`,
+    // `&colon;`) to decode them all here, so none are allowed in it.
+    if url[..end].contains('&') {
+        return false;
+    }
+    match url[..end].find(':') {
+        Some(i) => match &url[..i] {
+            "http" | "https" | "mailto" | "tel" => true,
+            "data" => image && url[i + 1..].starts_with("image/"),
+            _ => false,
+        },
+        // Relative.
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::html;
+
+    #[test]
+    fn unsafe_urls() {
+        for href in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            "java\tscript:alert(1)",
+            "javascript&colon;alert(1)",
+            "javascript&#58;alert(1)",
+            "javascript&#58alert(1)",
+            "javascript&#0000000000058;alert(1)",
+            "javascript&#x3a;alert(1)",
+            "&#106;avascript:alert(1)",
+        ] {
+            let sanitized = html(&format!("<a href=\"{href}\">x</a>"));
+            assert!(!sanitized.contains("alert"), "{href}: {sanitized}");
+        }
+    }
+
+    #[test]
+    fn safe_urls() {
+        for href in [
+            "https://example.com/?a=1&amp;b=2",
+            "/blog/post?a=1&b=2",
+            "post#section",
+            "mailto:someone@example.com",
+        ] {
+            let link = format!("<a href=\"{href}\">x</a>");
+            assert_eq!(html(&link), link, "{href}");
+        }
+    }
+}
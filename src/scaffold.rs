@@ -0,0 +1,99 @@
+//! Creating new documents.
+
+use crate::SectionConfig;
+use eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The metadata given to a section's new documents, from the `[defaults]`
+/// table of its `.section.toml`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    /// The title, where `{title}` is replaced by the slug with its words
+    /// separated by spaces and `{slug}` by the slug itself.
+    title: Option<String>,
+    lang: Option<String>,
+    tags: Vec<String>,
+    draft: bool,
+    toc: bool,
+}
+
+/// The metadata block of a new document, leaving out what isn't set.
+#[derive(Serialize)]
+struct NewMeta {
+    title: String,
+    date: chrono::NaiveDate,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    draft: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    toc: bool,
+}
+
+/// Create the document for `path` (`<section>/<slug>` or `<slug>`) in
+/// `content_path`, filling in its metadata from the section's defaults.
+pub fn new(content_path: &Path, path: &str) -> eyre::Result<()> {
+    let path = path.trim_matches('/');
+    let path = ["md", "markdown"]
+        .iter()
+        .find_map(|x| path.strip_suffix(&format!(".{x}")))
+        .unwrap_or(path);
+    let (section, slug) = path.split_once('/').unwrap_or(("", path));
+    if slug.is_empty() || slug.contains('/') || slug.starts_with('.') {
+        return Err(eyre!(
+            "Expected <section>/<slug> or <slug>, got \"{path}\""
+        ));
+    }
+    if section.starts_with('.') {
+        return Err(eyre!("\"{section}\" is hidden, so it can't be a section"));
+    }
+
+    let config_path = content_path.join(section).join(".section.toml");
+    let config = if section.is_empty() || !config_path.is_file() {
+        SectionConfig::default()
+    } else {
+        let config = std::fs::read_to_string(&config_path)?;
+        toml::de::from_str(&config)
+            .map_err(|e| eyre!("Invalid \"{}\": {e}", config_path.display()))?
+    };
+    let defaults = config.defaults;
+
+    let title = defaults
+        .title
+        .as_deref()
+        .unwrap_or("{title}")
+        .replace("{title}", &title_from_slug(slug))
+        .replace("{slug}", slug);
+    let meta = NewMeta {
+        title,
+        date: chrono::Local::now().date_naive(),
+        lang: defaults.lang,
+        tags: defaults.tags,
+        draft: defaults.draft,
+        toc: defaults.toc,
+    };
+    let meta = toml::ser::to_string(&meta)?;
+
+    let file = content_path.join(section).join(format!("{slug}.md"));
+    if file.exists() {
+        return Err(eyre!("\"{}\" already exists", file.display()));
+    }
+    std::fs::create_dir_all(file.parent().expect("has a parent"))?;
+    std::fs::write(&file, format!("```meta\n{meta}```\n\n"))?;
+    println!("Created \"{}\"", file.display());
+    Ok(())
+}
+
+/// Turn a slug like `my-first_post` into `My first post`.
+fn title_from_slug(slug: &str) -> String {
+    let words = slug.replace(['-', '_'], " ");
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => words,
+    }
+}
@@ -0,0 +1,152 @@
+//! A small inverted index for searching documents.
+
+use crate::IndexEntry;
+use std::collections::HashMap;
+
+/// How much a match in each part of a document counts towards its score.
+const TITLE_WEIGHT: u32 = 5;
+const DESC_WEIGHT: u32 = 3;
+const BODY_WEIGHT: u32 = 1;
+
+/// How many bytes of context to show on either side of a match.
+const SNIPPET_CONTEXT: usize = 80;
+
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// The plain text of each document, by URL.
+    texts: HashMap<String, String>,
+    /// The score of each document for a term, by term.
+    terms: HashMap<String, HashMap<String, u32>>,
+}
+
+/// A search result, the snippet is split around the matched text.
+#[derive(Debug)]
+pub struct SearchResult<'a> {
+    pub entry: &'a IndexEntry,
+    pub score: u32,
+    pub snippet: Option<(&'a str, &'a str, &'a str)>,
+}
+
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl SearchIndex {
+    /// Index a document, `text` is its body as plain text.
+    pub fn insert(&mut self, entry: &IndexEntry, text: String) {
+        let meta = &entry.meta;
+        let parts = [
+            (meta.title.as_str(), TITLE_WEIGHT),
+            (meta.desc.as_deref().unwrap_or_default(), DESC_WEIGHT),
+            (text.as_str(), BODY_WEIGHT),
+        ];
+        for (part, weight) in parts {
+            for term in tokenize(part) {
+                *self
+                    .terms
+                    .entry(term)
+                    .or_default()
+                    .entry(entry.url.clone())
+                    .or_default() += weight;
+            }
+        }
+        self.texts.insert(entry.url.clone(), text);
+    }
+
+    /// Find the documents in `index` containing every word of `query`, best
+    /// matches first.
+    pub fn search<'a>(
+        &'a self,
+        index: &'a [IndexEntry],
+        query: &str,
+    ) -> Vec<SearchResult<'a>> {
+        let terms = tokenize(query).collect::<Vec<_>>();
+        if terms.is_empty() {
+            return vec![];
+        }
+
+        let mut results = index
+            .iter()
+            .filter_map(|entry| {
+                let score = terms.iter().try_fold(0, |score, term| {
+                    Some(score + self.terms.get(term)?.get(&entry.url)?)
+                })?;
+                let snippet = self
+                    .texts
+                    .get(&entry.url)
+                    .and_then(|text| snippet(text, &terms));
+                Some(SearchResult {
+                    entry,
+                    score,
+                    snippet,
+                })
+            })
+            .collect::<Vec<_>>();
+        results.sort_by_key(|x| std::cmp::Reverse(x.score));
+        results
+    }
+}
+
+/// Find the first occurrence of any of `terms` in `text` and split out some
+/// context around it.
+fn snippet<'a>(
+    text: &'a str,
+    terms: &[String],
+) -> Option<(&'a str, &'a str, &'a str)> {
+    let lower = text.to_lowercase();
+    // Lowercasing can change byte lengths, in which case the offsets into
+    // `lower` don't apply to `text`.
+    if lower.len() != text.len() {
+        return None;
+    }
+    let (start, term) = terms
+        .iter()
+        .filter_map(|term| lower.find(term.as_str()).map(|i| (i, term)))
+        .min_by_key(|(i, _)| *i)?;
+    let end = start + term.len();
+
+    let mut before = start.saturating_sub(SNIPPET_CONTEXT);
+    while !text.is_char_boundary(before) {
+        before -= 1;
+    }
+    let mut after = (end + SNIPPET_CONTEXT).min(text.len());
+    while !text.is_char_boundary(after) {
+        after += 1;
+    }
+    Some((&text[before..start], &text[start..end], &text[end..after]))
+}
+
+/// The text of a markdown document, without markup, code blocks or
+/// frontmatter.
+pub fn markdown_text(contents: &str) -> String {
+    use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+    let options = Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+        | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
+    let mut text = String::new();
+    let mut in_code_block = false;
+    for event in Parser::new_ext(contents, options) {
+        match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::MetadataBlock(_)) => {
+                in_code_block = true;
+            }
+            Event::End(TagEnd::CodeBlock | TagEnd::MetadataBlock(_)) => {
+                in_code_block = false;
+            }
+            Event::Text(t) | Event::Code(t) if !in_code_block => {
+                text.push_str(&t);
+            }
+            Event::SoftBreak
+            | Event::HardBreak
+            | Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::Item
+                | TagEnd::TableCell,
+            ) => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
@@ -0,0 +1,111 @@
+//! Security headers, which are added to every HTML response.
+//!
+//! Each can be changed in the `[security_headers]` table of `site.toml`, or
+//! left out by setting it to `""`. The default `Content-Security-Policy`
+//! allows what the templates need: the site's own scripts and stylesheets,
+//! inline styles (the page's styles, math and diagrams have them), images
+//! from anywhere and the players of video embeds.
+
+use crate::mermaid::MermaidConfig;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use tiny_http::{Header, Response};
+use url::Url;
+
+/// Where video embeds load their players from.
+const FRAMES: [&str; 2] = [
+    "https://www.youtube-nocookie.com",
+    "https://player.vimeo.com",
+];
+
+thread_local! {
+    /// The headers for the site the request being handled by this thread is
+    /// for, set when it's received like the access log's start time.
+    static CURRENT: RefCell<Vec<Header>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The `[security_headers]` table of `site.toml`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeaders {
+    /// Derived from the site's settings if it isn't set.
+    pub content_security_policy: Option<String>,
+    pub content_type_options: String,
+    pub referrer_policy: String,
+    /// Browsers ignore it on plain HTTP, so it's only in effect once the site
+    /// has been visited over HTTPS.
+    pub strict_transport_security: String,
+    pub permissions_policy: String,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            content_security_policy: None,
+            content_type_options: "nosniff".to_string(),
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+            strict_transport_security: "max-age=31536000".to_string(),
+            permissions_policy: "camera=(), geolocation=(), microphone=()"
+                .to_string(),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    /// The headers to add, with the default policy allowing the Mermaid
+    /// script if it's on another site.
+    fn headers(&self, mermaid: &MermaidConfig) -> Vec<Header> {
+        let csp = match &self.content_security_policy {
+            Some(x) => x.clone(),
+            None => default_policy(mermaid),
+        };
+        [
+            ("Content-Security-Policy", csp.as_str()),
+            ("X-Content-Type-Options", &self.content_type_options),
+            ("Referrer-Policy", &self.referrer_policy),
+            ("Strict-Transport-Security", &self.strict_transport_security),
+            ("Permissions-Policy", &self.permissions_policy),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| crate::http::make_header(name, value))
+        .collect()
+    }
+}
+
+fn default_policy(mermaid: &MermaidConfig) -> String {
+    let mut scripts = "'self'".to_string();
+    if let Some(script) = &mermaid.script
+        && let Ok(url) = Url::parse(script)
+    {
+        scripts.push(' ');
+        scripts.push_str(&url.origin().ascii_serialization());
+    }
+    format!(
+        "default-src 'self'; script-src {scripts}; \
+         style-src 'self' 'unsafe-inline'; img-src 'self' data: https:; \
+         media-src 'self' https:; frame-src {}; object-src 'none'; \
+         base-uri 'self'; form-action 'self'; frame-ancestors 'self'",
+        FRAMES.join(" ")
+    )
+}
+
+/// Note the headers for the request this thread is handling, which is for a
+/// site with `config`.
+pub fn begin(config: &SecurityHeaders, mermaid: &MermaidConfig) {
+    CURRENT.set(config.headers(mermaid));
+}
+
+/// Add the headers for the current request to `response`, if it's HTML.
+pub fn apply<R: std::io::Read>(response: &mut Response<R>) {
+    let headers = CURRENT.take();
+    let html = response.headers().iter().any(|x| {
+        x.field.equiv("Content-Type")
+            && x.value.as_str().starts_with("text/html")
+    });
+    if html {
+        for header in headers {
+            response.add_header(header);
+        }
+    }
+}
@@ -0,0 +1,504 @@
+//! Serving sites over HTTP, for the `site` binary and programs that embed it.
+
+use crate::backend::{self, Backend, Front, Tls};
+use crate::listen::BindAddr;
+use crate::{
+    Control, DEBOUNCE, LoadOptions, ServeOptions, Site, State, access,
+    access_log, base_path, git_pull, listen, pool, ratelimit, stats, systemd,
+};
+use eyre::eyre;
+use log::{error, info};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tiny_http::{Request, ResponseBox};
+
+/// Answers a request before the site does, if it returns a response.
+type RequestHook = Box<dyn Fn(&Request) -> Option<ResponseBox> + Send + Sync>;
+/// Called with each site after it's reloaded.
+type ReloadHook = Box<dyn Fn(&Site) + Send + Sync>;
+
+/// Code run by the server for programs that embed it.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    pub request: Vec<RequestHook>,
+    pub reload: Vec<ReloadHook>,
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("request", &self.request.len())
+            .field("reload", &self.reload.len())
+            .finish()
+    }
+}
+
+/// Builds and runs a server for a content directory, and any others served
+/// for particular hosts. The defaults match the `site` binary's.
+#[derive(Debug)]
+pub struct Server {
+    content_path: PathBuf,
+    vhosts: Vec<(String, PathBuf)>,
+    bind: Vec<BindAddr>,
+    socket_mode: Option<u32>,
+    threads: usize,
+    queue_size: usize,
+    load: LoadOptions,
+    control_token: Option<String>,
+    webhook_secret: Option<String>,
+    git_pull: bool,
+    preview_token: Option<String>,
+    trust_proxy: bool,
+    /// Requests per second and burst size.
+    rate_limit: Option<(f64, u32)>,
+    access: access::Access,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    backend: Backend,
+    tls: Option<Tls>,
+    base_path: Option<String>,
+    watch: bool,
+    stats_file: Option<PathBuf>,
+    access_log: Option<PathBuf>,
+    signals: bool,
+    hooks: Hooks,
+    reload: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
+}
+
+/// Asks a running [`Server`] to reload its content or shut down.
+#[derive(Debug, Clone)]
+pub struct Handle {
+    reload: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Handle {
+    pub fn reload(&self) {
+        self.reload.store(true, Ordering::Relaxed);
+    }
+
+    /// Stop accepting requests, finish the ones already accepted and return
+    /// from [`Server::run`].
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Server {
+    /// Serve the content in `content_path`.
+    pub fn new(content_path: impl Into<PathBuf>) -> Self {
+        Self {
+            content_path: content_path.into(),
+            vhosts: vec![],
+            bind: vec![],
+            socket_mode: None,
+            threads: 4,
+            queue_size: 64,
+            load: LoadOptions {
+                cache_size: 32 * 1024 * 1024,
+                ..LoadOptions::default()
+            },
+            control_token: None,
+            webhook_secret: None,
+            git_pull: false,
+            preview_token: None,
+            trust_proxy: false,
+            rate_limit: None,
+            access: access::Access::default(),
+            read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            request_timeout: Some(Duration::from_secs(300)),
+            backend: Backend::Threads,
+            tls: None,
+            base_path: None,
+            watch: false,
+            stats_file: None,
+            access_log: None,
+            signals: false,
+            hooks: Hooks::default(),
+            reload: Arc::new(AtomicBool::new(false)),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Serve the content in `content_path` for requests to `host`.
+    pub fn vhost(
+        mut self,
+        host: impl Into<String>,
+        content_path: impl Into<PathBuf>,
+    ) -> Self {
+        self.vhosts
+            .push((host.into().to_ascii_lowercase(), content_path.into()));
+        self
+    }
+
+    /// Listen on `addr`, as well as the other addresses it's called with.
+    /// Ignored when systemd passes sockets.
+    pub fn bind(mut self, addr: BindAddr) -> Self {
+        self.bind.push(addr);
+        self
+    }
+
+    /// The permissions of a Unix socket.
+    pub fn socket_mode(mut self, mode: impl Into<Option<u32>>) -> Self {
+        self.socket_mode = mode.into();
+        self
+    }
+
+    /// How many threads handle requests, and how many requests can wait for
+    /// them before more are answered with `503 Service Unavailable`.
+    pub fn threads(mut self, threads: usize, queue_size: usize) -> Self {
+        self.threads = threads;
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// How the content is loaded.
+    pub fn load_options(mut self, options: LoadOptions) -> Self {
+        self.load = options;
+        self
+    }
+
+    /// Read template overrides from `dir` instead of each content
+    /// directory's `.templates/`.
+    pub fn templates(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.load.templates = Some(dir.into());
+        self
+    }
+
+    /// Enable the control endpoints and the stats page with this token.
+    pub fn control_token(mut self, token: impl Into<Option<String>>) -> Self {
+        self.control_token = token.into();
+        self
+    }
+
+    /// Enable the `/.reload` webhook with this secret, running `git pull`
+    /// before reloading if `git_pull` is set.
+    pub fn webhook(
+        mut self,
+        secret: impl Into<Option<String>>,
+        git_pull: bool,
+    ) -> Self {
+        self.webhook_secret = secret.into();
+        self.git_pull = git_pull;
+        self
+    }
+
+    /// Allow viewing drafts with this token.
+    pub fn preview_token(mut self, token: impl Into<Option<String>>) -> Self {
+        self.preview_token = token.into();
+        self
+    }
+
+    /// Believe the `X-Forwarded-*` headers.
+    pub fn trust_proxy(mut self, trust: bool) -> Self {
+        self.trust_proxy = trust;
+        self
+    }
+
+    /// Limit each client to `rate` requests per second after a `burst`.
+    pub fn rate_limit(
+        mut self,
+        rate: impl Into<Option<f64>>,
+        burst: u32,
+    ) -> Self {
+        self.rate_limit = rate.into().filter(|x| *x > 0.0).map(|x| (x, burst));
+        self
+    }
+
+    /// Only let through the clients `access` allows, on top of what each
+    /// site allows.
+    pub fn access(mut self, access: access::Access) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Give up on a TCP connection when reading from or writing to it takes
+    /// longer than `read` or `write`, and on a request that hasn't been
+    /// answered after `request`. `None` waits forever.
+    pub fn timeouts(
+        mut self,
+        read: impl Into<Option<Duration>>,
+        write: impl Into<Option<Duration>>,
+        request: impl Into<Option<Duration>>,
+    ) -> Self {
+        self.read_timeout = read.into().filter(|x| !x.is_zero());
+        self.write_timeout = write.into().filter(|x| !x.is_zero());
+        self.request_timeout = request.into().filter(|x| !x.is_zero());
+        self
+    }
+
+    /// Accept connections with `backend`.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Serve HTTPS with `tls`, which needs the async backend.
+    pub fn tls(mut self, tls: impl Into<Option<Tls>>) -> Self {
+        self.tls = tls.into();
+        self
+    }
+
+    /// Serve the sites under `path`, e.g. `/blog`, for a reverse proxy that
+    /// passes them a path of a larger site.
+    pub fn base_path(mut self, path: impl Into<Option<String>>) -> Self {
+        self.base_path = path.into();
+        self
+    }
+
+    /// Reload when something in a content directory changes.
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    /// Keep the view counts in this file.
+    pub fn stats_file(mut self, path: impl Into<Option<PathBuf>>) -> Self {
+        self.stats_file = path.into();
+        self
+    }
+
+    /// Write the access log to this file instead of standard output.
+    pub fn access_log(mut self, path: impl Into<Option<PathBuf>>) -> Self {
+        self.access_log = path.into();
+        self
+    }
+
+    /// Reload on `SIGHUP` and reopen the access log on `SIGUSR1`.
+    pub fn handle_signals(mut self, signals: bool) -> Self {
+        self.signals = signals;
+        self
+    }
+
+    /// Answer requests with `hook` before the site does, whenever it returns
+    /// a response. Hooks are tried in the order they're added.
+    pub fn on_request(
+        mut self,
+        hook: impl Fn(&Request) -> Option<ResponseBox> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.request.push(Box::new(hook));
+        self
+    }
+
+    /// Call `hook` with each site after it's reloaded.
+    pub fn on_reload(
+        mut self,
+        hook: impl Fn(&Site) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.reload.push(Box::new(hook));
+        self
+    }
+
+    /// A handle to reload or shut down the server once it's running.
+    pub fn handle(&self) -> Handle {
+        Handle {
+            reload: self.reload.clone(),
+            shutdown: self.shutdown.clone(),
+        }
+    }
+
+    /// Load the content and serve it until the server is shut down, through
+    /// a [`Handle`] or the control endpoint.
+    pub fn run(self) -> eyre::Result<()> {
+        let control = Arc::new(Control {
+            token: self.control_token,
+            webhook_secret: self.webhook_secret,
+            git_pull: self.git_pull,
+            reload: self.reload,
+            pull: AtomicBool::new(false),
+            shutdown: self.shutdown,
+            reopen_log: Arc::new(AtomicBool::new(false)),
+        });
+        if self.signals {
+            use signal_hook::consts::signal::{SIGHUP, SIGUSR1};
+            signal_hook::flag::register(SIGHUP, control.reload.clone())?;
+            signal_hook::flag::register(SIGUSR1, control.reopen_log.clone())?;
+        }
+
+        let load_options = self.load;
+        let mut sites = vec![];
+        let content_paths = std::iter::once((None, self.content_path))
+            .chain(self.vhosts.into_iter().map(|(x, y)| (Some(x), y)));
+        for (host, path) in content_paths {
+            let content_path: Arc<Path> =
+                std::fs::canonicalize(&path)?.as_path().into();
+            let state = State::load(&content_path, &load_options, None)?;
+            if let Some(host) = &host {
+                info!("Serving \"{}\" for {host}", content_path.display());
+            }
+            sites.push(Site {
+                host,
+                content_path,
+                state: Arc::new(RwLock::new(state)),
+            });
+        }
+        let sites: Arc<[Site]> = sites.into();
+        access_log::init(self.access_log.as_deref())?;
+
+        let last_change = Arc::new(Mutex::new(None));
+        let _watchers = if self.watch {
+            sites
+                .iter()
+                .map(|x| crate::watch(&x.content_path, last_change.clone()))
+                .collect::<eyre::Result<Vec<_>>>()?
+        } else {
+            vec![]
+        };
+
+        if self.tls.is_some() && self.backend != Backend::Async {
+            return Err(eyre!("HTTPS needs the async backend"));
+        }
+        let mut bind = self.bind;
+        if bind.is_empty() {
+            bind.push(BindAddr::Tcp(([127, 0, 0, 2], 6969).into()));
+        }
+        let activated = systemd::listeners();
+        let from_systemd = !activated.is_empty();
+        let listeners = if from_systemd {
+            info!("Spawned server on the sockets passed by systemd");
+            activated
+        } else {
+            let mut listeners = vec![];
+            for addr in &bind {
+                listeners.push(listen::bind(addr, self.socket_mode)?);
+                info!("Spawned server on address: {addr}");
+            }
+            listeners
+        };
+        let (listeners, front, timeouts) = match self.backend {
+            Backend::Threads => {
+                let timeouts = listen::Timeouts::new(
+                    self.read_timeout,
+                    self.write_timeout,
+                );
+                (listeners, None, Some(Arc::new(timeouts)))
+            }
+            Backend::Async => {
+                let (front, internal) = Front::spawn(
+                    listeners,
+                    self.trust_proxy,
+                    self.tls.as_ref(),
+                    backend::Timeouts {
+                        read: self.read_timeout,
+                        write: self.write_timeout,
+                    },
+                )?;
+                info!("Accepting connections with the async backend");
+                (vec![internal], Some(front), None)
+            }
+        };
+        let servers = listeners
+            .into_iter()
+            .map(|x| {
+                tiny_http::Server::from_listener(x, None)
+                    .map(Arc::new)
+                    .map_err(|e| eyre!("{e}"))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        let serve_options = Arc::new(ServeOptions {
+            stats: stats::Stats::load(self.stats_file.as_deref())?,
+            pool: Arc::new(pool::Usage::new(self.threads, self.queue_size)),
+            preview_token: self.preview_token,
+            // The async backend passes the client's address on in
+            // `X-Forwarded-For`.
+            trust_proxy: self.trust_proxy || front.is_some(),
+            rate_limiter: self
+                .rate_limit
+                .map(|(rate, burst)| ratelimit::RateLimiter::new(rate, burst)),
+            access: self.access,
+            request_timeout: self.request_timeout,
+            base_path: base_path::normalize(self.base_path.as_deref()),
+            hooks: self.hooks,
+        });
+        let pool = Arc::new(pool::Pool::new(serve_options.pool.clone(), {
+            let sites = sites.clone();
+            let control = control.clone();
+            let serve_options = serve_options.clone();
+            move |rq| crate::serve(rq, &sites, &control, &serve_options)
+        }));
+        // One thread accepts the requests of each address, for the same
+        // serve threads.
+        let acceptors = servers
+            .iter()
+            .map(|server| {
+                let server = server.clone();
+                let pool = pool.clone();
+                let control = control.clone();
+                let timeouts = timeouts.clone();
+                std::thread::spawn(move || {
+                    crate::accept(&server, &pool, &control, timeouts.as_deref())
+                })
+            })
+            .collect::<Vec<_>>();
+        systemd::notify("READY=1");
+
+        loop {
+            if control.shutdown.load(Ordering::Relaxed) {
+                info!("Shutting down...");
+                systemd::notify("STOPPING=1");
+                serve_options.stats.save();
+                if let Some(front) = front {
+                    front.stop();
+                }
+                for server in &servers {
+                    server.unblock();
+                }
+                for acceptor in acceptors {
+                    if let Ok(Err(e)) = acceptor.join() {
+                        error!("Failed to accept requests: {e}");
+                    }
+                }
+                // The acceptors had the other references.
+                if let Ok(pool) = Arc::try_unwrap(pool) {
+                    pool.join();
+                }
+                // systemd owns the sockets it passed.
+                if !from_systemd {
+                    for addr in &bind {
+                        listen::cleanup(addr);
+                    }
+                }
+                return Ok(());
+            }
+
+            // Wait for changes to settle before reloading, editors and git
+            // tend to touch several files in quick succession.
+            {
+                let mut last_change = last_change.lock().unwrap();
+                if last_change.is_some_and(|x: Instant| x.elapsed() >= DEBOUNCE)
+                {
+                    *last_change = None;
+                    control.reload.store(true, Ordering::Relaxed);
+                }
+            }
+
+            if control.reopen_log.swap(false, Ordering::Relaxed) {
+                access_log::reopen();
+            }
+
+            if control.reload.swap(false, Ordering::Relaxed) {
+                let pull = control.pull.swap(false, Ordering::Relaxed);
+                systemd::notify("RELOADING=1");
+                serve_options.stats.save();
+                for site in sites.iter() {
+                    if pull {
+                        git_pull(&site.content_path);
+                    }
+                    site.reload(&load_options);
+                    for hook in &serve_options.hooks.reload {
+                        hook(site);
+                    }
+                }
+                systemd::notify("READY=1");
+            }
+
+            std::thread::sleep(Duration::from_millis(256));
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Shortcodes, e.g. `{{ figure src="cat.png" caption="A cat" }}`, which are
+//! expanded before a document is parsed.
+//!
+//! `figure`, `youtube`, `vimeo` and `include` are built in. Any other
+//! shortcode is the template `.templates/shortcodes/<name>.html`, which is
+//! given the arguments as values and can also replace a built-in one.
+//! Shortcodes in code are left alone, as are ones escaped as `\{{`.
+//!
+//! Markdown that's included has its own shortcodes expanded, so it can include
+//! more, but a file can't end up including itself.
+
+use crate::overrides::Overrides;
+use log::warn;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A document's source with its shortcodes expanded.
+#[derive(Debug)]
+pub struct Expanded<'s> {
+    pub source: Cow<'s, str>,
+    /// Whether there are embeds, which need the script that loads them.
+    pub embeds: bool,
+}
+
+/// Expand the shortcodes in `source`, which is parsed with `options`.
+/// Included files are read from `content_path`.
+pub fn expand<'s>(
+    source: &'s str,
+    options: pulldown_cmark::Options,
+    templates: &Overrides,
+    content_path: &Path,
+) -> Expanded<'s> {
+    let mut expander = Expander {
+        options,
+        templates,
+        content_path,
+        including: vec![],
+        embeds: false,
+    };
+    let source = expander.expand(source);
+    Expanded {
+        source,
+        embeds: expander.embeds,
+    }
+}
+
+struct Expander<'a> {
+    options: pulldown_cmark::Options,
+    templates: &'a Overrides,
+    content_path: &'a Path,
+    /// The files being included, innermost last.
+    including: Vec<PathBuf>,
+    embeds: bool,
+}
+
+impl Expander<'_> {
+    fn expand<'s>(&mut self, source: &'s str) -> Cow<'s, str> {
+        if !source.contains("{{") {
+            return Cow::Borrowed(source);
+        }
+        let skipped = skipped_ranges(source, self.options);
+        let mut out = String::with_capacity(source.len());
+        let mut end = 0;
+        let mut offset = 0;
+        while let Some(start) = source[offset..].find("{{").map(|x| x + offset)
+        {
+            offset = start + 2;
+            if source[..start].ends_with('\\')
+                || skipped.iter().any(|x| x.contains(&start))
+            {
+                continue;
+            }
+            let Some(len) = source[offset..].find("}}") else {
+                break;
+            };
+            let inner = &source[offset..offset + len];
+            let Some((name, args)) = parse(inner) else {
+                continue;
+            };
+            let html = match self.templates.render(
+                &format!("shortcodes/{name}.html"),
+                minijinja::Value::from_serialize(&args),
+            ) {
+                Some(html) => html,
+                None => match self.builtin(name, &args) {
+                    Some(html) => html,
+                    None => continue,
+                },
+            };
+            out.push_str(&source[end..start]);
+            out.push_str(&html);
+            end = offset + len + 2;
+            offset = end;
+        }
+        if end == 0 {
+            return Cow::Borrowed(source);
+        }
+        out.push_str(&source[end..]);
+        Cow::Owned(out)
+    }
+
+    /// Expand a built-in shortcode. `None` if there's no such shortcode or
+    /// it's missing an argument.
+    fn builtin(
+        &mut self,
+        name: &str,
+        args: &BTreeMap<String, String>,
+    ) -> Option<String> {
+        let arg = |key: &str| {
+            let value = args.get(key).map(String::as_str);
+            if value.is_none() {
+                warn!("The \"{name}\" shortcode needs a \"{key}\"");
+            }
+            value
+        };
+        Some(match name {
+            "figure" => {
+                let src = escape(arg("src")?);
+                let alt = escape(args.get("alt").map_or("", String::as_str));
+                let caption = args.get("caption").map_or(String::new(), |x| {
+                    format!("<figcaption>{}</figcaption>", escape(x))
+                });
+                format!(
+                    "<figure class=\"figure\"><img src=\"{src}\" alt=\"{alt}\" \
+                     loading=\"lazy\">{caption}</figure>"
+                )
+            }
+            "youtube" | "vimeo" => {
+                let id = percent_encoding::utf8_percent_encode(
+                    arg("id")?,
+                    percent_encoding::NON_ALPHANUMERIC,
+                )
+                .to_string();
+                let (embed, link) = if name == "youtube" {
+                    (
+                        format!(
+                            "https://www.youtube-nocookie.com/embed/{id}\
+                             ?autoplay=1"
+                        ),
+                        format!("https://www.youtube.com/watch?v={id}"),
+                    )
+                } else {
+                    (
+                        format!(
+                            "https://player.vimeo.com/video/{id}\
+                             ?autoplay=1&dnt=1"
+                        ),
+                        format!("https://vimeo.com/{id}"),
+                    )
+                };
+                let title =
+                    escape(args.get("title").map_or("Video", String::as_str));
+                // Nothing is loaded from the video's site until the reader
+                // asks for it, the script swaps the link for the player.
+                self.embeds = true;
+                format!(
+                    "<div class=\"embed\" data-embed=\"{}\" \
+                     data-title=\"{title}\"><a class=\"embed-placeholder\" \
+                     href=\"{}\">&#9654; {title}</a></div>",
+                    escape(&embed),
+                    escape(&link),
+                )
+            }
+            "include" => {
+                let file = match args.get("0") {
+                    Some(file) => file,
+                    None => arg("file")?,
+                };
+                let (path, contents) = self.include(file)?;
+                if let Some(lang) = args.get("lang") {
+                    return Some(format!("```{lang}\n{contents}\n```"));
+                }
+                if self.including.contains(&path) {
+                    warn!("Can't include \"{file}\" in itself");
+                    return None;
+                }
+                self.including.push(path);
+                let contents = self.expand(&contents).into_owned();
+                self.including.pop();
+                contents
+            }
+            _ => {
+                warn!("Unknown shortcode \"{name}\"");
+                return None;
+            }
+        })
+    }
+
+    /// Read `file`, relative to the content directory, along with its
+    /// canonical path. Files outside of the content directory can't be
+    /// included.
+    fn include(&self, file: &str) -> Option<(PathBuf, String)> {
+        let path = self.content_path.join(file.trim_start_matches('/'));
+        let Some(path) = std::fs::canonicalize(path)
+            .ok()
+            .zip(std::fs::canonicalize(self.content_path).ok())
+            .and_then(|(path, root)| path.starts_with(root).then_some(path))
+        else {
+            warn!(
+                "Can't include \"{file}\", it isn't in the content directory"
+            );
+            return None;
+        };
+        let contents = std::fs::read_to_string(&path)
+            .inspect_err(|e| warn!("Failed to include \"{file}\": {e}"))
+            .ok()?;
+        Some((path, contents.trim_end().to_string()))
+    }
+}
+
+/// Where the code and metadata in `source` are.
+fn skipped_ranges(
+    source: &str,
+    options: pulldown_cmark::Options,
+) -> Vec<Range<usize>> {
+    use pulldown_cmark::{Event, Parser, Tag};
+    Parser::new_ext(source, options)
+        .into_offset_iter()
+        // A start's range covers the whole element.
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::MetadataBlock(_))
+            | Event::Code(_) => Some(range),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Split the inside of a shortcode into its name and arguments, e.g.
+/// `figure src="cat.png"` into `figure` and `{src: cat.png}`. Arguments
+/// without a name are numbered from 0. `None` if it isn't a shortcode.
+fn parse(inner: &str) -> Option<(&str, BTreeMap<String, String>)> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    let inner = inner.trim();
+    let (name, mut rest) =
+        inner.split_once(char::is_whitespace).unwrap_or((inner, ""));
+    if name.is_empty() || !name.chars().all(is_name) {
+        return None;
+    }
+    let mut args = BTreeMap::new();
+    let mut positional = 0;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break Some((name, args));
+        }
+        let key_len = rest.find(|c| !is_name(c)).unwrap_or(rest.len());
+        let (key, after) = match rest[key_len..].trim_start().strip_prefix('=')
+        {
+            Some(after) if key_len > 0 => {
+                (rest[..key_len].to_string(), after.trim_start())
+            }
+            _ => {
+                positional += 1;
+                ((positional - 1).to_string(), rest)
+            }
+        };
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => {
+                after.split_once(char::is_whitespace).unwrap_or((after, ""))
+            }
+        };
+        args.insert(key, value.to_string());
+        rest = after;
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
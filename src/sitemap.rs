@@ -0,0 +1,68 @@
+//! `sitemap.xml` and `robots.txt`.
+
+use crate::State;
+use chrono::NaiveDate;
+use rinja::Template;
+
+#[derive(Template)]
+#[template(ext = "xml", path = "sitemap.xml")]
+struct SitemapTemplate<'a> {
+    base: &'a str,
+    urls: &'a [(String, Option<NaiveDate>)],
+}
+
+/// List every section index and document, or `None` if the sitemap can't be
+/// rendered.
+pub fn sitemap(state: &State, origin: &str) -> Option<String> {
+    let listed = |url: &str| {
+        crate::auth::listable(&state.config.protected, url, "/sitemap.xml")
+    };
+    let mut urls = vec![];
+    for section in &state.sections {
+        let (path, url) = if section.name.is_empty() {
+            ("/index.html".to_string(), "/index.html".to_string())
+        } else {
+            (
+                format!("/{}/index.html", section.name),
+                format!(
+                    "/{}/index.html",
+                    crate::http::encode_path(&section.name)
+                ),
+            )
+        };
+        if !listed(&path) {
+            continue;
+        }
+        let lastmod = state
+            .index
+            .iter()
+            .filter(|x| section.name.is_empty() || x.section == section.name)
+            .filter(|x| listed(&x.url))
+            .filter_map(|x| x.meta.last_updated())
+            .max();
+        urls.push((url, lastmod));
+    }
+    for entry in state.index.iter().filter(|x| listed(&x.url)) {
+        urls.push((
+            crate::http::encode_path(&entry.url),
+            entry.meta.last_updated(),
+        ));
+    }
+
+    let template = SitemapTemplate {
+        base: state.config.base(origin),
+        urls: &urls,
+    };
+    crate::render(&template, "sitemap.xml")
+}
+
+/// The configured `robots.txt`, or one allowing everything.
+pub fn robots(state: &State, origin: &str) -> String {
+    match &state.config.robots {
+        Some(robots) => robots.clone(),
+        None => format!(
+            "User-agent: *\nAllow: /\n\nSitemap: {}/sitemap.xml\n",
+            state.config.base(origin)
+        ),
+    }
+}
@@ -0,0 +1,123 @@
+//! Counting views of documents, for the `/.stats` page.
+
+use chrono::{DateTime, Local};
+use log::{error, info};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// How many of the latest views are kept.
+const RECENT: usize = 100;
+
+/// View counts by site (named by its host, empty for the main content) and
+/// document URL, as they're saved.
+type Counts = BTreeMap<String, BTreeMap<String, u64>>;
+
+#[derive(Debug)]
+pub struct Stats {
+    /// Where the counts are saved, they're lost on restart if `None`.
+    path: Option<PathBuf>,
+    counts: Mutex<Counts>,
+    recent: Mutex<VecDeque<View>>,
+}
+
+/// A document being viewed.
+#[derive(Debug, Clone)]
+pub struct View {
+    pub time: DateTime<Local>,
+    site: String,
+    pub url: String,
+}
+
+impl Stats {
+    /// Start counting, from the counts saved at `path` if there are any.
+    pub fn load(path: Option<&Path>) -> eyre::Result<Self> {
+        let counts = match path {
+            Some(path) if path.is_file() => {
+                let counts = std::fs::read_to_string(path)?;
+                toml::de::from_str(&counts).map_err(|e| {
+                    eyre::eyre!("Invalid \"{}\": {e}", path.display())
+                })?
+            }
+            _ => Counts::new(),
+        };
+        Ok(Self {
+            path: path.map(Path::to_path_buf),
+            counts: Mutex::new(counts),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT)),
+        })
+    }
+
+    /// Count a view of the document at `url` on the site for `host`.
+    pub fn record(&self, host: Option<&str>, url: &str) {
+        let site = host.unwrap_or_default().to_string();
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .entry(site.clone())
+            .or_default()
+            .entry(url.to_string())
+            .or_default() += 1;
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT {
+            recent.pop_front();
+        }
+        recent.push_back(View {
+            time: Local::now(),
+            site,
+            url: url.to_string(),
+        });
+    }
+
+    /// The `n` most viewed documents of the site for `host` and their view
+    /// counts, most viewed first.
+    pub fn top(&self, host: Option<&str>, n: usize) -> Vec<(String, u64)> {
+        let counts = self.counts.lock().unwrap();
+        let Some(counts) = counts.get(host.unwrap_or_default()) else {
+            return vec![];
+        };
+        let mut top = counts
+            .iter()
+            .map(|(url, count)| (url.clone(), *count))
+            .collect::<Vec<_>>();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
+
+    /// The latest views of the site for `host`, newest first.
+    pub fn recent(&self, host: Option<&str>) -> Vec<View> {
+        let host = host.unwrap_or_default();
+        self.recent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|x| x.site == host)
+            .cloned()
+            .collect()
+    }
+
+    /// Write the counts to the file they're kept in, if there is one.
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let counts = toml::ser::to_string(&*self.counts.lock().unwrap())
+            .expect("counts are serializable");
+        // Write to a temporary file first so that a crash can't leave the
+        // counts half written.
+        let temp = path.with_extension("tmp");
+        match std::fs::write(&temp, counts)
+            .and_then(|()| std::fs::rename(&temp, path))
+        {
+            Ok(()) => info!("Saved view counts to \"{}\"", path.display()),
+            Err(e) => error!(
+                "Failed to save view counts to \"{}\": {e}",
+                path.display()
+            ),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Documents to suggest on the 404 page, for URLs that were mistyped or cut
+//! off.
+//!
+//! A document is suggested if its URL starts with the requested path, or if
+//! either its URL or its last segment is only a few edits from the request's.
+
+use crate::{IndexEntry, State};
+
+/// How many documents are suggested at most.
+const MAX: usize = 5;
+
+/// The documents whose URLs are closest to `path`, which wasn't found,
+/// closest first.
+pub fn similar<'a>(state: &'a State, path: &str) -> Vec<&'a IndexEntry> {
+    let path = path.trim_end_matches(".html").to_lowercase();
+    let name = last_segment(&path);
+    // A few characters are too little to go on.
+    if name.chars().count() < 3 {
+        return vec![];
+    }
+    let max_distance = (name.chars().count() / 3).max(1);
+
+    let mut similar = state
+        .index
+        .iter()
+        .filter(|x| state.is_listed_at(x, &path))
+        .filter_map(|x| {
+            let url = x.url.to_lowercase();
+            if url.starts_with(&path) {
+                return Some((0, x));
+            }
+            let distance = strsim::levenshtein(&path, &url)
+                .min(strsim::levenshtein(name, last_segment(&url)));
+            (distance <= max_distance).then_some((distance, x))
+        })
+        .collect::<Vec<_>>();
+    similar.sort_by(|(a, x), (b, y)| a.cmp(b).then(x.url.cmp(&y.url)));
+    similar.into_iter().take(MAX).map(|(_, x)| x).collect()
+}
+
+fn last_segment(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
@@ -0,0 +1,70 @@
+//! Socket activation and readiness notification for running as a systemd
+//! service, see `sd_listen_fds(3)` and `sd_notify(3)`.
+
+use log::{debug, warn};
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+
+/// The first file descriptor systemd passes sockets in.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The sockets systemd is listening on for us, or none if we weren't started
+/// by socket activation.
+pub fn listeners() -> Vec<tiny_http::Listener> {
+    let Some(fds) = listen_fds() else {
+        return vec![];
+    };
+    (LISTEN_FDS_START..LISTEN_FDS_START + fds)
+        .map(listener)
+        .collect()
+}
+
+/// How many sockets systemd passed.
+fn listen_fds() -> Option<RawFd> {
+    let pid = std::env::var("LISTEN_PID").ok()?;
+    // The variables are inherited by children that weren't meant to have the
+    // sockets.
+    if pid.parse::<u32>().ok()? != std::process::id() {
+        return None;
+    }
+    std::env::var("LISTEN_FDS").ok()?.parse::<RawFd>().ok()
+}
+
+fn listener(fd: RawFd) -> tiny_http::Listener {
+    // SAFETY: systemd passes the sockets starting at `LISTEN_FDS_START` and
+    // nothing else owns them.
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    if let Ok(addr) = tcp.local_addr() {
+        debug!("Listening on {addr} from systemd");
+        return tcp.into();
+    }
+    // Not an IP socket, so it should be a Unix one.
+    let fd = tcp.into_raw_fd();
+    // SAFETY: as above, ownership was just given back by `into_raw_fd`.
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+    debug!("Listening on a Unix socket from systemd");
+    unix.into()
+}
+
+/// Tell systemd about the service's state, e.g. `READY=1`. Does nothing if
+/// we aren't running under systemd.
+pub fn notify(state: &str) {
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        // Sockets in the abstract namespace start with an `@`.
+        let addr = match path.as_encoded_bytes().strip_prefix(b"@") {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                SocketAddr::from_abstract_name(name)?
+            }
+            _ => SocketAddr::from_pathname(&path)?,
+        };
+        socket.send_to_addr(state.as_bytes(), &addr)
+    });
+    if let Err(e) = result {
+        warn!("Failed to notify systemd of \"{state}\": {e}");
+    }
+}
@@ -0,0 +1,70 @@
+//! CSV and TSV files in the assets directory, shown as tables that can be
+//! sorted by clicking their headers.
+
+use log::error;
+
+/// How many rows are shown, so that a big dataset doesn't make a page too
+/// big to load. The rest are only in the file itself.
+const MAX_ROWS: usize = 1000;
+
+/// The delimiter of the file at `path`, if it's one that's shown as a table.
+pub fn delimiter(path: &std::path::Path) -> Option<u8> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "csv" => Some(b','),
+        "tsv" => Some(b'\t'),
+        _ => None,
+    }
+}
+
+/// The table of `contents`, with its first row as the header. `None` if it
+/// can't be read.
+pub fn html(contents: &str, delimiter: u8) -> Option<String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents.as_bytes());
+    let mut html =
+        String::from("<div class=\"data\"><table class=\"sortable\">");
+    let mut rows = 0;
+    for record in reader.records() {
+        let record = record
+            .inspect_err(|e| error!("Failed to read a table: {e}"))
+            .ok()?;
+        if rows == 0 {
+            html.push_str("<thead><tr>");
+            for cell in &record {
+                html.push_str(&format!(
+                    "<th scope=\"col\">{}</th>",
+                    escape(cell)
+                ));
+            }
+            html.push_str("</tr></thead><tbody>");
+        } else if rows <= MAX_ROWS {
+            html.push_str("<tr>");
+            for cell in &record {
+                html.push_str(&format!("<td>{}</td>", escape(cell)));
+            }
+            html.push_str("</tr>");
+        }
+        rows += 1;
+    }
+    html.push_str("</tbody></table></div>");
+    // Not counting the header.
+    let rows = rows.saturating_sub(1);
+    if rows > MAX_ROWS {
+        html.push_str(&format!(
+            "<p class=\"truncated\">Showing the first {MAX_ROWS} of {rows} \
+             rows.</p>"
+        ));
+    }
+    Some(html)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,65 @@
+//! Keeping slow clients from holding on to the serve threads, by cutting
+//! requests off once they've taken too long.
+//!
+//! Each read or write of a TCP connection has its own timeout, set on its
+//! socket once its first request arrives (see [`crate::listen::Timeouts`]),
+//! which tiny_http answers with `408 Request Timeout` while reading a later
+//! request. A
+//! client can still keep a thread busy by reading a response a few bytes at
+//! a time, so request bodies and responses are also cut off once the whole
+//! request has taken longer than its deadline.
+
+use std::cell::Cell;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+use tiny_http::Response;
+
+thread_local! {
+    /// When the request being handled by this thread has to be done by.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Note that the request this thread is handling, received at `started`, has
+/// `limit` to finish.
+pub fn begin(started: Instant, limit: Option<Duration>) {
+    DEADLINE.set(limit.map(|x| started + x));
+}
+
+/// A reader that fails once the current request's deadline has passed.
+pub struct Deadline<R> {
+    inner: R,
+    deadline: Option<Instant>,
+}
+
+impl<R: Read> Read for Deadline<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.deadline.is_some_and(|x| Instant::now() > x) {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the request took too long",
+            ));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Read `inner`, e.g. a request's body, until the current request's deadline.
+pub fn reader<R: Read>(inner: R) -> Deadline<R> {
+    Deadline {
+        inner,
+        deadline: DEADLINE.get(),
+    }
+}
+
+/// Send `response` until the current request's deadline, which ends with the
+/// response.
+pub fn limit<R: Read>(response: Response<R>) -> Response<Deadline<R>> {
+    let status = response.status_code();
+    let headers = response.headers().to_vec();
+    let length = response.data_length();
+    let threshold = response.chunked_threshold();
+    let inner = response.into_reader();
+    let deadline = DEADLINE.take();
+    Response::new(status, headers, Deadline { inner, deadline }, length, None)
+        .with_chunked_threshold(threshold)
+}
@@ -0,0 +1,597 @@
+//! [Webmentions](https://www.w3.org/TR/webmention/), which sites send each
+//! other when one links to the other.
+//!
+//! They're off unless `enabled = true` is set in the `[webmention]` table of
+//! `site.toml`. A mention sent to `/.webmention` is answered with `202
+//! Accepted`, then its source is fetched in the background. If it links to
+//! the document, the mention is kept in the configured file and listed under
+//! the document. Sending one again updates it, and removes it if the source
+//! is gone or no longer links there.
+//!
+//! When a reload adds or changes documents, the pages they link to on other
+//! sites are sent mentions too, unless `send = false`. Which ones were sent
+//! is logged, so that they're only sent once per link.
+
+use crate::{LinkTarget, State, background, html_pages, http};
+use chrono::{DateTime, Local};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tiny_http::{Method, Request, Response, StatusCode};
+use url::Url;
+
+pub const PATH: &str = "/.webmention";
+
+/// How much of a source is read looking for the link.
+const MAX_SOURCE: u64 = 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `[webmention]` table of `site.toml`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebmentionConfig {
+    pub enabled: bool,
+    /// Where mentions are kept, relative to the content directory.
+    pub file: PathBuf,
+    /// Whether mentions are sent to the pages documents link to, which needs
+    /// `base_url`.
+    pub send: bool,
+    /// Where the mentions that were sent are logged, relative to the content
+    /// directory.
+    pub sent_file: PathBuf,
+}
+
+impl Default for WebmentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            file: PathBuf::from(".webmentions.json"),
+            send: true,
+            sent_file: PathBuf::from(".webmentions-sent.json"),
+        }
+    }
+}
+
+/// A page that links to a document.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Mention {
+    pub source: String,
+    /// The source's `<title>`, if it has one.
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub received: DateTime<Local>,
+}
+
+/// The accepted mentions of the site's documents.
+#[derive(Debug, Default)]
+pub struct Mentions {
+    /// Where they're saved, `None` if mentions are off.
+    path: Option<PathBuf>,
+    /// By the URL of the document they mention, oldest first.
+    by_url: Mutex<BTreeMap<String, Vec<Mention>>>,
+    /// When one was last received or removed since they were loaded.
+    changed: Mutex<Option<SystemTime>>,
+}
+
+impl Mentions {
+    pub fn load(
+        content_path: &Path,
+        config: &WebmentionConfig,
+    ) -> eyre::Result<Self> {
+        if !config.enabled {
+            return Ok(Self::default());
+        }
+        let path = content_path.join(&config.file);
+        let by_url = if path.is_file() {
+            let mentions = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&mentions).map_err(|e| {
+                eyre::eyre!("Invalid \"{}\": {e}", path.display())
+            })?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            path: Some(path),
+            by_url: Mutex::new(by_url),
+            changed: Mutex::default(),
+        })
+    }
+
+    /// The mentions of the document at `url`, oldest first.
+    pub fn of(&self, url: &str) -> Vec<Mention> {
+        let by_url = self.by_url.lock().unwrap();
+        by_url.get(url).cloned().unwrap_or_default()
+    }
+
+    /// When a mention was last received or removed, `None` if none have
+    /// been since they were loaded.
+    pub fn changed(&self) -> Option<SystemTime> {
+        *self.changed.lock().unwrap()
+    }
+
+    /// Replace the mention of the document at `url` from `source`, or remove
+    /// it if `mention` is `None`, and save them.
+    fn update(&self, url: &str, source: &str, mention: Option<Mention>) {
+        let mut by_url = self.by_url.lock().unwrap();
+        let mentions = by_url.entry(url.to_string()).or_default();
+        mentions.retain(|x| x.source != source);
+        mentions.extend(mention);
+        if mentions.is_empty() {
+            by_url.remove(url);
+        }
+        *self.changed.lock().unwrap() = Some(SystemTime::now());
+
+        let Some(path) = &self.path else {
+            return;
+        };
+        let json = serde_json::to_string_pretty(&*by_url)
+            .expect("mentions are serializable");
+        // Written to a temporary file first, like the view counts.
+        let temp = path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&temp, json)
+            .and_then(|()| std::fs::rename(&temp, path))
+        {
+            error!("Failed to save mentions to \"{}\": {e}", path.display());
+        }
+    }
+}
+
+/// Handle a request to [`PATH`]. `origin` is the scheme and host of the
+/// request, which mentioned documents must be on unless the site has a
+/// configured base URL.
+pub fn receive(mut rq: Request, state: &Arc<RwLock<State>>, origin: &str) {
+    {
+        let state_l = state.read().unwrap();
+        if !state_l.config.webmention.enabled {
+            crate::respond(rq, crate::error_response(&state_l, 404));
+            return;
+        }
+    }
+    if *rq.method() != Method::Post {
+        crate::respond(
+            rq,
+            Response::new_empty(StatusCode(405))
+                .with_header(http::make_header("Allow", "POST")),
+        );
+        return;
+    }
+
+    let mut body = vec![];
+    if let Err(e) = crate::timeout::reader(rq.as_reader())
+        .take(64 * 1024)
+        .read_to_end(&mut body)
+    {
+        error!("Failed to read a webmention: {e}");
+        crate::respond(rq, Response::new_empty(StatusCode(400)));
+        return;
+    }
+    let form = url::form_urlencoded::parse(&body).collect::<BTreeMap<_, _>>();
+    let (Some(source), Some(target)) = (form.get("source"), form.get("target"))
+    else {
+        crate::respond(rq, bad_request("Both source and target are needed."));
+        return;
+    };
+    let (Ok(source), Ok(target)) = (Url::parse(source), Url::parse(target))
+    else {
+        crate::respond(rq, bad_request("Source and target must be URLs."));
+        return;
+    };
+    if !matches!(source.scheme(), "http" | "https") || source == target {
+        crate::respond(rq, bad_request("The source isn't a web page."));
+        return;
+    }
+
+    let document = {
+        let state_l = state.read().unwrap();
+        let base = Url::parse(state_l.config.base(origin)).ok();
+        let path = percent_encoding::percent_decode_str(target.path())
+            .decode_utf8_lossy()
+            .into_owned();
+        let path = path.strip_suffix('/').unwrap_or(&path);
+        let ours = base.is_some_and(|x| x.host_str() == target.host_str());
+        state_l
+            .index
+            .iter()
+            .find(|x| ours && x.url == path)
+            .map(|x| x.url.clone())
+    };
+    let Some(document) = document else {
+        crate::respond(rq, bad_request("The target isn't a document here."));
+        return;
+    };
+
+    // Fetching the source can take a while, so it's left to the background.
+    let state = state.clone();
+    if !background::submit(move || verify(&state, &document, &source, &target))
+    {
+        warn!("Too many webmentions are waiting, rejecting one");
+        crate::respond(
+            rq,
+            Response::new_empty(StatusCode(503))
+                .with_header(http::make_header("Retry-After", "60")),
+        );
+        return;
+    }
+    crate::respond(
+        rq,
+        Response::from_string("The mention will be verified.")
+            .with_status_code(202)
+            .with_header(http::make_header(
+                "Content-Type",
+                "text/plain; charset=utf-8",
+            )),
+    );
+}
+
+/// Fetch `source` and accept its mention of `document` if it links to
+/// `target`, or remove its earlier one if it doesn't.
+fn verify(state: &RwLock<State>, document: &str, source: &Url, target: &Url) {
+    let mention = match fetch(source) {
+        Ok(Some(html)) if links_to(&html, source, target) => Some(Mention {
+            source: source.to_string(),
+            title: title(&html),
+            author: html_pages::tags(&html, "meta")
+                .filter(|x| {
+                    html_pages::attribute(x, "name")
+                        .is_some_and(|x| x.eq_ignore_ascii_case("author"))
+                })
+                .find_map(|x| html_pages::attribute(x, "content")),
+            received: Local::now(),
+        }),
+        // Gone, or no longer linking.
+        Ok(_) => None,
+        Err(e) => {
+            info!("Failed to fetch webmention source \"{source}\": {e}");
+            return;
+        }
+    };
+
+    // The current state, which a reload may have replaced in the meantime.
+    let state_l = state.read().unwrap();
+    let accepted = mention.is_some();
+    state_l.mentions.update(document, source.as_str(), mention);
+    // Pages rendered before don't list the mention.
+    state_l.cache.clear();
+    if accepted {
+        info!("Accepted a webmention of \"{document}\" from \"{source}\"");
+    } else {
+        info!("Removed the webmention of \"{document}\" from \"{source}\"");
+    }
+}
+
+fn bad_request(message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(message)
+        .with_status_code(400)
+        .with_header(http::make_header(
+            "Content-Type",
+            "text/plain; charset=utf-8",
+        ))
+}
+
+/// The mentions to send for the documents a reload added or changed.
+pub struct Outgoing {
+    /// Where the sent mentions are logged.
+    log: PathBuf,
+    /// The URL of each document and the pages on other sites it links to.
+    documents: Vec<(String, Vec<Url>)>,
+}
+
+impl Outgoing {
+    /// The mentions to send for the documents in `current` that are new or
+    /// changed since `previous`, `None` if there are none.
+    pub fn new(previous: &State, current: &State) -> Option<Self> {
+        let config = &current.config.webmention;
+        if !config.enabled || !config.send {
+            return None;
+        }
+        let Some(base) = current.config.base_url.as_deref() else {
+            warn!("Webmentions can't be sent without a base_url");
+            return None;
+        };
+        let base = base.trim_end_matches('/');
+        let host = Url::parse(base).ok()?.host_str()?.to_string();
+
+        let documents = current
+            .index
+            .iter()
+            // Other sites can't see the ones behind a password.
+            .filter(|x| current.is_listed_at(x, PATH))
+            .filter_map(|entry| {
+                let path = current.content_path.join(&entry.path);
+                let doc = current.parsed.get(&path)?;
+                let unchanged = previous
+                    .parsed
+                    .get(&path)
+                    .is_some_and(|x| x.modified == doc.modified);
+                if unchanged {
+                    return None;
+                }
+                let targets = doc
+                    .links
+                    .iter()
+                    .filter_map(|x| match x {
+                        LinkTarget::Path(x) => Url::parse(x).ok(),
+                        LinkTarget::Wiki(_) => None,
+                    })
+                    .filter(|x| matches!(x.scheme(), "http" | "https"))
+                    .filter(|x| x.host_str() != Some(host.as_str()))
+                    .collect();
+                let source = format!("{base}{}", http::encode_path(&entry.url));
+                Some((source, targets))
+            })
+            .collect::<Vec<_>>();
+        (!documents.is_empty()).then(|| Self {
+            log: current.content_path.join(&config.sent_file),
+            documents,
+        })
+    }
+
+    /// Send the mentions that haven't been already, which can take a while.
+    /// Pages a document no longer links to are sent one too, so that they
+    /// can remove theirs.
+    pub fn send(self) {
+        let mut log = match std::fs::read_to_string(&self.log) {
+            Ok(x) => serde_json::from_str(&x).unwrap_or_else(|e| {
+                error!("Invalid \"{}\": {e}", self.log.display());
+                SentLog::new()
+            }),
+            Err(_) => SentLog::new(),
+        };
+        let agent = agent();
+        for (source, targets) in &self.documents {
+            let previous = log.remove(source).unwrap_or_default();
+            let mut sent = BTreeSet::new();
+            for target in targets {
+                let target_str = target.as_str().to_string();
+                if previous.contains(&target_str)
+                    || sent.contains(&target_str)
+                    || notify(&agent, source, target)
+                {
+                    sent.insert(target_str);
+                }
+            }
+            for target in previous.difference(&sent) {
+                if let Ok(target) = Url::parse(target) {
+                    notify(&agent, source, &target);
+                }
+            }
+            if !sent.is_empty() {
+                log.insert(source.clone(), sent);
+            }
+        }
+
+        let json = serde_json::to_string_pretty(&log)
+            .expect("the log is serializable");
+        let temp = self.log.with_extension("tmp");
+        if let Err(e) = std::fs::write(&temp, json)
+            .and_then(|()| std::fs::rename(&temp, &self.log))
+        {
+            error!(
+                "Failed to save sent webmentions to \"{}\": {e}",
+                self.log.display()
+            );
+        }
+    }
+}
+
+/// The pages each document's URL was sent mentions for, or that had nowhere
+/// to send them.
+type SentLog = BTreeMap<String, BTreeSet<String>>;
+
+/// Send a mention of `target` from `source`, returning whether it's done
+/// with: it was sent, or the target doesn't accept them.
+fn notify(agent: &ureq::Agent, source: &str, target: &Url) -> bool {
+    let endpoint = match endpoint(agent, target) {
+        Ok(Some(x)) => x,
+        Ok(None) => {
+            debug!("\"{target}\" doesn't accept webmentions");
+            return true;
+        }
+        Err(e) => {
+            info!(
+                "Failed to find the webmention endpoint of \"{target}\": {e}"
+            );
+            return false;
+        }
+    };
+    let result = agent
+        .post(endpoint.as_str())
+        .send_form(&[("source", source), ("target", target.as_str())]);
+    match result {
+        Ok(_) => {
+            info!("Sent a webmention of \"{target}\" from \"{source}\"");
+            true
+        }
+        Err(e) => {
+            info!("Failed to send a webmention to \"{endpoint}\": {e}");
+            false
+        }
+    }
+}
+
+/// Where `target` accepts webmentions: the first `webmention` link in its
+/// `Link` headers, or else in its HTML.
+fn endpoint(
+    agent: &ureq::Agent,
+    target: &Url,
+) -> Result<Option<Url>, Box<ureq::Error>> {
+    let response = agent.get(target.as_str()).call()?;
+    // Relative to where it was redirected to.
+    let base =
+        Url::parse(response.get_url()).unwrap_or_else(|_| target.clone());
+    let is_webmention = |rel: &str| {
+        rel.split_whitespace()
+            .any(|x| x.eq_ignore_ascii_case("webmention"))
+    };
+
+    // `<https://example.com/webmention>; rel="webmention"`
+    let from_header = response
+        .all("Link")
+        .into_iter()
+        .flat_map(|x| x.split(','))
+        .find_map(|link| {
+            let (href, params) = link.split_once(';')?;
+            let rel = params
+                .split(';')
+                .find_map(|x| x.trim().strip_prefix("rel="))?;
+            is_webmention(rel.trim_matches('"')).then(|| {
+                href.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+        });
+    let href = match from_header {
+        Some(x) => Some(x),
+        None if response.content_type() == "text/html" => {
+            let mut html = String::new();
+            let _ = response
+                .into_reader()
+                .take(MAX_SOURCE)
+                .read_to_string(&mut html);
+            html_pages::tags(&html, "link")
+                .chain(html_pages::tags(&html, "a"))
+                .filter(|x| {
+                    html_pages::attribute(x, "rel")
+                        .is_some_and(|x| is_webmention(&x))
+                })
+                .find_map(|x| html_pages::attribute(x, "href"))
+        }
+        None => None,
+    };
+    // An empty `href` is the page itself.
+    Ok(href.and_then(|x| base.join(&x).ok()))
+}
+
+/// The HTML of `source`, or `None` if it's gone.
+fn fetch(source: &Url) -> Result<Option<String>, Box<ureq::Error>> {
+    match agent().get(source.as_str()).call() {
+        Ok(response) => {
+            let mut html = String::new();
+            let _ = response
+                .into_reader()
+                .take(MAX_SOURCE)
+                .read_to_string(&mut html);
+            Ok(Some(html))
+        }
+        Err(ureq::Error::Status(404 | 410, _)) => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// An agent that only makes requests to other sites.
+pub fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .redirects(5)
+        .resolver(PublicResolver)
+        .build()
+}
+
+/// Resolves only to addresses on the internet, so that mentions can't be
+/// used to make requests to the server's own network.
+struct PublicResolver;
+
+impl ureq::Resolver for PublicResolver {
+    fn resolve(&self, netloc: &str) -> std::io::Result<Vec<SocketAddr>> {
+        let addresses = netloc
+            .to_socket_addrs()?
+            .filter(|x| is_public(x.ip()))
+            .collect::<Vec<_>>();
+        if addresses.is_empty() {
+            return Err(std::io::Error::other("not a public address"));
+        }
+        Ok(addresses)
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // "This network", 0.0.0.0/8.
+                || ip.octets()[0] == 0
+                // Shared address space (carrier-grade NAT), 100.64.0.0/10.
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => {
+            let unique_local = ip.segments()[0] & 0xfe00 == 0xfc00;
+            let link_local = ip.segments()[0] & 0xffc0 == 0xfe80;
+            match ip.to_ipv4_mapped() {
+                Some(ip) => is_public(IpAddr::V4(ip)),
+                None => {
+                    !(ip.is_loopback()
+                        || ip.is_unspecified()
+                        || unique_local
+                        || link_local)
+                }
+            }
+        }
+    }
+}
+
+/// Whether a link in `html` (from `source`) points to `target`.
+fn links_to(html: &str, source: &Url, target: &Url) -> bool {
+    let without_slash = |x: &Url| {
+        let mut x = x.clone();
+        x.set_fragment(None);
+        x.as_str().trim_end_matches('/').to_string()
+    };
+    let target = without_slash(target);
+    html_pages::tags(html, "a")
+        .filter_map(|x| html_pages::attribute(x, "href"))
+        .filter_map(|x| source.join(&x).ok())
+        .any(|x| without_slash(&x) == target)
+}
+
+fn title(html: &str) -> Option<String> {
+    let start = html.to_ascii_lowercase().find("<title")?;
+    let rest = &html[start..];
+    let text = &rest[rest.find('>')? + 1..];
+    let end = text.to_ascii_lowercase().find("</title")?;
+    let title = html_pages::text(&text[..end]);
+    (!title.is_empty()).then_some(title)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_public;
+    use std::net::IpAddr;
+
+    fn public(ip: &str) -> bool {
+        is_public(ip.parse::<IpAddr>().unwrap())
+    }
+
+    #[test]
+    fn public_addresses() {
+        assert!(public("93.184.216.34"));
+        assert!(public("100.63.255.255"));
+        assert!(public("100.128.0.0"));
+        assert!(public("2606:2800:220:1::1"));
+    }
+
+    #[test]
+    fn local_addresses() {
+        assert!(!public("127.0.0.1"));
+        assert!(!public("10.1.2.3"));
+        assert!(!public("192.168.0.1"));
+        assert!(!public("169.254.169.254"));
+        assert!(!public("0.0.0.0"));
+        assert!(!public("0.1.2.3"));
+        assert!(!public("100.64.0.1"));
+        assert!(!public("100.127.255.255"));
+        assert!(!public("::1"));
+        assert!(!public("fd00::1"));
+        assert!(!public("::ffff:100.64.0.1"));
+    }
+}
@@ -0,0 +1,18 @@
+// Copy buttons for code blocks, which are hidden until this runs.
+for (const button of document.querySelectorAll("figure.code button.copy")) {
+    button.hidden = false;
+    button.addEventListener("click", async () => {
+        const code = button.closest("figure").querySelector("pre code");
+        const copy = code.cloneNode(true);
+        for (const number of copy.querySelectorAll(".line-number")) {
+            number.remove();
+        }
+        try {
+            await navigator.clipboard.writeText(copy.textContent);
+            button.textContent = "Copied";
+        } catch {
+            button.textContent = "Failed";
+        }
+        setTimeout(() => (button.textContent = "Copy"), 2000);
+    });
+}
@@ -0,0 +1,14 @@
+// Video embeds are links to the video until they're clicked, so nothing is
+// loaded from the video's site before the reader asks for it.
+for (const embed of document.querySelectorAll("div.embed[data-embed]")) {
+    const placeholder = embed.querySelector("a.embed-placeholder");
+    placeholder.addEventListener("click", (event) => {
+        event.preventDefault();
+        const player = document.createElement("iframe");
+        player.src = embed.dataset.embed;
+        player.title = embed.dataset.title;
+        player.allow = "autoplay; encrypted-media; fullscreen; picture-in-picture";
+        player.allowFullscreen = true;
+        placeholder.replaceWith(player);
+    });
+}
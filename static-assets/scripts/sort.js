@@ -0,0 +1,38 @@
+// Tables are sorted by a column when its header is clicked, and the other way
+// when it's clicked again. Numbers are sorted as numbers.
+const compare = (a, b) => {
+    const x = Number(a);
+    const y = Number(b);
+    if (a.trim() !== "" && b.trim() !== "" && !isNaN(x) && !isNaN(y)) {
+        return x - y;
+    }
+    return a.localeCompare(b, undefined, { numeric: true });
+};
+
+for (const table of document.querySelectorAll("table.sortable")) {
+    const headers = table.querySelectorAll("thead th");
+    const body = table.tBodies[0];
+    headers.forEach((header, column) => {
+        header.tabIndex = 0;
+        const sort = () => {
+            const ascending = header.getAttribute("aria-sort") !== "ascending";
+            for (const other of headers) {
+                other.removeAttribute("aria-sort");
+            }
+            header.setAttribute("aria-sort", ascending ? "ascending" : "descending");
+            const text = (row) => row.cells[column]?.textContent ?? "";
+            const rows = [...body.rows].sort((a, b) => compare(text(a), text(b)));
+            if (!ascending) {
+                rows.reverse();
+            }
+            body.append(...rows);
+        };
+        header.addEventListener("click", sort);
+        header.addEventListener("keydown", (event) => {
+            if (event.key === "Enter" || event.key === " ") {
+                event.preventDefault();
+                sort();
+            }
+        });
+    });
+}
@@ -0,0 +1,387 @@
+//! Requests to a running server, made over a Unix socket.
+
+use site::Server;
+use site::listen::BindAddr;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// A server running on its own thread until it's dropped.
+struct Running {
+    handle: site::Handle,
+    thread: Option<JoinHandle<eyre::Result<()>>>,
+    socket: PathBuf,
+    _dir: tempfile::TempDir,
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, x)| x.as_str())
+    }
+}
+
+/// Serve `content`, with the server's options set by `configure`.
+fn serve(content: &Path, configure: impl FnOnce(Server) -> Server) -> Running {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("site.sock");
+    let server = Server::new(content)
+        .bind(BindAddr::Unix(socket.clone()))
+        .access_log(dir.path().join("access.log"));
+    let server = configure(server);
+    let handle = server.handle();
+    let thread = std::thread::spawn(move || server.run());
+
+    let started = Instant::now();
+    while UnixStream::connect(&socket).is_err() {
+        assert!(!thread.is_finished(), "the server stopped");
+        assert!(started.elapsed() < Duration::from_secs(10), "not listening");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    Running {
+        handle,
+        thread: Some(thread),
+        socket,
+        _dir: dir,
+    }
+}
+
+impl Running {
+    fn get(&self, path: &str) -> Response {
+        self.request("GET", path, &[])
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> Response {
+        self.send(method, path, headers, b"")
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Response {
+        let mut stream = UnixStream::connect(&self.socket).unwrap();
+        // HTTP/1.0, so that the body isn't chunked and the connection is
+        // closed after it.
+        let mut rq = format!("{method} {path} HTTP/1.0\r\nHost: localhost\r\n");
+        for (name, value) in headers {
+            rq.push_str(&format!("{name}: {value}\r\n"));
+        }
+        rq.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+        stream.write_all(rq.as_bytes()).unwrap();
+        // The server can answer before it's read all of a large body.
+        let _ = stream.write_all(body);
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response).unwrap();
+        let response = String::from_utf8_lossy(&response);
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let mut lines = head.lines();
+        let status = lines.next().unwrap().split(' ').nth(1).unwrap();
+        let headers = lines
+            .filter_map(|x| x.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+            .collect();
+        Response {
+            status: status.parse().unwrap(),
+            headers,
+            body: body.to_string(),
+        }
+    }
+
+    /// Wait for `path` to be answered with `status`, e.g. after a reload.
+    fn wait_for(&self, path: &str, status: u16) {
+        let started = Instant::now();
+        while self.get(path).status != status {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "{path} wasn't answered with {status}"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.handle.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let stopped = thread.join().unwrap();
+            if !std::thread::panicking() {
+                stopped.unwrap();
+            }
+        }
+    }
+}
+
+/// A content directory with a document in it.
+fn content() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("blog")).unwrap();
+    std::fs::write(
+        dir.path().join("blog/post.md"),
+        "```meta\ntitle = \"A post\"\ndate = \"2024-01-15\"\n```\n\nHello.\n",
+    )
+    .unwrap();
+    dir
+}
+
+#[test]
+fn control_reload() {
+    let content = content();
+    let server =
+        serve(content.path(), |x| x.control_token("secret".to_string()));
+    server.wait_for("/blog/new", 404);
+
+    std::fs::write(
+        content.path().join("blog/new.md"),
+        "```meta\ntitle = \"New\"\ndate = \"2024-02-01\"\n```\n\nNew.\n",
+    )
+    .unwrap();
+    let response = server.request(
+        "POST",
+        "/.control/reload",
+        &[("Authorization", "Bearer secret")],
+    );
+    assert_eq!(response.status, 202);
+    server.wait_for("/blog/new", 200);
+    assert!(server.get("/blog/new").body.contains("New."));
+}
+
+#[test]
+fn control_unauthorized() {
+    let content = content();
+    let server =
+        serve(content.path(), |x| x.control_token("secret".to_string()));
+    let missing = server.request("POST", "/.control/reload", &[]);
+    assert_eq!(missing.status, 401);
+    assert_eq!(missing.header("WWW-Authenticate"), Some("Bearer"));
+    let wrong = server.request(
+        "POST",
+        "/.control/shutdown",
+        &[("Authorization", "Bearer wrong")],
+    );
+    assert_eq!(wrong.status, 401);
+    // It's still serving.
+    assert_eq!(server.get("/blog/post").status, 200);
+    // `.` segments are resolved along with the URL.
+    assert_eq!(server.get("/blog/./post").status, 200);
+}
+
+#[test]
+fn control_disabled() {
+    let content = content();
+    let server = serve(content.path(), |x| x);
+    let response = server.request(
+        "POST",
+        "/.control/reload",
+        &[("Authorization", "Bearer secret")],
+    );
+    assert_eq!(response.status, 404);
+}
+
+#[test]
+fn duplicate_slashes() {
+    let content = content();
+    let server = serve(content.path(), |x| x);
+    for (path, location) in [
+        ("/blog//post", "/blog/post"),
+        ("//blog/post", "/blog/post"),
+        ("/blog/post/", "/blog/post"),
+        ("/blog//post?preview=1&x=%20", "/blog/post?preview=1&x=%20"),
+    ] {
+        let response = server.get(path);
+        assert_eq!(response.status, 308, "{path}");
+        assert_eq!(response.header("Location"), Some(location), "{path}");
+    }
+    assert_eq!(server.get("/blog/post").status, 200);
+    // `.` segments are resolved along with the URL.
+    assert_eq!(server.get("/blog/./post").status, 200);
+}
+
+/// Run git in `dir`, committing as a fixed author on 2023-05-06.
+fn git(dir: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .current_dir(dir)
+        .args(["-c", "user.name=Site", "-c", "user.email=site@example.com"])
+        .args(args)
+        .env("GIT_AUTHOR_DATE", "2023-05-06T12:00:00Z")
+        .env("GIT_COMMITTER_DATE", "2023-05-06T12:00:00Z")
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {args:?}");
+}
+
+#[test]
+fn git_metadata() {
+    let content = content();
+    git(content.path(), &["init", "-q"]);
+    git(content.path(), &["add", "blog/post.md"]);
+    git(content.path(), &["commit", "-q", "-m", "Add a post"]);
+
+    let server = serve(content.path(), |x| {
+        x.load_options(site::LoadOptions {
+            git_metadata: true,
+            repo_url: Some("https://example.com/repo/commits/main".into()),
+            ..site::LoadOptions::default()
+        })
+    });
+    let page = server.get("/blog/post").body;
+    assert!(page.contains("<time datetime=\"2023-05-06\">"), "{page}");
+    assert!(
+        page.contains("https://example.com/repo/commits/main/blog/post.md"),
+        "{page}"
+    );
+}
+
+#[test]
+fn git_metadata_outside_a_repo() {
+    let content = content();
+    let server = serve(content.path(), |x| {
+        x.load_options(site::LoadOptions {
+            git_metadata: true,
+            ..site::LoadOptions::default()
+        })
+    });
+    let response = server.get("/blog/post");
+    assert_eq!(response.status, 200);
+    assert!(!response.body.contains("class=\"git-meta\""));
+}
+
+#[test]
+fn git_metadata_after_a_commit() {
+    let content = content();
+    std::fs::write(content.path().join("README"), "Notes.\n").unwrap();
+    git(content.path(), &["init", "-q"]);
+    git(content.path(), &["add", "README"]);
+    git(content.path(), &["commit", "-q", "-m", "Start"]);
+
+    let server = serve(content.path(), |x| {
+        x.control_token("secret".to_string())
+            .load_options(site::LoadOptions {
+                git_metadata: true,
+                ..site::LoadOptions::default()
+            })
+    });
+    assert!(!server.get("/blog/post").body.contains("class=\"git-meta\""));
+
+    // Committing doesn't change the file, but it does change its history.
+    git(content.path(), &["add", "blog/post.md"]);
+    git(content.path(), &["commit", "-q", "-m", "Add a post"]);
+    let reload = server.request(
+        "POST",
+        "/.control/reload",
+        &[("Authorization", "Bearer secret")],
+    );
+    assert_eq!(reload.status, 202);
+    let started = Instant::now();
+    while !server.get("/blog/post").body.contains("class=\"git-meta\"") {
+        assert!(started.elapsed() < Duration::from_secs(10), "not reloaded");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn base_path() {
+    let content = content();
+    std::fs::create_dir(content.path().join("assets")).unwrap();
+    std::fs::write(
+        content.path().join("assets/style.css"),
+        "body { background: url(/bg.png); }\n",
+    )
+    .unwrap();
+    let server = serve(content.path(), |x| x.base_path("/site".to_string()));
+
+    // Ranges would be of the file, not of what's sent.
+    let css = server.request(
+        "GET",
+        "/site/assets/style.css",
+        &[("Range", "bytes=0-3")],
+    );
+    assert_eq!(css.status, 200);
+    assert_eq!(css.body, "body { background: url(/site/bg.png); }\n");
+    assert_eq!(css.header("Accept-Ranges"), None);
+    let etag = css.header("ETag").unwrap();
+    let cached = server.request(
+        "GET",
+        "/site/assets/style.css",
+        &[("If-None-Match", etag)],
+    );
+    assert_eq!(cached.status, 304);
+
+    let page =
+        server.request("GET", "/site/blog/post", &[("Range", "bytes=0-3")]);
+    assert_eq!(page.status, 200);
+    assert!(page.body.contains("href=\"/site/"), "{}", page.body);
+
+    // Another server in the same process has its own.
+    let other = serve(content.path(), |x| x);
+    let css = other.get("/assets/style.css");
+    assert_eq!(css.body, "body { background: url(/bg.png); }\n");
+    assert_eq!(css.header("Accept-Ranges"), Some("bytes"));
+    assert_eq!(other.get("/site/blog/post").status, 404);
+}
+
+#[test]
+fn source_link() {
+    let content = content();
+    std::fs::write(
+        content.path().join("blog/notes.org"),
+        "#+title: Notes\n#+date: 2024-01-20\n\nSome notes.\n",
+    )
+    .unwrap();
+    let server = serve(content.path(), |x| x);
+    let markdown = server.get("/blog/post").body;
+    assert!(markdown.contains("href=\"?raw=1\""), "{markdown}");
+    let org = server.get("/blog/notes").body;
+    assert!(org.contains("Some notes."), "{org}");
+    assert!(!org.contains("href=\"?raw=1\""), "{org}");
+}
+
+#[test]
+fn last_modified() {
+    let content = content();
+    let server = serve(content.path(), |x| x);
+    // After the post's date, but before its file was written.
+    let since = "Tue, 16 Jan 2024 00:00:00 GMT";
+    let page =
+        server.request("GET", "/blog/post", &[("If-Modified-Since", since)]);
+    assert_eq!(page.status, 200);
+    let last_modified = page.header("Last-Modified").unwrap();
+    assert_ne!(last_modified, "Mon, 15 Jan 2024 00:00:00 GMT");
+    let cached = server.request(
+        "GET",
+        "/blog/post",
+        &[("If-Modified-Since", last_modified)],
+    );
+    assert_eq!(cached.status, 304);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_body_too_large() {
+    let content = content();
+    let server =
+        serve(content.path(), |x| x.backend(site::backend::Backend::Async));
+    let body = vec![b'x'; 5 * 1024 * 1024];
+    let response = server.send("POST", "/.webmention", &[], &body);
+    assert_eq!(response.status, 413);
+}